@@ -0,0 +1,6 @@
+alter table rooms add column description text not null default '';
+-- unix seconds
+alter table rooms add column created_at integer not null default 0;
+
+-- unix seconds
+alter table messages add column created_at integer not null default 0;
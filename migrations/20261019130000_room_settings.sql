@@ -0,0 +1,3 @@
+-- uid, null for rooms made before owners existed
+alter table rooms add column owner_id text;
+alter table rooms add column is_archived integer not null default 0;
@@ -0,0 +1,53 @@
+-- sqlite can't add foreign keys to existing tables, so rebuild the children of rooms
+
+-- unix seconds, set while a deletion is pending
+alter table rooms add column delete_at integer;
+
+create table profiles_new (
+    -- pid
+    uuid text not null,
+
+    -- uid
+    user_id text not null,
+    -- rid
+    room_id text not null references rooms(uuid) on delete cascade,
+
+    handle text not null,
+    alias text not null,
+
+    unique(uuid),
+    unique(user_id, room_id),
+    unique(handle, room_id)
+) strict;
+
+insert into profiles_new (uuid, user_id, room_id, handle, alias)
+    select uuid, user_id, room_id, handle, alias from profiles
+    where room_id in (select uuid from rooms);
+drop table profiles;
+alter table profiles_new rename to profiles;
+
+create table messages_new (
+    -- mid
+    id text not null,
+    -- rid
+    room_id text not null references rooms(uuid) on delete cascade,
+
+    -- pid, nil uuid for system messages
+    profile_id text not null,
+    -- rtid
+    reply_to_id text,
+    
+    content text not null,
+    -- unix seconds
+    created_at integer not null default 0,
+
+    unique(id, room_id)
+) strict;
+
+insert into messages_new (id, room_id, profile_id, reply_to_id, content, created_at)
+    select id, room_id, profile_id, reply_to_id, content, created_at from messages
+    where room_id in (select uuid from rooms);
+drop table messages;
+alter table messages_new rename to messages;
+
+create index messages_room on messages(room_id);
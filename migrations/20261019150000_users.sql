@@ -0,0 +1,23 @@
+create table users (
+    -- uid
+    id text not null primary key,
+
+    -- unix seconds
+    created_at integer not null default 0
+) strict;
+
+-- who a user is at an outside provider
+create table identities (
+    -- uid
+    user_id text not null references users(id) on delete cascade,
+
+    -- ClientProvider slug: google, github, or an oidc id
+    provider text not null,
+    -- the provider's stable id for the user
+    subject text not null,
+
+    unique(provider, subject)
+) strict;
+
+-- everyone with a profile already is a user, keyed by whatever firebase gave them
+insert into users (id) select distinct user_id from profiles;
@@ -0,0 +1,11 @@
+create table sessions (
+    id text primary key not null,
+    data text not null,
+    expiry_date integer not null,
+    user_id text,
+    provider text,
+    user_agent text,
+    created_at integer not null,
+    last_active integer not null
+) strict;
+create index sessions_user on sessions(user_id);
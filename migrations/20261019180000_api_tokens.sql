@@ -0,0 +1,18 @@
+-- set for the user an API token acts as, its profiles are the token's rooms
+alter table users add column bot_owner_id text references users(id) on delete cascade;
+
+create table api_tokens (
+    -- uid of the bot user, doubles as the token's id
+    id text not null primary key references users(id) on delete cascade,
+    -- uid of whoever made it
+    owner_id text not null references users(id) on delete cascade,
+
+    name text not null,
+    -- sha256 hex, the token itself is only ever shown once
+    token_hash text not null unique,
+    can_write integer not null default 0,
+
+    -- unix seconds
+    created_at integer not null,
+    last_used integer
+) strict;
@@ -0,0 +1,4 @@
+-- unix seconds, set when a message is edited or deleted
+alter table messages add column edited_at integer;
+-- deleted messages keep their row (threads hang off it) but lose their content
+alter table messages add column deleted_at integer;
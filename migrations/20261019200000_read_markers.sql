@@ -0,0 +1,2 @@
+-- the newest message this profile has seen, everything after it is unread
+alter table profiles add column last_read_id text;
@@ -0,0 +1,21 @@
+-- one-to-one messages between two profiles of a room, kept apart from the room's own
+create table direct_messages (
+    id text primary key not null,
+    room_id text not null references rooms(uuid) on delete cascade,
+    from_id text not null references profiles(uuid) on delete cascade,
+    to_id text not null references profiles(uuid) on delete cascade,
+    content text not null,
+    -- unix seconds
+    created_at integer not null
+) strict;
+
+create index direct_messages_pair on direct_messages(from_id, to_id, created_at);
+create index direct_messages_to on direct_messages(to_id, created_at);
+
+-- either side of a block stops DMs both ways
+create table profile_blocks (
+    blocker_id text not null references profiles(uuid) on delete cascade,
+    blocked_id text not null references profiles(uuid) on delete cascade,
+    created_at integer not null default (unixepoch()),
+    primary key (blocker_id, blocked_id)
+) strict;
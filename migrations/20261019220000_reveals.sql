@@ -0,0 +1,25 @@
+-- what a user shows of themselves once they agree to a reveal
+alter table users add column display_name text;
+-- what the account goes by at its provider (login, username or email), what a
+-- reveal shows of it, set on sign in
+alter table identities add column handle text;
+
+-- profile from_id asks to_id that both learn who the other is, nothing shows
+-- until to_id accepts
+create table reveals (
+    id text primary key not null,
+    room_id text not null references rooms(uuid) on delete cascade,
+    from_id text not null references profiles(uuid) on delete cascade,
+    to_id text not null references profiles(uuid) on delete cascade,
+    -- 'name' for users.display_name, or a provider slug from identities
+    from_shows text not null,
+    -- set on accept
+    to_shows text,
+    -- unix seconds
+    created_at integer not null,
+    -- unanswered requests lapse at this point
+    expires_at integer not null,
+    accepted_at integer,
+
+    unique(from_id, to_id)
+) strict;
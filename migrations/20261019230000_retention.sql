@@ -0,0 +1,6 @@
+-- seconds a room keeps messages for, NULL keeps them forever
+alter table rooms add column retention_secs integer;
+-- unix seconds, set for messages sent with their own TTL
+alter table messages add column expires_at integer;
+
+create index messages_expires on messages(expires_at) where expires_at is not null;
@@ -0,0 +1,18 @@
+-- unix seconds, the profile can't post in its room until then
+alter table profiles add column muted_until integer;
+
+-- bans are per user, not per profile, so a fresh profile doesn't get around them
+-- and they outlive the profile that was banned
+create table room_bans (
+    room_id text not null references rooms(uuid) on delete cascade,
+    user_id text not null,
+    created_at integer not null default (unixepoch()),
+    primary key (room_id, user_id)
+) strict;
+
+create trigger banned_users_get_no_profiles
+before insert on profiles
+when exists (select 1 from room_bans where room_id=new.room_id and user_id=new.user_id)
+begin
+    select raise(abort, 'user is banned from this room');
+end;
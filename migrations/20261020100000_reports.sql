@@ -0,0 +1,21 @@
+-- instance admins see every room's reports, and who made them
+alter table users add column is_admin integer not null default 0;
+
+create table reports (
+    id text primary key not null,
+    room_id text not null references rooms(uuid) on delete cascade,
+    -- uid, only ever shown to admins
+    reporter_id text not null,
+    profile_id text not null references profiles(uuid) on delete cascade,
+    -- set when a message was reported rather than the profile
+    message_id text,
+    reason text not null,
+    note text not null,
+    -- unix seconds
+    created_at integer not null,
+    resolved_at integer,
+    -- 'dismissed', 'deleted', 'muted' or 'banned'
+    resolution text
+) strict;
+
+create index reports_open on reports(room_id, created_at) where resolved_at is null;
@@ -0,0 +1,40 @@
+-- content rules checked before a message goes in
+create table automod_rules (
+    id text primary key not null,
+    room_id text not null references rooms(uuid) on delete cascade,
+    -- 'word', 'regex', 'link_allow', 'link_deny', 'max_length' or 'max_mentions'
+    kind text not null,
+    -- the word, the pattern, space separated domains, or the limit
+    pattern text not null,
+    -- 'reject', 'hold' or 'mask'
+    action text not null,
+    created_at integer not null
+) strict;
+
+create index automod_rules_room on automod_rules(room_id, created_at);
+
+-- messages a rule held back until a moderator approves them
+create table held_messages (
+    id text primary key not null,
+    room_id text not null references rooms(uuid) on delete cascade,
+    profile_id text not null references profiles(uuid) on delete cascade,
+    reply_to_id text,
+    content text not null,
+    ttl_secs integer,
+    rule_id text references automod_rules(id) on delete set null,
+    created_at integer not null
+) strict;
+
+create index held_messages_room on held_messages(room_id, created_at);
+
+-- every time a rule caught something
+create table automod_log (
+    id text primary key not null,
+    room_id text not null references rooms(uuid) on delete cascade,
+    rule_id text references automod_rules(id) on delete set null,
+    profile_id text references profiles(uuid) on delete set null,
+    action text not null,
+    created_at integer not null
+) strict;
+
+create index automod_log_room on automod_log(room_id, created_at);
@@ -0,0 +1,2 @@
+-- seconds a profile has to wait between messages in the room, null when off
+alter table rooms add column slow_mode_secs integer;
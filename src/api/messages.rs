@@ -0,0 +1,252 @@
+use axum::{debug_handler, extract::{Path, Query, State}, http::StatusCode, response::{IntoResponse, Response}, Json};
+use serde::{Deserialize, Serialize};
+use sqlx::SqlitePool;
+use tokio::sync::broadcast;
+use utoipa::{IntoParams, ToSchema};
+use uuid::Uuid;
+
+use crate::{auth::{Caller, GuestConfig}, rooms::{can_moderate, sender_profile, HELD, msg::{self, SendMessageQuery, Sent}, Limits, RoomEvent}, AppResult, AppState};
+
+use super::{access, can_write, error, signed_in, ErrorBody};
+
+#[derive(Serialize, ToSchema)]
+pub struct Message {
+    pub id: Uuid,
+    pub room_id: Uuid,
+    /// nil for messages from the room itself
+    pub profile_id: Uuid,
+    pub reply_to_id: Option<Uuid>,
+    /// markdown, empty once deleted
+    pub content: String,
+    /// unix seconds
+    pub created_at: i64,
+    pub edited_at: Option<i64>,
+    pub deleted_at: Option<i64>,
+    /// when this message's own TTL runs out, the room's retention may take it sooner
+    pub expires_at: Option<i64>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct MessagePage {
+    /// newest first
+    pub messages: Vec<Message>,
+    /// pass as `before` for the next page, absent on the last one
+    pub next_before: Option<Uuid>,
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub(crate) struct MessagesQuery {
+    /// only messages older than this one
+    before: Option<Uuid>,
+    /// 1 to 100, default 50
+    limit: Option<i64>,
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct NewMessage {
+    pub content: String,
+    pub reply_to_id: Option<Uuid>,
+    /// seconds until the message disappears, up to 30 days
+    pub ttl_secs: Option<i64>,
+}
+
+/// A message an automod rule kept back, it's posted once a moderator approves it.
+#[derive(Serialize, ToSchema)]
+pub struct HeldMessage {
+    pub held_id: Uuid,
+    pub message: String,
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct MessageEdit {
+    pub content: String,
+}
+
+/// id, profile_id, reply_to_id, content, created_at, edited_at, deleted_at, expires_at
+type MessageRow = (String, String, Option<String>, String, i64, Option<i64>, Option<i64>, Option<i64>);
+
+const MESSAGE_COLUMNS: &str = "id,profile_id,reply_to_id,content,created_at,edited_at,deleted_at,expires_at";
+
+fn to_message(room_id: Uuid, (id, profile_id, reply_to_id, content, created_at, edited_at, deleted_at, expires_at): MessageRow) -> AppResult<Message> {
+    Ok(Message {
+        id: Uuid::parse_str(&id)?,
+        room_id,
+        profile_id: Uuid::parse_str(&profile_id)?,
+        reply_to_id: match reply_to_id {
+            Some(reply_to_id) => Some(Uuid::parse_str(&reply_to_id)?),
+            None => None,
+        },
+        content,
+        created_at,
+        edited_at,
+        deleted_at,
+        expires_at,
+    })
+}
+
+async fn load_message(db_pool: &SqlitePool, room_id: Uuid, message_id: Uuid) -> AppResult<Message> {
+    let row: MessageRow = sqlx::query_as(&format!("SELECT {MESSAGE_COLUMNS} FROM messages WHERE id=? AND room_id=?"))
+        .bind(message_id.to_string())
+        .bind(room_id.to_string())
+        .fetch_optional(db_pool)
+        .await?
+        .ok_or_else(|| error(StatusCode::NOT_FOUND, "No such message"))?;
+    to_message(room_id, row)
+}
+
+/// Pages back through a room, newest first.
+#[utoipa::path(
+    get,
+    path = "/rooms/{room_id}/messages",
+    tag = "messages",
+    params(("room_id" = Uuid, Path), MessagesQuery),
+    responses(
+        (status = 200, body = MessagePage),
+        (status = 401, body = ErrorBody),
+        (status = 404, body = ErrorBody),
+    ),
+)]
+#[debug_handler]
+pub(crate) async fn list_messages(
+    State(db_pool): State<SqlitePool>,
+    caller: Option<Caller>,
+    Path(room_id): Path<Uuid>,
+    Query(MessagesQuery { before, limit }): Query<MessagesQuery>,
+) -> AppResult<Json<MessagePage>> {
+    let caller = signed_in(caller)?;
+    access(&db_pool, &caller, room_id).await?;
+
+    let limit = limit.unwrap_or(50).clamp(1, 100);
+    // one extra to know if there's another page
+    let rows: Vec<MessageRow> = sqlx::query_as(&format!(
+        "SELECT {MESSAGE_COLUMNS} FROM messages
+        WHERE room_id=?1 AND (?2 IS NULL OR (created_at,id) < (SELECT created_at,id FROM messages WHERE id=?2 AND room_id=?1))
+        ORDER BY created_at DESC, id DESC
+        LIMIT ?3"
+    ))
+        .bind(room_id.to_string())
+        .bind(before.as_ref().map(Uuid::to_string))
+        .bind(limit + 1)
+        .fetch_all(&db_pool)
+        .await?;
+
+    let mut messages = rows.into_iter()
+        .map(|row| to_message(room_id, row))
+        .collect::<AppResult<Vec<_>>>()?;
+    let next_before = if messages.len() as i64 > limit {
+        messages.truncate(limit as usize);
+        messages.last().map(|message| message.id)
+    } else {
+        None
+    };
+
+    Ok(Json(MessagePage { messages, next_before }))
+}
+
+/// Posts as your profile in the room, joining public rooms on the way like the
+/// room page does. Messages the room's automod holds back get a 202.
+#[utoipa::path(
+    post,
+    path = "/rooms/{room_id}/messages",
+    tag = "messages",
+    params(("room_id" = Uuid, Path)),
+    request_body = NewMessage,
+    responses(
+        (status = 201, body = Message),
+        (status = 202, body = HeldMessage, description = "Held until a moderator approves it"),
+        (status = 400, body = ErrorBody),
+        (status = 401, body = ErrorBody),
+        (status = 403, body = ErrorBody),
+        (status = 404, body = ErrorBody),
+        (status = 429, body = ErrorBody, description = "Too many messages or slow mode, see `Retry-After`"),
+    ),
+)]
+#[debug_handler(state = AppState)]
+pub(crate) async fn send_message(
+    State(db_pool): State<SqlitePool>,
+    State(tx): State<broadcast::Sender<RoomEvent>>,
+    State(guests): State<GuestConfig>,
+    State(limits): State<Limits>,
+    caller: Option<Caller>,
+    Path(room_id): Path<Uuid>,
+    Json(NewMessage { content, reply_to_id, ttl_secs }): Json<NewMessage>,
+) -> AppResult<Response> {
+    let caller = signed_in(caller)?;
+    can_write(&caller)?;
+
+    let access = access(&db_pool, &caller, room_id).await?;
+    let profile_id = sender_profile(&db_pool, &guests, &limits, &caller.user_id, room_id, access).await?;
+
+    Ok(match msg::send_msg(&db_pool, &tx, profile_id, room_id, SendMessageQuery { reply_to_id, content, ttl_secs }).await? {
+        Sent::Posted(id) => (StatusCode::CREATED, Json(load_message(&db_pool, room_id, id).await?)).into_response(),
+        Sent::Held(held_id) => (StatusCode::ACCEPTED, Json(HeldMessage { held_id, message: HELD.to_owned() })).into_response(),
+    })
+}
+
+/// Only your own messages.
+#[utoipa::path(
+    patch,
+    path = "/rooms/{room_id}/messages/{message_id}",
+    tag = "messages",
+    params(("room_id" = Uuid, Path), ("message_id" = Uuid, Path)),
+    request_body = MessageEdit,
+    responses(
+        (status = 200, body = Message),
+        (status = 401, body = ErrorBody),
+        (status = 403, body = ErrorBody),
+        (status = 404, body = ErrorBody),
+    ),
+)]
+#[debug_handler(state = AppState)]
+pub(crate) async fn edit_message(
+    State(db_pool): State<SqlitePool>,
+    State(tx): State<broadcast::Sender<RoomEvent>>,
+    caller: Option<Caller>,
+    Path((room_id, message_id)): Path<(Uuid, Uuid)>,
+    Json(MessageEdit { content }): Json<MessageEdit>,
+) -> AppResult<Json<Message>> {
+    let caller = signed_in(caller)?;
+    can_write(&caller)?;
+
+    let Some(profile_id) = access(&db_pool, &caller, room_id).await?.profile_id() else {
+        return Err(error(StatusCode::NOT_FOUND, "No such message of yours"));
+    };
+
+    msg::edit_msg(&db_pool, &tx, profile_id, room_id, message_id, content).await?;
+    Ok(Json(load_message(&db_pool, room_id, message_id).await?))
+}
+
+/// Your own messages, or any in a room you moderate.
+#[utoipa::path(
+    delete,
+    path = "/rooms/{room_id}/messages/{message_id}",
+    tag = "messages",
+    params(("room_id" = Uuid, Path), ("message_id" = Uuid, Path)),
+    responses(
+        (status = 204),
+        (status = 401, body = ErrorBody),
+        (status = 403, body = ErrorBody),
+        (status = 404, body = ErrorBody),
+    ),
+)]
+#[debug_handler(state = AppState)]
+pub(crate) async fn delete_message(
+    State(db_pool): State<SqlitePool>,
+    State(tx): State<broadcast::Sender<RoomEvent>>,
+    caller: Option<Caller>,
+    Path((room_id, message_id)): Path<(Uuid, Uuid)>,
+) -> AppResult<StatusCode> {
+    let caller = signed_in(caller)?;
+    can_write(&caller)?;
+
+    let profile_id = access(&db_pool, &caller, room_id).await?.profile_id();
+    let message = load_message(&db_pool, room_id, message_id).await?;
+
+    if Some(message.profile_id) != profile_id && !can_moderate(&db_pool, &caller.user_id, room_id).await? {
+        return Err(error(StatusCode::FORBIDDEN, "Not your message"));
+    }
+
+    msg::delete_msg(&db_pool, &tx, room_id, message_id).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
@@ -0,0 +1,131 @@
+//! `/api/v1`, JSON over the same rules as the HTML pages.
+//!
+//! Sign in with a cookie session (send `X-CSRF-Token` on writes) or an API
+//! token as `Authorization: Bearer`. The OpenAPI document is generated from
+//! the handlers and served at `/api/v1/openapi.json`.
+
+mod messages;
+mod profiles;
+mod rooms;
+
+use axum::{body, extract::Request, http::{header, StatusCode}, middleware::{self, Next}, response::{IntoResponse, Response}, routing::get, Json, Router};
+use serde::Serialize;
+use sqlx::SqlitePool;
+use utoipa::{openapi::{security::{ApiKey, ApiKeyValue, HttpAuthScheme, HttpBuilder, SecurityScheme}, OpenApi as OpenApiDoc}, Modify, OpenApi, ToSchema};
+use utoipa_axum::{router::OpenApiRouter, routes};
+use uuid::Uuid;
+
+use crate::{auth::Caller, rooms::{room_access, RoomAccess}, AppError, AppResult, AppState};
+
+pub use messages::{Message, MessagePage, MessageEdit, NewMessage};
+pub use profiles::Profile;
+pub use rooms::{Present, Room};
+
+#[derive(OpenApi)]
+#[openapi(
+    info(title = "silentkisses", description = "Rooms, profiles and messages."),
+    servers((url = "/api/v1")),
+    modifiers(&Auth),
+    security(("token" = []), ("session" = [])),
+    tags(
+        (name = "rooms"),
+        (name = "messages"),
+        (name = "profiles"),
+    ),
+)]
+struct ApiDoc;
+
+struct Auth;
+
+impl Modify for Auth {
+    fn modify(&self, openapi: &mut OpenApiDoc) {
+        let components = openapi.components.get_or_insert_with(Default::default);
+        components.add_security_scheme(
+            "token",
+            SecurityScheme::Http(HttpBuilder::new().scheme(HttpAuthScheme::Bearer).description(Some("API token from /account/tokens")).build()),
+        );
+        components.add_security_scheme(
+            "session",
+            SecurityScheme::ApiKey(ApiKey::Cookie(ApiKeyValue::with_description("id", "cookie session, writes also need X-CSRF-Token"))),
+        );
+    }
+}
+
+/// What every error looks like.
+#[derive(Serialize, ToSchema)]
+pub struct ErrorBody {
+    pub error: String,
+}
+
+fn api_router() -> OpenApiRouter<AppState> {
+    OpenApiRouter::with_openapi(ApiDoc::openapi())
+        .routes(routes!(rooms::list_rooms))
+        .routes(routes!(rooms::get_room))
+        .routes(routes!(rooms::get_presence))
+        .routes(routes!(messages::list_messages, messages::send_message))
+        .routes(routes!(messages::edit_message, messages::delete_message))
+        .routes(routes!(profiles::get_profile))
+}
+
+/// The OpenAPI document for `/api/v1`.
+pub fn openapi() -> OpenApiDoc {
+    api_router().split_for_parts().1
+}
+
+pub fn router() -> Router<AppState> {
+    let (router, openapi) = api_router().split_for_parts();
+    router
+        .route("/openapi.json", get(move || {
+            let openapi = openapi.clone();
+            async move { Json(openapi) }
+        }))
+        .layer(middleware::from_fn(json_errors))
+}
+
+pub(crate) fn error(status: StatusCode, message: &str) -> AppError {
+    (status, Json(ErrorBody { error: message.to_owned() })).into_response().into()
+}
+
+/// Turns the plain text errors from shared code and extractors into [`ErrorBody`].
+/// Server errors only say so, the details go to the log.
+async fn json_errors(request: Request, next: Next) -> Response {
+    let response = next.run(request).await;
+    let status = response.status();
+    let is_json = response.headers().get(header::CONTENT_TYPE)
+        .is_some_and(|value| value.as_bytes().starts_with(b"application/json"));
+    if is_json || !(status.is_client_error() || status.is_server_error()) {
+        return response;
+    }
+
+    let (mut parts, body) = response.into_parts();
+    let text = body::to_bytes(body, usize::MAX).await
+        .map(|bytes| String::from_utf8_lossy(&bytes).trim().to_owned())
+        .unwrap_or_default();
+    let error = if status.is_server_error() {
+        println!("api: {text}");
+        "Something went wrong".to_owned()
+    } else {
+        text
+    };
+
+    parts.headers.remove(header::CONTENT_TYPE);
+    parts.headers.remove(header::CONTENT_LENGTH);
+    (parts, Json(ErrorBody { error })).into_response()
+}
+
+fn signed_in(caller: Option<Caller>) -> AppResult<Caller> {
+    caller.ok_or_else(|| error(StatusCode::UNAUTHORIZED, "Sign in or send an API token"))
+}
+
+/// Same as [`room_access`], but missing and forbidden rooms look alike.
+async fn access(db_pool: &SqlitePool, caller: &Caller, room_id: Uuid) -> AppResult<RoomAccess> {
+    room_access(db_pool, Some(&caller.user_id), room_id).await?
+        .ok_or_else(|| error(StatusCode::NOT_FOUND, "No such room"))
+}
+
+fn can_write(caller: &Caller) -> AppResult<()> {
+    if !caller.can_write {
+        return Err(error(StatusCode::FORBIDDEN, "This token is read only"));
+    }
+    Ok(())
+}
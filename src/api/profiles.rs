@@ -0,0 +1,56 @@
+use axum::{debug_handler, extract::{Path, State}, http::StatusCode, Json};
+use serde::Serialize;
+use sqlx::SqlitePool;
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+use crate::{auth::Caller, AppResult};
+
+use super::{access, error, signed_in, ErrorBody};
+
+#[derive(Serialize, ToSchema)]
+pub struct Profile {
+    pub id: Uuid,
+    pub room_id: Uuid,
+    pub handle: String,
+    pub alias: String,
+}
+
+/// Anyone who can see the room can see its profiles.
+#[utoipa::path(
+    get,
+    path = "/profiles/{profile_id}",
+    tag = "profiles",
+    params(("profile_id" = Uuid, Path)),
+    responses(
+        (status = 200, body = Profile),
+        (status = 401, body = ErrorBody),
+        (status = 404, body = ErrorBody),
+    ),
+)]
+#[debug_handler]
+pub(crate) async fn get_profile(
+    State(db_pool): State<SqlitePool>,
+    caller: Option<Caller>,
+    Path(profile_id): Path<Uuid>,
+) -> AppResult<Json<Profile>> {
+    let caller = signed_in(caller)?;
+    let not_found = || error(StatusCode::NOT_FOUND, "No such profile");
+
+    let (room_id, handle, alias): (String, String, String) =
+        sqlx::query_as("SELECT room_id,handle,alias FROM profiles WHERE uuid=?")
+            .bind(profile_id.to_string())
+            .fetch_optional(&db_pool)
+            .await?
+            .ok_or_else(not_found)?;
+    let room_id = Uuid::parse_str(&room_id)?;
+
+    access(&db_pool, &caller, room_id).await.map_err(|_| not_found())?;
+
+    Ok(Json(Profile {
+        id: profile_id,
+        room_id,
+        handle,
+        alias,
+    }))
+}
@@ -0,0 +1,133 @@
+use axum::{debug_handler, extract::{Path, State}, Json};
+use serde::Serialize;
+use sqlx::SqlitePool;
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+use crate::{auth::Caller, rooms::{room_access, Presence, RoomAccess}, AppResult, AppState};
+
+use super::{access, signed_in, ErrorBody};
+
+#[derive(Serialize, ToSchema)]
+pub struct Room {
+    pub id: Uuid,
+    pub name: String,
+    pub description: String,
+    pub is_public: bool,
+    pub is_archived: bool,
+    pub members: i64,
+    /// your profile here, absent if you haven't joined
+    pub profile_id: Option<Uuid>,
+}
+
+/// Someone with the room open right now.
+#[derive(Serialize, ToSchema)]
+pub struct Present {
+    pub profile_id: Uuid,
+    pub alias: String,
+}
+
+async fn load_room(db_pool: &SqlitePool, room_id: Uuid, access: RoomAccess) -> AppResult<Room> {
+    let (name, description, is_public, is_archived, members): (String, String, bool, bool, i64) = sqlx::query_as(
+        "SELECT name,description,is_public,is_archived,
+            (SELECT count(*) FROM profiles WHERE profiles.room_id=rooms.uuid)
+        FROM rooms WHERE uuid=?"
+    )
+        .bind(room_id.to_string())
+        .fetch_one(db_pool)
+        .await?;
+
+    Ok(Room {
+        id: room_id,
+        name,
+        description,
+        is_public,
+        is_archived,
+        members,
+        profile_id: access.profile_id(),
+    })
+}
+
+/// Rooms you have a profile in.
+#[utoipa::path(
+    get,
+    path = "/rooms",
+    tag = "rooms",
+    responses(
+        (status = 200, body = Vec<Room>),
+        (status = 401, body = ErrorBody),
+    ),
+)]
+#[debug_handler]
+pub(crate) async fn list_rooms(
+    State(db_pool): State<SqlitePool>,
+    caller: Option<Caller>,
+) -> AppResult<Json<Vec<Room>>> {
+    let caller = signed_in(caller)?;
+
+    let room_ids: Vec<(String,)> = sqlx::query_as("SELECT room_id FROM profiles WHERE user_id=?")
+        .bind(&caller.user_id)
+        .fetch_all(&db_pool)
+        .await?;
+
+    let mut rooms = Vec::new();
+    for (room_id,) in room_ids {
+        let room_id = Uuid::parse_str(&room_id)?;
+        // guests and bots can have profiles in rooms they no longer get
+        if let Some(access) = room_access(&db_pool, Some(&caller.user_id), room_id).await? {
+            rooms.push(load_room(&db_pool, room_id, access).await?);
+        }
+    }
+    Ok(Json(rooms))
+}
+
+#[utoipa::path(
+    get,
+    path = "/rooms/{room_id}",
+    tag = "rooms",
+    params(("room_id" = Uuid, Path)),
+    responses(
+        (status = 200, body = Room),
+        (status = 401, body = ErrorBody),
+        (status = 404, body = ErrorBody),
+    ),
+)]
+#[debug_handler]
+pub(crate) async fn get_room(
+    State(db_pool): State<SqlitePool>,
+    caller: Option<Caller>,
+    Path(room_id): Path<Uuid>,
+) -> AppResult<Json<Room>> {
+    let caller = signed_in(caller)?;
+    let access = access(&db_pool, &caller, room_id).await?;
+    Ok(Json(load_room(&db_pool, room_id, access).await?))
+}
+
+/// Who has the room open right now, by alias.
+#[utoipa::path(
+    get,
+    path = "/rooms/{room_id}/presence",
+    tag = "rooms",
+    params(("room_id" = Uuid, Path)),
+    responses(
+        (status = 200, body = Vec<Present>),
+        (status = 401, body = ErrorBody),
+        (status = 404, body = ErrorBody),
+    ),
+)]
+#[debug_handler(state = AppState)]
+pub(crate) async fn get_presence(
+    State(db_pool): State<SqlitePool>,
+    State(presence): State<Presence>,
+    caller: Option<Caller>,
+    Path(room_id): Path<Uuid>,
+) -> AppResult<Json<Vec<Present>>> {
+    let caller = signed_in(caller)?;
+    access(&db_pool, &caller, room_id).await?;
+
+    Ok(Json(
+        presence.snapshot(room_id).into_iter()
+            .map(|(profile_id, alias)| Present { profile_id, alias })
+            .collect()
+    ))
+}
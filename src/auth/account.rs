@@ -0,0 +1,126 @@
+use axum::{debug_handler, extract::{Path, State}, response::{Html, IntoResponse, Redirect, Response}, Form};
+use serde::Deserialize;
+use sqlx::SqlitePool;
+use tower_sessions::Session;
+
+use crate::{include_res, res, session::{LINKING, USER_ID}, AppResult, AppState};
+
+use super::{clients::ClientProvider, csrf_token, identities, is_guest, login, unlink_identity, Clients};
+
+#[derive(Deserialize)]
+pub(crate) struct UnlinkQuery {
+    provider: String,
+    subject: String,
+}
+
+#[derive(Deserialize)]
+pub(crate) struct DisplayNameForm {
+    display_name: String,
+}
+
+#[debug_handler(state = AppState)]
+pub(crate) async fn account(
+    State(db_pool): State<SqlitePool>,
+    State(clients): State<Clients>,
+    session: Session,
+) -> AppResult<Response> {
+    let Some(user_id) = session.get::<String>(USER_ID).await? else {
+        return Ok(Redirect::to("/login?return_url=/account").into_response());
+    };
+
+    let providers = clients.providers();
+    let name_of = |slug: &str| providers.iter()
+        .find(|(provider, _)| provider.slug() == slug)
+        .map(|(_, name)| name.clone())
+        .unwrap_or(slug.to_owned());
+
+    let csrf_token = csrf_token(&session).await?;
+    let linked = identities(&db_pool, &user_id).await?;
+    let mut identity_items = String::new();
+    for (provider, subject) in &linked {
+        identity_items += &include_res!(str, "pages/auth/identity_item.html")
+            .replace("{csrf_token}", &csrf_token)
+            .replace("{provider_name}", &res::escape(&name_of(provider)))
+            .replace("{provider}", &res::escape(provider))
+            .replace("{subject}", &res::escape(subject))
+            .replace("{disabled}", if linked.len() <= 1 { "disabled" } else { "" });
+    }
+
+    let mut link_providers = String::new();
+    for (provider, name) in &providers {
+        link_providers += &format!(
+            "<li><form action=\"/account/link/{}\" method=\"post\"><input name=\"csrf_token\" type=\"hidden\" value=\"{}\"/><input type=\"submit\" value=\"{}\"/></form></li>\n",
+            provider.slug(),
+            csrf_token,
+            res::escape(name),
+        );
+    }
+
+    let display_name: Option<String> = sqlx::query_scalar("SELECT display_name FROM users WHERE id=?")
+        .bind(&user_id)
+        .fetch_one(&db_pool)
+        .await?;
+
+    Ok(Html(
+        include_res!(str, "pages/auth/account.html")
+            .replace("{csrf_token}", &csrf_token)
+            .replace("{display_name}", &res::escape(&display_name.unwrap_or_default()))
+            .replace("{identities}", &identity_items)
+            .replace("{link_providers}", &link_providers)
+    ).into_response())
+}
+
+/// A POST (with the CSRF token) so another site can't link its own account
+/// to yours.
+#[debug_handler(state = AppState)]
+pub(crate) async fn link(
+    Path(provider): Path<ClientProvider>,
+    State(db_pool): State<SqlitePool>,
+    State(clients): State<Clients>,
+    session: Session,
+) -> AppResult<Response> {
+    let Some(user_id) = session.get::<String>(USER_ID).await? else {
+        return Ok(Redirect::to("/login?return_url=/account").into_response());
+    };
+    // guests sign in instead, which upgrades them
+    if is_guest(&db_pool, &user_id).await? {
+        return res::sorry("linking (sign in first)");
+    }
+
+    session.insert(LINKING, true).await?;
+    login::authorize_redirect(&provider, &clients, &session).await
+}
+
+#[debug_handler]
+pub(crate) async fn unlink(
+    State(db_pool): State<SqlitePool>,
+    session: Session,
+    Form(UnlinkQuery { provider, subject }): Form<UnlinkQuery>,
+) -> AppResult<Response> {
+    let Some(user_id) = session.get::<String>(USER_ID).await? else {
+        return res::sorry("account");
+    };
+
+    unlink_identity(&db_pool, &user_id, &provider, &subject).await?;
+    Ok(Redirect::to("/account").into_response())
+}
+
+/// The name shown to people you agree to a reveal with, blank to clear it.
+#[debug_handler]
+pub(crate) async fn display_name(
+    State(db_pool): State<SqlitePool>,
+    session: Session,
+    Form(DisplayNameForm { display_name }): Form<DisplayNameForm>,
+) -> AppResult<Response> {
+    let Some(user_id) = session.get::<String>(USER_ID).await? else {
+        return res::sorry("account");
+    };
+
+    let display_name = display_name.trim();
+    sqlx::query("UPDATE users SET display_name=? WHERE id=?")
+        .bind((!display_name.is_empty()).then_some(display_name))
+        .bind(&user_id)
+        .execute(&db_pool)
+        .await?;
+    Ok(Redirect::to("/account").into_response())
+}
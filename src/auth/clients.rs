@@ -1,88 +1,150 @@
 use std::fmt;
 
-use oauth2::{basic::BasicClient, AuthUrl, Client, ClientId, ClientSecret, RedirectUrl, TokenUrl};
-use serde::Deserialize;
+use oauth2::{basic::BasicClient, AuthUrl, AuthorizationCode, Client, ClientId, ClientSecret, PkceCodeVerifier, RedirectUrl, TokenResponse, TokenUrl};
+use serde::{Deserialize, Deserializer};
 use serde_json::Value;
 
 use crate::{AppResult, GetField};
 
+use super::OidcConfig;
+
 type HappyClient = Client<oauth2::StandardErrorResponse<oauth2::basic::BasicErrorResponseType>, oauth2::StandardTokenResponse<oauth2::EmptyExtraTokenFields, oauth2::basic::BasicTokenType>, oauth2::StandardTokenIntrospectionResponse<oauth2::EmptyExtraTokenFields, oauth2::basic::BasicTokenType>, oauth2::StandardRevocableToken, oauth2::StandardErrorResponse<oauth2::RevocationErrorResponseType>, oauth2::EndpointSet, oauth2::EndpointNotSet, oauth2::EndpointNotSet, oauth2::EndpointNotSet, oauth2::EndpointSet>;
 
-#[derive(Debug, Deserialize, Clone, Copy)]
-#[serde(rename_all = "snake_case")]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum ClientProvider {
     Google,
     Github,
+    /// config-defined OpenID Connect provider, by its `id`
+    Oidc(String),
 }
 
 impl ClientProvider {
+    pub fn from_slug(slug: &str) -> ClientProvider {
+        use ClientProvider::*;
+        match slug {
+            "google" => Google,
+            "github" => Github,
+            _ => Oidc(slug.to_owned()),
+        }
+    }
+
+    /// as used in `/login/{provider}`
+    pub fn slug(&self) -> &str {
+        use ClientProvider::*;
+        match self {
+            Google => "google",
+            Github => "github",
+            Oidc(id) => id,
+        }
+    }
+
     pub fn id(&self) -> &str {
         use ClientProvider::*;
         match self {
             Google => "google.com",
             Github => "github.com",
+            Oidc(id) => id,
         }
     }
 }
 
+impl<'de> Deserialize<'de> for ClientProvider {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        Ok(ClientProvider::from_slug(&String::deserialize(deserializer)?))
+    }
+}
+
 impl fmt::Display for ClientProvider {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "{:?}", self)
+        match self {
+            ClientProvider::Oidc(id) => write!(f, "{id}"),
+            _ => write!(f, "{:?}", self),
+        }
     }
 }
 
+/// Where each built-in provider lives, any of which client_secret.json may
+/// override (`auth_url`, `token_url`, `userinfo_url`, `redirect_url`).
+struct ProviderUrls {
+    auth_url: &'static str,
+    token_url: &'static str,
+    userinfo_url: &'static str,
+    redirect_url: &'static str,
+}
+
+const GOOGLE_URLS: ProviderUrls = ProviderUrls {
+    auth_url: "https://accounts.google.com/o/oauth2/auth",
+    token_url: "https://oauth2.googleapis.com/token",
+    userinfo_url: "https://openidconnect.googleapis.com/v1/userinfo",
+    redirect_url: "http://localhost:8080/lockin/google",
+};
+
+const GITHUB_URLS: ProviderUrls = ProviderUrls {
+    auth_url: "https://github.com/login/oauth/authorize",
+    token_url: "https://github.com/login/oauth/access_token",
+    userinfo_url: "https://api.github.com/user",
+    redirect_url: "http://localhost:8080/lockin/github",
+};
+
 #[derive(Clone)]
-pub struct Clients {
-    pub(crate) firebase_idpurl: String,
-    google_client: Option<HappyClient>,
-    github_client: Option<HappyClient>,
+struct OAuthClient {
+    client: HappyClient,
+    userinfo_url: String,
 }
 
-impl Clients {
-    pub fn from_json(json: Value) -> AppResult<Clients> {
-        let firebase_idpurl = format!(
-            "https://identitytoolkit.googleapis.com/v1/accounts:signInWithIdp?key={}",
-            json.get_obj_field("firebase")?.get_str_field("apikey")?
-        );
-        let google_client = 'a: {
-            let json = json.get("google");
-            let Some(json) = json else {
-                break 'a None;
-            };
-            let client_id = ClientId::new(json.get_str_field("client_id")?);
-            let client_secret = ClientSecret::new(json.get_str_field("client_secret")?);
-
-            let auth_url = AuthUrl::new("https://accounts.google.com/o/oauth2/auth".to_string()).unwrap();
-            let token_url = TokenUrl::new("https://oauth2.googleapis.com/token".to_string()).unwrap();
-            let redirect_url = RedirectUrl::new("http://localhost:8080/lockin/google".to_owned()).unwrap();
-
-            Some(
-                BasicClient::new(client_id)
-                .set_client_secret(client_secret)
-                .set_auth_uri(auth_url)
-                .set_token_uri(token_url)
-                .set_redirect_uri(redirect_url)
-            )
+impl OAuthClient {
+    fn from_json(json: Option<&Value>, urls: ProviderUrls) -> AppResult<Option<OAuthClient>> {
+        let Some(json) = json else {
+            return Ok(None);
         };
-        let github_client = 'a: {
-            let json = json.get("github");
-            let Some(json) = json else {
-                break 'a None;
-            };
-            let client_id = ClientId::new(json.get_str_field("client_id")?);
-            let client_secret = ClientSecret::new(json.get_str_field("client_secret")?);
-
-            let auth_url = AuthUrl::new("https://github.com/login/oauth/authorize".to_string()).unwrap();
-            let token_url = TokenUrl::new("https://github.com/login/oauth/access_token".to_string()).unwrap();
-            let redirect_url = RedirectUrl::new("http://localhost:8080/lockin/github".to_owned()).unwrap();
-
-            Some(
-                BasicClient::new(client_id)
+        let client_id = ClientId::new(json.get_str_field("client_id")?);
+        let client_secret = ClientSecret::new(json.get_str_field("client_secret")?);
+
+        let url = |field: &str, default: &str| json.get_str_field(field).unwrap_or(default.to_owned());
+        let auth_url = AuthUrl::new(url("auth_url", urls.auth_url))?;
+        let token_url = TokenUrl::new(url("token_url", urls.token_url))?;
+        let redirect_url = RedirectUrl::new(url("redirect_url", urls.redirect_url))?;
+
+        Ok(Some(OAuthClient {
+            client: BasicClient::new(client_id)
                 .set_client_secret(client_secret)
                 .set_auth_uri(auth_url)
                 .set_token_uri(token_url)
-                .set_redirect_uri(redirect_url)
-            )
+                .set_redirect_uri(redirect_url),
+            userinfo_url: url("userinfo_url", urls.userinfo_url),
+        }))
+    }
+}
+
+#[derive(Clone)]
+pub struct Clients {
+    /// `None` when identities are resolved locally, see [`Clients::firebase_idpurl`]
+    firebase_idpurl: Option<String>,
+    google_client: Option<OAuthClient>,
+    github_client: Option<OAuthClient>,
+    oidc: Vec<OidcConfig>,
+}
+
+impl Clients {
+    pub fn from_json(json: Value) -> AppResult<Clients> {
+        // "identity": "local" ignores firebase even when its key is there
+        let local = json.get_str_field("identity").is_ok_and(|identity| identity == "local");
+        let firebase_idpurl = match json.get("firebase") {
+            Some(firebase) if !local => Some(format!(
+                "https://identitytoolkit.googleapis.com/v1/accounts:signInWithIdp?key={}",
+                firebase.get_str_field("apikey")?
+            )),
+            _ => None,
+        };
+        let google_client = OAuthClient::from_json(json.get("google"), GOOGLE_URLS)?;
+        let github_client = OAuthClient::from_json(json.get("github"), GITHUB_URLS)?;
+
+        let oidc = match json.get("oidc") {
+            Some(Value::Array(providers)) => providers.iter()
+                .map(OidcConfig::from_json)
+                .collect::<AppResult<Vec<_>>>()?,
+            Some(_) => return Err("expected oidc to be an array")?,
+            None => Vec::new(),
         };
 
         Ok(
@@ -90,15 +152,96 @@ impl Clients {
                 firebase_idpurl,
                 google_client,
                 github_client,
+                oidc,
             }
         )
     }
 
-    pub fn get_client(&self, provider: ClientProvider) -> AppResult<HappyClient> {
+    fn get_oauth(&self, provider: &ClientProvider) -> AppResult<&OAuthClient> {
         use ClientProvider::*;
         match provider {
-            Google => self.google_client.clone(),
-            Github => self.github_client.clone(),
+            Google => self.google_client.as_ref(),
+            Github => self.github_client.as_ref(),
+            Oidc(_) => None,
         }.ok_or(format!("OAuth provider {provider} keys not supplied").into())
     }
+
+    pub fn get_client(&self, provider: &ClientProvider) -> AppResult<HappyClient> {
+        Ok(self.get_oauth(provider)?.client.clone())
+    }
+
+    /// Firebase's `signInWithIdp`, if Firebase is the identity backend.
+    /// Without it users are resolved from the provider's userinfo instead.
+    pub fn firebase_idpurl(&self) -> Option<&str> {
+        self.firebase_idpurl.as_deref()
+    }
+
+    /// Trades an authorization code for the provider's access token.
+    pub async fn exchange_code(
+        &self,
+        provider: &ClientProvider,
+        http_client: &reqwest::Client,
+        code: String,
+        pkce_verifier: String,
+    ) -> AppResult<String> {
+        let token_result = self.get_client(provider)?
+            .exchange_code(AuthorizationCode::new(code))
+            .set_pkce_verifier(PkceCodeVerifier::new(pkce_verifier))
+            .request_async(http_client)
+            .await?;
+
+        Ok(token_result.access_token().secret().clone())
+    }
+
+    /// Asks the provider who the access token belongs to, returns their stable
+    /// subject (`sub` for Google, numeric `id` for GitHub).
+    /// The account's subject and what it goes by there (Github's login,
+    /// Google's email).
+    pub async fn userinfo(
+        &self,
+        provider: &ClientProvider,
+        http_client: &reqwest::Client,
+        access_token: &str,
+    ) -> AppResult<(String, Option<String>)> {
+        let body: Value = http_client.get(&self.get_oauth(provider)?.userinfo_url)
+            .bearer_auth(access_token)
+            .header(reqwest::header::USER_AGENT, "silentkisses")
+            .header(reqwest::header::ACCEPT, "application/json")
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+
+        let subject = match body.get("sub").or(body.get("id")) {
+            Some(Value::String(subject)) => subject.clone(),
+            Some(Value::Number(subject)) => subject.to_string(),
+            _ => return Err(format!("expected sub or id in {body}"))?,
+        };
+        let handle = body.get("login").or(body.get("email"))
+            .and_then(Value::as_str)
+            .map(str::to_owned);
+        Ok((subject, handle))
+    }
+
+    pub fn get_oidc(&self, id: &str) -> AppResult<&OidcConfig> {
+        self.oidc.iter()
+            .find(|config| config.id == id)
+            .ok_or(format!("OIDC provider {id} not configured").into())
+    }
+
+    /// `(provider, display name)` for every provider with keys supplied
+    pub fn providers(&self) -> Vec<(ClientProvider, String)> {
+        let mut providers = Vec::new();
+        if self.google_client.is_some() {
+            providers.push((ClientProvider::Google, "Google".to_owned()));
+        }
+        if self.github_client.is_some() {
+            providers.push((ClientProvider::Github, "Github".to_owned()));
+        }
+        for config in &self.oidc {
+            providers.push((ClientProvider::Oidc(config.id.clone()), config.name.clone()));
+        }
+        providers
+    }
 }
\ No newline at end of file
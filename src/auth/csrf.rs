@@ -0,0 +1,124 @@
+use axum::{body::{self, Body}, extract::{FromRef, FromRequestParts, Request}, http::{header, request::Parts, HeaderMap, Method, StatusCode}, middleware::Next, response::{IntoResponse, Response}};
+use rand::Rng;
+use tower_sessions::Session;
+
+use crate::{res, session::CSRF_TOKEN, AppResult};
+
+use super::ApiToken;
+
+/// Scripts send the token here instead of in a form field.
+pub const CSRF_HEADER: &str = "x-csrf-token";
+
+/// Same as axum's default body limit.
+const FORM_LIMIT: usize = 2 * 1024 * 1024;
+
+/// The session's CSRF token, made on first use. Every form that POSTs needs it
+/// as `<input name="csrf_token" type="hidden" value="{csrf_token}"/>`.
+pub async fn csrf_token(session: &Session) -> AppResult<String> {
+    if let Some(token) = session.get::<String>(CSRF_TOKEN).await? {
+        return Ok(token);
+    }
+
+    let token = res::hex(&rand::rng().random::<[u8; 32]>());
+    session.insert(CSRF_TOKEN, token.clone()).await?;
+    Ok(token)
+}
+
+/// Middleware rejecting any non-GET request that doesn't carry the session's
+/// CSRF token, either as a `csrf_token` form field or an `X-CSRF-Token` header.
+///
+/// API token requests are let through, browsers don't send those by themselves.
+pub async fn verify_csrf(session: Session, request: Request, next: Next) -> AppResult<Response> {
+    if matches!(*request.method(), Method::GET | Method::HEAD | Method::OPTIONS)
+        || request.extensions().get::<ApiToken>().is_some()
+    {
+        return Ok(next.run(request).await);
+    }
+
+    let (parts, body) = request.into_parts();
+    let mut sent = parts.headers.get(CSRF_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .map(str::to_owned);
+
+    // forms carry it in the body, which the handler still needs afterwards
+    let body = if sent.is_none() && is_form(&parts.headers) {
+        let Ok(bytes) = body::to_bytes(body, FORM_LIMIT).await else {
+            return Ok(StatusCode::PAYLOAD_TOO_LARGE.into_response());
+        };
+        sent = serde_urlencoded::from_bytes::<Vec<(String, String)>>(&bytes)
+            .unwrap_or_default()
+            .into_iter()
+            .find(|(key, _)| key == "csrf_token")
+            .map(|(_, value)| value);
+        Body::from(bytes)
+    } else {
+        body
+    };
+
+    let expected = session.get::<String>(CSRF_TOKEN).await?;
+    match (sent, expected) {
+        (Some(sent), Some(expected)) if tokens_match(&sent, &expected) =>
+            Ok(next.run(Request::from_parts(parts, body)).await),
+        _ => Ok((
+            StatusCode::FORBIDDEN,
+            "This form has expired, go back, reload the page and try again"
+        ).into_response()),
+    }
+}
+
+fn is_form(headers: &HeaderMap) -> bool {
+    headers.get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.starts_with("application/x-www-form-urlencoded"))
+}
+
+/// Compares without bailing at the first difference.
+fn tokens_match(a: &str, b: &str) -> bool {
+    a.len() == b.len() && a.bytes().zip(b.bytes()).fold(0, |diff, (a, b)| diff | (a ^ b)) == 0
+}
+
+/// Origins allowed to open room sockets, set in .env as a comma separated
+/// `ALLOWED_ORIGINS` (default http://localhost:8080).
+#[derive(Debug, Clone)]
+pub struct AllowedOrigins(pub Vec<String>);
+
+impl AllowedOrigins {
+    pub fn from_env() -> AllowedOrigins {
+        let origins = dotenv::var("ALLOWED_ORIGINS").unwrap_or("http://localhost:8080".to_owned());
+        AllowedOrigins(
+            origins.split(',')
+                .map(|origin| origin.trim().trim_end_matches('/').to_owned())
+                .filter(|origin| !origin.is_empty())
+                .collect()
+        )
+    }
+
+    /// Browsers always send `Origin` on a WebSocket upgrade, so a missing one
+    /// is a non-browser client, which can't be riding on someone's cookies.
+    pub fn allows(&self, headers: &HeaderMap) -> bool {
+        match headers.get(header::ORIGIN) {
+            None => true,
+            Some(origin) => origin.to_str().is_ok_and(|origin| self.0.iter().any(|allowed| allowed == origin)),
+        }
+    }
+}
+
+/// Extractor rejecting requests from origins not in [`AllowedOrigins`], for
+/// WebSocket upgrades, where cookies ride along cross-site and SameSite doesn't help.
+pub struct AllowedOrigin;
+
+impl<S> FromRequestParts<S> for AllowedOrigin
+where
+    AllowedOrigins: FromRef<S>,
+    S: Send + Sync,
+{
+    type Rejection = Response;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        if AllowedOrigins::from_ref(state).allows(&parts.headers) {
+            Ok(AllowedOrigin)
+        } else {
+            Err((StatusCode::FORBIDDEN, "Origin not allowed").into_response())
+        }
+    }
+}
@@ -0,0 +1,232 @@
+use axum::{debug_handler, extract::State, http::HeaderMap, response::{IntoResponse, Redirect, Response}};
+use sqlx::SqlitePool;
+use tower_sessions::Session;
+use uuid::Uuid;
+
+use crate::{res, session::{RETURN_URL, USER_ID}, AppResult, AppState};
+
+use super::{safe_redirect, sessions::sign_in};
+
+/// Opt-in signed-out access, set in .env:
+/// `GUESTS=1`, `GUEST_MESSAGES_PER_MINUTE` (default 10), `GUEST_MAX_ROOMS` (default 5).
+#[derive(Debug, Clone)]
+pub struct GuestConfig {
+    pub enabled: bool,
+    pub messages_per_minute: i64,
+    pub max_rooms: i64,
+}
+
+impl GuestConfig {
+    pub fn from_env() -> GuestConfig {
+        let var = |key: &str, default: i64| dotenv::var(key).ok()
+            .and_then(|value| value.parse().ok())
+            .unwrap_or(default);
+
+        GuestConfig {
+            enabled: var("GUESTS", 0) != 0,
+            messages_per_minute: var("GUEST_MESSAGES_PER_MINUTE", 10),
+            max_rooms: var("GUEST_MAX_ROOMS", 5),
+        }
+    }
+}
+
+pub async fn is_guest(db_pool: &SqlitePool, user_id: &str) -> AppResult<bool> {
+    Ok(
+        sqlx::query_as::<_, (bool,)>("SELECT is_guest FROM users WHERE id=?")
+            .bind(user_id)
+            .fetch_optional(db_pool)
+            .await?
+            .is_some_and(|(is_guest,)| is_guest)
+    )
+}
+
+pub async fn create_guest(db_pool: &SqlitePool) -> AppResult<String> {
+    let user_id = Uuid::now_v7().to_string();
+    sqlx::query("INSERT INTO users (id,is_guest,created_at) VALUES (?,1,unixepoch())")
+        .bind(&user_id)
+        .execute(db_pool)
+        .await?;
+    Ok(user_id)
+}
+
+/// Whether a guest is still under their message and room limits.
+/// Full accounts always can.
+pub async fn guest_can_post(db_pool: &SqlitePool, guests: &GuestConfig, user_id: &str) -> AppResult<bool> {
+    if !is_guest(db_pool, user_id).await? {
+        return Ok(true);
+    }
+
+    // DMs count too, or they'd get around it
+    let (sent,): (i64,) = sqlx::query_as(
+        "SELECT (
+            SELECT count(*) FROM messages JOIN profiles ON profiles.uuid=messages.profile_id
+            WHERE profiles.user_id=?1 AND messages.created_at > unixepoch()-60
+        ) + (
+            SELECT count(*) FROM direct_messages JOIN profiles ON profiles.uuid=direct_messages.from_id
+            WHERE profiles.user_id=?1 AND direct_messages.created_at > unixepoch()-60
+        )"
+    )
+        .bind(user_id)
+        .fetch_one(db_pool)
+        .await?;
+    Ok(sent < guests.messages_per_minute)
+}
+
+pub async fn guest_can_join(db_pool: &SqlitePool, guests: &GuestConfig, user_id: &str) -> AppResult<bool> {
+    if !is_guest(db_pool, user_id).await? {
+        return Ok(true);
+    }
+
+    let (rooms,): (i64,) = sqlx::query_as("SELECT count(*) FROM profiles WHERE user_id=?")
+        .bind(user_id)
+        .fetch_one(db_pool)
+        .await?;
+    Ok(rooms < guests.max_rooms)
+}
+
+/// Every `(table, column)` holding a profile id, moved over when a guest's
+/// profile folds into the account's profile in the same room.
+const FOLDED: [(&str, &str); 10] = [
+    ("messages", "profile_id"),
+    ("reports", "profile_id"),
+    ("direct_messages", "from_id"),
+    ("direct_messages", "to_id"),
+    ("profile_blocks", "blocker_id"),
+    ("profile_blocks", "blocked_id"),
+    ("reveals", "from_id"),
+    ("reveals", "to_id"),
+    ("held_messages", "profile_id"),
+    ("automod_log", "profile_id"),
+];
+
+/// Turns a guest into a full account on their first real sign in.
+///
+/// If the identity is new, the guest keeps their id and gets the identity. If
+/// it already belongs to someone, the guest's profiles move over to them. In
+/// rooms where they already have one, everything in [`FOLDED`] and any mute
+/// move to it and the guest's profile goes. Bans move over either way.
+pub async fn upgrade_guest(
+    db_pool: &SqlitePool,
+    guest_id: &str,
+    provider: &str,
+    subject: &str,
+) -> AppResult<String> {
+    let mut tx = db_pool.begin().await?;
+    let existing: Option<(String,)> = sqlx::query_as("SELECT user_id FROM identities WHERE provider=? AND subject=?")
+        .bind(provider)
+        .bind(subject)
+        .fetch_optional(&mut *tx)
+        .await?;
+
+    let user_id = match existing {
+        Some((user_id,)) if user_id != guest_id => {
+            sqlx::query("UPDATE OR IGNORE profiles SET user_id=? WHERE user_id=?")
+                .bind(&user_id)
+                .bind(guest_id)
+                .execute(&mut *tx)
+                .await?;
+            // bans and mutes follow the person, or signing in would lift them
+            sqlx::query("UPDATE OR IGNORE room_bans SET user_id=? WHERE user_id=?")
+                .bind(&user_id)
+                .bind(guest_id)
+                .execute(&mut *tx)
+                .await?;
+            sqlx::query("DELETE FROM room_bans WHERE user_id=?")
+                .bind(guest_id)
+                .execute(&mut *tx)
+                .await?;
+            sqlx::query(
+                "UPDATE profiles SET muted_until=max(coalesce(muted_until, 0), (
+                    SELECT guest.muted_until FROM profiles guest WHERE guest.user_id=?2 AND guest.room_id=profiles.room_id
+                ))
+                WHERE user_id=?1 AND room_id IN (SELECT room_id FROM profiles WHERE user_id=?2 AND muted_until IS NOT NULL)"
+            )
+                .bind(&user_id)
+                .bind(guest_id)
+                .execute(&mut *tx)
+                .await?;
+            // what's left are rooms the account was already in, everything
+            // pointing at those profiles moves to the account's, deleting them
+            // would take reports against the guest along
+            for (table, column) in FOLDED {
+                sqlx::query(&format!(
+                    "UPDATE OR IGNORE {table} SET {column}=(
+                        SELECT kept.uuid FROM profiles kept JOIN profiles guest ON guest.room_id=kept.room_id
+                        WHERE guest.uuid={table}.{column} AND kept.user_id=?1
+                    )
+                    WHERE {column} IN (SELECT uuid FROM profiles WHERE user_id=?2)"
+                ))
+                    .bind(&user_id)
+                    .bind(guest_id)
+                    .execute(&mut *tx)
+                    .await?;
+            }
+            // the account and the guest had each other in these, it's one person now
+            for (table, one, other) in [("direct_messages", "from_id", "to_id"), ("profile_blocks", "blocker_id", "blocked_id"), ("reveals", "from_id", "to_id")] {
+                sqlx::query(&format!("DELETE FROM {table} WHERE {one}={other}"))
+                    .execute(&mut *tx)
+                    .await?;
+            }
+            sqlx::query("UPDATE reports SET reporter_id=? WHERE reporter_id=?")
+                .bind(&user_id)
+                .bind(guest_id)
+                .execute(&mut *tx)
+                .await?;
+            sqlx::query("DELETE FROM profiles WHERE user_id=?")
+                .bind(guest_id)
+                .execute(&mut *tx)
+                .await?;
+            sqlx::query("DELETE FROM users WHERE id=?")
+                .bind(guest_id)
+                .execute(&mut *tx)
+                .await?;
+            user_id
+        },
+        // linked to the guest already, nothing to move
+        Some((user_id,)) => {
+            sqlx::query("UPDATE users SET is_guest=0 WHERE id=?")
+                .bind(&user_id)
+                .execute(&mut *tx)
+                .await?;
+            user_id
+        },
+        None => {
+            sqlx::query("UPDATE users SET is_guest=0 WHERE id=?")
+                .bind(guest_id)
+                .execute(&mut *tx)
+                .await?;
+            sqlx::query("INSERT INTO identities (user_id,provider,subject) VALUES (?,?,?)")
+                .bind(guest_id)
+                .bind(provider)
+                .bind(subject)
+                .execute(&mut *tx)
+                .await?;
+            guest_id.to_owned()
+        },
+    };
+
+    tx.commit().await?;
+    println!("upgraded guest u/{guest_id} to u/{user_id}");
+    Ok(user_id)
+}
+
+#[debug_handler(state = AppState)]
+pub(crate) async fn guest(
+    State(db_pool): State<SqlitePool>,
+    State(guests): State<GuestConfig>,
+    session: Session,
+    headers: HeaderMap,
+) -> AppResult<Response> {
+    if !guests.enabled {
+        return res::sorry("guest mode");
+    }
+
+    if session.get::<String>(USER_ID).await?.is_none() {
+        let user_id = create_guest(&db_pool).await?;
+        sign_in(&session, &user_id, "guest", &headers).await?;
+        println!("welcome guest u/{user_id}");
+    }
+
+    let return_url: String = session.remove(RETURN_URL).await?.unwrap_or("/r".to_owned());
+    Ok(Redirect::to(&safe_redirect(Some(return_url))).into_response())
+}
@@ -1,12 +1,12 @@
-use axum::{debug_handler, extract::{Path, Query, State}, response::{IntoResponse, Redirect}};
-use oauth2::{AuthorizationCode, CsrfToken, PkceCodeVerifier, TokenResponse};
+use axum::{debug_handler, extract::{Path, Query, State}, http::HeaderMap, response::{IntoResponse, Redirect}};
+use oauth2::{AuthorizationCode, CsrfToken};
 use serde::{Deserialize, Serialize};
 use sqlx::SqlitePool;
 use tower_sessions::Session;
 
-use crate::{session::{CSRF_STATE, PKCE_VERIFIER, USER_ID}, AppResult, AppState, GetField};
+use crate::{session::{CSRF_STATE, LINKING, NONCE, PKCE_VERIFIER, RETURN_URL, USER_ID}, AppResult, AppState, GetField};
 
-use super::{clients::ClientProvider, Clients};
+use super::{clients::ClientProvider, is_guest, sessions::sign_in, link_identity, resolve_user, safe_redirect, set_identity_handle, upgrade_guest, Clients};
 
 #[derive(Deserialize)]
 pub struct LockinQuery {
@@ -29,6 +29,7 @@ pub(crate) async fn lockin(
     State(db_pool): State<SqlitePool>,
     State(clients): State<Clients>,
     session: Session,
+    headers: HeaderMap,
 ) -> AppResult<impl IntoResponse> {
     let state = CsrfToken::new(state.ok_or("OAuth: without state")?);
     let code = AuthorizationCode::new(code.ok_or("OAuth: without code")?);
@@ -45,36 +46,69 @@ pub(crate) async fn lockin(
         return Err("no pkce_verifier")?;
     };
     
-    let client = clients.get_client(provider)?;
     let http_client = reqwest::ClientBuilder::new()
         .redirect(reqwest::redirect::Policy::none())
         .build()?;
-    let token_result = client
-        .exchange_code(code)
-        .set_pkce_verifier(PkceCodeVerifier::new(pkce_verifier))
-        .request_async(&http_client)
-        .await?;
-
-    let access_token = token_result.access_token().secret();
-    let body: serde_json::Value = http_client.post(clients.firebase_idpurl)
-        .json(&FirebaseRequest {
-            post_body: format!("access_token={access_token}&providerId={}", provider.id()),
-            request_uri: "http://localhost/".to_owned(),
-            return_idp_credential: true,
-            return_secure_token: true,
-        })
-        .send()
-        .await?
-        .json()
-        .await?;
-    
-    let user_id = body.get_str_field("localId")?;
-    session.insert(USER_ID, user_id.clone()).await?;
 
-    let return_url = session.get("return_url").await?;
+    let (subject, firebase_id, handle) = if let ClientProvider::Oidc(id) = &provider {
+        let Some(nonce) = session.remove::<String>(NONCE).await? else {
+            return Err("no nonce")?;
+        };
+
+        let identity = clients.get_oidc(id)?
+            .exchange(&http_client, code.into_secret(), pkce_verifier, nonce)
+            .await?;
+        (identity.subject, None, identity.handle)
+    } else {
+        let access_token = clients.exchange_code(&provider, &http_client, code.into_secret(), pkce_verifier).await?;
+
+        match clients.firebase_idpurl() {
+            Some(firebase_idpurl) => {
+                let body: serde_json::Value = http_client.post(firebase_idpurl)
+                    .json(&FirebaseRequest {
+                        post_body: format!("access_token={access_token}&providerId={}", provider.id()),
+                        request_uri: "http://localhost/".to_owned(),
+                        return_idp_credential: true,
+                        return_secure_token: true,
+                    })
+                    .send()
+                    .await?
+                    .json()
+                    .await?;
+
+                let local_id = body.get_str_field("localId")?;
+                let handle = body.get_str_field("screenName").or(body.get_str_field("email")).ok();
+                (local_id.clone(), Some(local_id), handle)
+            },
+            None => {
+                let (subject, handle) = clients.userinfo(&provider, &http_client, &access_token).await?;
+                (subject, None, handle)
+            },
+        }
+    };
+
+    if session.remove::<bool>(LINKING).await?.unwrap_or(false) {
+        let Some(user_id) = session.get::<String>(USER_ID).await? else {
+            return Err("signed out while linking")?;
+        };
+
+        link_identity(&db_pool, &user_id, provider.slug(), &subject).await?;
+        set_identity_handle(&db_pool, provider.slug(), &subject, handle.as_deref()).await?;
+        println!("linked {provider} to u/{user_id}");
+        return Ok(Redirect::to("/account"));
+    }
+
+    let user_id = match session.get::<String>(USER_ID).await? {
+        Some(guest_id) if is_guest(&db_pool, &guest_id).await? => upgrade_guest(&db_pool, &guest_id, provider.slug(), &subject).await?,
+        _ => resolve_user(&db_pool, provider.slug(), &subject, firebase_id.as_deref()).await?,
+    };
+    // kept up to date, people rename themselves
+    set_identity_handle(&db_pool, provider.slug(), &subject, handle.as_deref()).await?;
+    sign_in(&session, &user_id, provider.slug(), &headers).await?;
+
+    let return_url = session.remove(RETURN_URL).await?;
     
     println!("welcome u/{user_id}");
 
-    let return_url: String = return_url.unwrap_or("/".to_string());
-    Ok(Redirect::to(return_url.as_str()))
+    Ok(Redirect::to(&safe_redirect(return_url)))
 }
\ No newline at end of file
@@ -3,18 +3,47 @@ use oauth2::{CsrfToken, PkceCodeChallenge, Scope};
 use serde::Deserialize;
 use tower_sessions::Session;
 
-use crate::{include_res, session::{CSRF_STATE, PKCE_VERIFIER, RETURN_URL}, AppResult};
+use crate::{include_res, res, session::{CSRF_STATE, LINKING, NONCE, PKCE_VERIFIER, RETURN_URL}, AppResult, AppState};
 
-use super::{clients::ClientProvider, Clients};
+use super::{clients::ClientProvider, csrf_token, is_safe_redirect, Clients, GuestConfig};
 
 #[derive(Deserialize)]
 pub(crate) struct LoginQuery {
     pub(crate) return_url: Option<String>,
 }
 
-#[debug_handler]
-pub(crate) async fn login_page() -> impl IntoResponse {
-    Html(include_res!(str, "/pages/auth/login.html"))
+#[debug_handler(state = AppState)]
+pub(crate) async fn login_page(
+    Query(LoginQuery { return_url }): Query<LoginQuery>,
+    State(clients): State<Clients>,
+    State(guests): State<GuestConfig>,
+    session: Session,
+) -> AppResult<Response> {
+    remember_return_url(&session, return_url).await?;
+
+    let mut providers = String::new();
+    for (provider, name) in clients.providers() {
+        providers += &format!(
+            "<button onclick=\"window.location.href = '/login/{}'\">{}</button>\n",
+            provider.slug(),
+            res::escape(&name),
+        );
+    }
+
+    Ok(Html(
+        include_res!(str, "/pages/auth/login.html")
+            .replace("{csrf_token}", &csrf_token(&session).await?)
+            .replace("{providers}", &providers)
+            .replace("{guest}", if guests.enabled { "" } else { "display: none;" })
+    ).into_response())
+}
+
+/// Keeps `return_url` for after sign in, but only if it stays on this site.
+async fn remember_return_url(session: &Session, return_url: Option<String>) -> AppResult<()> {
+    if let Some(return_url) = return_url.filter(|url| is_safe_redirect(url)) {
+        session.insert(RETURN_URL, return_url).await?;
+    }
+    Ok(())
 }
 
 
@@ -25,20 +54,43 @@ pub(crate) async fn login(
     State(clients): State<Clients>,
     session: Session,
 ) -> AppResult<Response> {
-    let client = clients.get_client(provider)?;
-    
-    let (pkce_code_challenge, pkce_verifier) = PkceCodeChallenge::new_random_sha256();
-
-    let (authorize_url, csrf_state) = client.authorize_url(CsrfToken::new_random)
-        .add_scope(Scope::new("openid".to_string()))
-        .set_pkce_challenge(pkce_code_challenge)
-        .url();
-
-    session.insert(CSRF_STATE, csrf_state.secret()).await?;
-    session.insert(PKCE_VERIFIER, pkce_verifier.secret()).await?;
-    if let Some(return_url) = return_url {
-        session.insert(RETURN_URL, return_url).await?;
-    }
+    remember_return_url(&session, return_url).await?;
+    // a link that was never finished mustn't turn this sign in into one
+    session.remove::<bool>(LINKING).await?;
+
+    authorize_redirect(&provider, &clients, &session).await
+}
+
+/// Sends the browser off to the provider, remembering what `lockin` needs to
+/// check when it comes back.
+pub(crate) async fn authorize_redirect(
+    provider: &ClientProvider,
+    clients: &Clients,
+    session: &Session,
+) -> AppResult<Response> {
+    let (authorize_url, csrf_state, pkce_verifier) = if let ClientProvider::Oidc(id) = provider {
+        let http_client = reqwest::ClientBuilder::new()
+            .redirect(reqwest::redirect::Policy::none())
+            .build()?;
+        let authorize = clients.get_oidc(id)?.authorize(&http_client).await?;
+
+        session.insert(NONCE, authorize.nonce).await?;
+        (authorize.url, authorize.csrf_state, authorize.pkce_verifier)
+    } else {
+        let client = clients.get_client(provider)?;
+
+        let (pkce_code_challenge, pkce_verifier) = PkceCodeChallenge::new_random_sha256();
+
+        let (authorize_url, csrf_state) = client.authorize_url(CsrfToken::new_random)
+            .add_scope(Scope::new("openid".to_string()))
+            .set_pkce_challenge(pkce_code_challenge)
+            .url();
+
+        (authorize_url.to_string(), csrf_state.secret().clone(), pkce_verifier.secret().clone())
+    };
+
+    session.insert(CSRF_STATE, csrf_state).await?;
+    session.insert(PKCE_VERIFIER, pkce_verifier).await?;
 
     Ok(Redirect::to(authorize_url.as_str()).into_response())
-}
\ No newline at end of file
+}
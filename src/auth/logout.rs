@@ -4,6 +4,8 @@ use tower_sessions::Session;
 
 use crate::AppResult;
 
+use super::safe_redirect;
+
 #[derive(Deserialize)]
 pub(crate) struct LogoutQuery {
     pub(crate) return_url: Option<String>,
@@ -14,6 +16,6 @@ pub(crate) async fn logout(
     Query(LogoutQuery { return_url }): Query<LogoutQuery>,
     session: Session
 ) -> AppResult<Redirect> {
-    session.clear().await;
-    Ok(Redirect::to(return_url.unwrap_or("/".to_string()).as_str()))
+    session.flush().await?;
+    Ok(Redirect::to(&safe_redirect(return_url)))
 }
\ No newline at end of file
@@ -1,15 +1,32 @@
-use axum::{routing::get, Router};
+use axum::{routing::{get, post}, Router};
 use rand::seq::IndexedRandom;
 use sqlx::SqlitePool;
 
 use uuid::Uuid;
 
+mod account;
 mod clients;
+mod csrf;
+mod guest;
 mod login;
 mod lockin;
 mod logout;
+mod oidc;
+mod redirect;
+mod sessions;
+mod store;
+mod tokens;
+mod users;
 
-pub use clients::Clients;
+pub use clients::{ClientProvider, Clients};
+pub use csrf::{csrf_token, verify_csrf, AllowedOrigin, AllowedOrigins, CSRF_HEADER};
+pub use guest::{create_guest, guest_can_join, guest_can_post, is_guest, upgrade_guest, GuestConfig};
+pub use oidc::{OidcAuthorize, OidcConfig, OidcIdentity};
+pub use redirect::{is_safe_redirect, safe_redirect};
+pub use sessions::coarse_user_agent;
+pub use store::{session_reaper, ActiveSession, SqliteStore};
+pub use tokens::{bearer_auth, create_token, delete_token, find_token, ApiToken, Caller};
+pub use users::{identities, link_identity, resolve_user, set_identity_handle, unlink_identity};
 
 use crate::AppState;
 
@@ -17,8 +34,18 @@ pub fn router() -> Router<AppState> {
     Router::new()
         .route("/login", get(login::login_page))
         .route("/login/{provider}", get(login::login))
+        .route("/guest", post(guest::guest))
         .route("/lockin/{provider}", get(lockin::lockin))
         .route("/logout", get(logout::logout))
+        .route("/account", get(account::account))
+        .route("/account/link/{provider}", post(account::link))
+        .route("/account/unlink", post(account::unlink))
+        .route("/account/name", post(account::display_name))
+        .route("/account/sessions", get(sessions::sessions))
+        .route("/account/sessions/revoke", post(sessions::revoke))
+        .route("/account/sessions/revoke_all", post(sessions::revoke_all))
+        .route("/account/tokens", get(tokens::tokens).post(tokens::create))
+        .route("/account/tokens/delete", post(tokens::delete))
 }
 
 pub(crate) async fn create_profile(db_pool: &SqlitePool, user_id: &str, room_id: &str) -> Result<(Uuid, sqlx::sqlite::SqliteQueryResult), sqlx::Error> {
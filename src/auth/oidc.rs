@@ -0,0 +1,143 @@
+use openidconnect::{
+    core::{CoreAuthenticationFlow, CoreClient, CoreProviderMetadata}, AccessTokenHash, AuthorizationCode, ClientId, ClientSecret, CsrfToken, EndpointMaybeSet, EndpointNotSet, EndpointSet, IssuerUrl, Nonce, OAuth2TokenResponse, PkceCodeChallenge, PkceCodeVerifier, RedirectUrl, Scope, TokenResponse
+};
+use serde_json::Value;
+
+use crate::{AppResult, GetField};
+
+use super::clients::ClientProvider;
+
+type OidcClient = CoreClient<EndpointSet, EndpointNotSet, EndpointNotSet, EndpointNotSet, EndpointMaybeSet, EndpointMaybeSet>;
+
+/// A config-defined OpenID Connect provider (Keycloak, Authentik, ...), found
+/// in client_secret.json under `"oidc": [ { "id": ..., ... } ]`.
+#[derive(Debug, Clone)]
+pub struct OidcConfig {
+    pub id: String,
+    pub name: String,
+    pub issuer: String,
+    pub client_id: String,
+    pub client_secret: Option<String>,
+    pub redirect_url: String,
+    pub scopes: Vec<String>,
+}
+
+/// Where to send the browser, plus what to remember until it comes back.
+pub struct OidcAuthorize {
+    pub url: String,
+    pub csrf_state: String,
+    pub nonce: String,
+    pub pkce_verifier: String,
+}
+
+/// Who the provider says signed in, from verified ID token claims.
+#[derive(Debug, Clone)]
+pub struct OidcIdentity {
+    pub subject: String,
+    pub name: Option<String>,
+    pub email: Option<String>,
+    /// what the account goes by there, for reveals
+    pub handle: Option<String>,
+}
+
+impl OidcConfig {
+    pub fn from_json(json: &Value) -> AppResult<OidcConfig> {
+        let id = json.get_str_field("id")?;
+        // the id is its /login/{provider} slug, which mustn't be a built in one
+        if !matches!(ClientProvider::from_slug(&id), ClientProvider::Oidc(_)) {
+            return Err(format!("oidc id {id} is taken by a built in provider"))?;
+        }
+        Ok(OidcConfig {
+            name: json.get_str_field("name").unwrap_or(id.clone()),
+            issuer: json.get_str_field("issuer")?,
+            client_id: json.get_str_field("client_id")?,
+            client_secret: json.get_str_field("client_secret").ok(),
+            redirect_url: json.get_str_field("redirect_url")
+                .unwrap_or(format!("http://localhost:8080/lockin/{id}")),
+            scopes: match json.get("scopes") {
+                Some(scopes) => serde_json::from_value(scopes.clone())?,
+                None => vec!["profile".to_owned(), "email".to_owned()],
+            },
+            id,
+        })
+    }
+
+    /// Runs discovery against the issuer, every time so rotated keys are picked up.
+    async fn client(&self, http_client: &reqwest::Client) -> AppResult<OidcClient> {
+        let metadata = CoreProviderMetadata::discover_async(IssuerUrl::new(self.issuer.clone())?, http_client).await?;
+
+        Ok(
+            CoreClient::from_provider_metadata(
+                metadata,
+                ClientId::new(self.client_id.clone()),
+                self.client_secret.clone().map(ClientSecret::new),
+            )
+            .set_redirect_uri(RedirectUrl::new(self.redirect_url.clone())?)
+        )
+    }
+
+    pub async fn authorize(&self, http_client: &reqwest::Client) -> AppResult<OidcAuthorize> {
+        let client = self.client(http_client).await?;
+        let (pkce_code_challenge, pkce_verifier) = PkceCodeChallenge::new_random_sha256();
+
+        let mut request = client.authorize_url(
+            CoreAuthenticationFlow::AuthorizationCode,
+            CsrfToken::new_random,
+            Nonce::new_random,
+        );
+        for scope in &self.scopes {
+            request = request.add_scope(Scope::new(scope.clone()));
+        }
+        let (url, csrf_state, nonce) = request
+            .set_pkce_challenge(pkce_code_challenge)
+            .url();
+
+        Ok(OidcAuthorize {
+            url: url.to_string(),
+            csrf_state: csrf_state.secret().clone(),
+            nonce: nonce.secret().clone(),
+            pkce_verifier: pkce_verifier.secret().clone(),
+        })
+    }
+
+    /// Trades the code for tokens and checks the ID token's signature, issuer,
+    /// audience, expiry, nonce and (if present) access token hash.
+    pub async fn exchange(
+        &self,
+        http_client: &reqwest::Client,
+        code: String,
+        pkce_verifier: String,
+        nonce: String,
+    ) -> AppResult<OidcIdentity> {
+        let client = self.client(http_client).await?;
+        let token_response = client
+            .exchange_code(AuthorizationCode::new(code))?
+            .set_pkce_verifier(PkceCodeVerifier::new(pkce_verifier))
+            .request_async(http_client)
+            .await?;
+
+        let id_token = token_response.id_token()
+            .ok_or(format!("OIDC provider {} did not return an ID token", self.id))?;
+        let id_token_verifier = client.id_token_verifier();
+        let claims = id_token.claims(&id_token_verifier, &Nonce::new(nonce))?;
+
+        if let Some(expected_hash) = claims.access_token_hash() {
+            let actual_hash = AccessTokenHash::from_token(
+                token_response.access_token(),
+                id_token.signing_alg()?,
+                id_token.signing_key(&id_token_verifier)?,
+            )?;
+            if actual_hash != *expected_hash {
+                return Err("OIDC access token hash doesn't match")?;
+            }
+        }
+
+        let email = claims.email().map(|email| email.to_string());
+        Ok(OidcIdentity {
+            subject: claims.subject().to_string(),
+            name: claims.name().and_then(|name| name.get(None)).map(|name| name.to_string()),
+            handle: claims.preferred_username().map(|username| username.to_string()).or(email.clone()),
+            email,
+        })
+    }
+}
@@ -0,0 +1,47 @@
+/// Whether `url` is a same-origin relative path that's safe to redirect to.
+///
+/// Browsers are generous about what they'll treat as another origin
+/// (`//evil.com`, `/\evil.com`, `/\t/evil.com`, ...), so anything that isn't
+/// plainly `/path` is refused, including after percent-decoding.
+pub fn is_safe_redirect(url: &str) -> bool {
+    let mut url = url.to_owned();
+    // decode a few times over, for %252F and friends
+    for _ in 0..3 {
+        if !url.starts_with('/')
+            || url.starts_with("//")
+            || url.contains('\\')
+            || url.chars().any(char::is_control) {
+            return false;
+        }
+
+        match percent_decode(&url) {
+            Some(decoded) if decoded == url => return true,
+            Some(decoded) => url = decoded,
+            None => return false,
+        }
+    }
+    false
+}
+
+/// `url` if it's safe to redirect to, `/` otherwise.
+pub fn safe_redirect(url: Option<String>) -> String {
+    url.filter(|url| is_safe_redirect(url))
+        .unwrap_or("/".to_owned())
+}
+
+fn percent_decode(url: &str) -> Option<String> {
+    let bytes = url.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' {
+            let hex = std::str::from_utf8(bytes.get(i + 1..i + 3)?).ok()?;
+            decoded.push(u8::from_str_radix(hex, 16).ok()?);
+            i += 3;
+        } else {
+            decoded.push(bytes[i]);
+            i += 1;
+        }
+    }
+    String::from_utf8(decoded).ok()
+}
@@ -0,0 +1,134 @@
+use axum::{debug_handler, extract::State, http::{header, HeaderMap}, response::{Html, IntoResponse, Redirect, Response}, Form};
+use serde::Deserialize;
+use tower_sessions::Session;
+
+use crate::{include_res, res, session::{PROVIDER, USER_AGENT, USER_ID}, AppResult, AppState};
+
+use super::{csrf_token, Clients, SqliteStore};
+
+#[derive(Deserialize)]
+pub(crate) struct RevokeQuery {
+    key: i64,
+}
+
+/// Signs `user_id` in under a fresh session id, noting how and from where for
+/// the sessions page.
+pub(crate) async fn sign_in(session: &Session, user_id: &str, provider: &str, headers: &HeaderMap) -> AppResult<()> {
+    session.cycle_id().await?;
+    session.insert(USER_ID, user_id).await?;
+    session.insert(PROVIDER, provider).await?;
+    session.insert(USER_AGENT, coarse_user_agent(
+        headers.get(header::USER_AGENT).and_then(|value| value.to_str().ok()).unwrap_or_default()
+    )).await?;
+    Ok(())
+}
+
+/// "Firefox on Linux", enough to tell sessions apart without keeping the full header.
+pub fn coarse_user_agent(user_agent: &str) -> String {
+    // order matters, Edge and Opera also say Chrome, Chrome also says Safari
+    let browser = [
+        ("Edg/", "Edge"),
+        ("OPR/", "Opera"),
+        ("Firefox/", "Firefox"),
+        ("Chrome/", "Chrome"),
+        ("Safari/", "Safari"),
+    ].into_iter().find(|(needle, _)| user_agent.contains(needle)).map(|(_, name)| name);
+
+    // same here, Android says Linux, iOS says Mac OS
+    let os = [
+        ("Android", "Android"),
+        ("iPhone", "iOS"),
+        ("iPad", "iOS"),
+        ("Windows", "Windows"),
+        ("Mac OS", "macOS"),
+        ("CrOS", "ChromeOS"),
+        ("Linux", "Linux"),
+    ].into_iter().find(|(needle, _)| user_agent.contains(needle)).map(|(_, name)| name);
+
+    match (browser, os) {
+        (Some(browser), Some(os)) => format!("{browser} on {os}"),
+        (Some(browser), None) => browser.to_owned(),
+        (None, Some(os)) => format!("Unknown browser on {os}"),
+        (None, None) => "Unknown browser".to_owned(),
+    }
+}
+
+#[debug_handler(state = AppState)]
+pub(crate) async fn sessions(
+    State(store): State<SqliteStore>,
+    State(clients): State<Clients>,
+    session: Session,
+) -> AppResult<Response> {
+    let Some(user_id) = session.get::<String>(USER_ID).await? else {
+        return Ok(Redirect::to("/login?return_url=/account/sessions").into_response());
+    };
+
+    let providers = clients.providers();
+    let name_of = |slug: &str| match slug {
+        "guest" => "Guest".to_owned(),
+        slug => providers.iter()
+            .find(|(provider, _)| provider.slug() == slug)
+            .map(|(_, name)| name.clone())
+            .unwrap_or(slug.to_owned()),
+    };
+
+    let csrf_token = csrf_token(&session).await?;
+    let mut session_items = String::new();
+    for active in store.list(&user_id).await? {
+        session_items += &include_res!(str, "pages/auth/session_item.html")
+            .replace("{csrf_token}", &csrf_token)
+            .replace("{key}", &active.key.to_string())
+            .replace("{user_agent}", &res::escape(active.user_agent.as_deref().unwrap_or("Unknown browser")))
+            .replace("{provider}", &res::escape(&active.provider.as_deref().map(name_of).unwrap_or("unknown".to_owned())))
+            .replace("{created_at}", &res::ago(active.created_at))
+            .replace("{last_active}", &res::ago(active.last_active))
+            .replace("{current}", if active.id.is_some() && active.id == session.id() { "(this one)" } else { "" });
+    }
+
+    Ok(Html(
+        include_res!(str, "pages/auth/sessions.html")
+            .replace("{csrf_token}", &csrf_token)
+            .replace("{sessions}", &session_items)
+    ).into_response())
+}
+
+#[debug_handler(state = AppState)]
+pub(crate) async fn revoke(
+    State(store): State<SqliteStore>,
+    session: Session,
+    Form(RevokeQuery { key }): Form<RevokeQuery>,
+) -> AppResult<Response> {
+    let Some(user_id) = session.get::<String>(USER_ID).await? else {
+        return res::sorry("session");
+    };
+
+    let is_current = store.list(&user_id).await?
+        .iter()
+        .any(|active| active.key == key && active.id.is_some() && active.id == session.id());
+
+    if !store.revoke(&user_id, key).await? {
+        return res::sorry("session");
+    }
+
+    if is_current {
+        session.flush().await?;
+        return Ok(Redirect::to("/").into_response());
+    }
+    Ok(Redirect::to("/account/sessions").into_response())
+}
+
+#[debug_handler(state = AppState)]
+pub(crate) async fn revoke_all(
+    State(store): State<SqliteStore>,
+    session: Session,
+) -> AppResult<Response> {
+    let Some(user_id) = session.get::<String>(USER_ID).await? else {
+        return res::sorry("session");
+    };
+
+    let revoked = store.revoke_all(&user_id).await?;
+    session.flush().await?;
+    println!("logged u/{user_id} out of {revoked} sessions");
+
+    Ok(Redirect::to("/").into_response())
+}
@@ -0,0 +1,213 @@
+use std::time::Duration;
+
+use async_trait::async_trait;
+use sqlx::SqlitePool;
+use time::OffsetDateTime;
+use tokio::sync::broadcast;
+use tower_sessions::{session::{Id, Record}, session_store, SessionStore};
+
+use crate::{session::{PROVIDER, USER_AGENT, USER_ID}, AppResult};
+
+/// Keeps sessions in the `sessions` table so they outlive restarts and can be
+/// listed and revoked from /account/sessions.
+///
+/// Who the session belongs to is copied out of the session data into columns
+/// on every save. Deleting a session, for whatever reason, is announced on
+/// [`SqliteStore::subscribe`] so open sockets can hang up.
+#[derive(Debug, Clone)]
+pub struct SqliteStore {
+    db_pool: SqlitePool,
+    revoked: broadcast::Sender<Id>,
+}
+
+/// A row of the sessions page. `key` is what the page hands out, so the
+/// session id (the cookie) never leaves the server.
+#[derive(Debug, Clone)]
+pub struct ActiveSession {
+    pub key: i64,
+    pub id: Option<Id>,
+    pub provider: Option<String>,
+    pub user_agent: Option<String>,
+    pub created_at: i64,
+    pub last_active: i64,
+}
+
+/// rowid, id, provider, user_agent, created_at, last_active
+type SessionRow = (i64, String, Option<String>, Option<String>, i64, i64);
+
+impl SqliteStore {
+    pub fn new(db_pool: SqlitePool) -> SqliteStore {
+        SqliteStore {
+            db_pool,
+            revoked: broadcast::channel(64).0,
+        }
+    }
+
+    /// Ids of sessions as they are deleted.
+    pub fn subscribe(&self) -> broadcast::Receiver<Id> {
+        self.revoked.subscribe()
+    }
+
+    /// Deletes sessions that ran out, returns how many went. Unlike a revoke
+    /// this isn't announced: socket frames don't keep a session alive, so one
+    /// open on a busy socket runs out all the same.
+    pub async fn delete_expired(&self) -> AppResult<u64> {
+        Ok(
+            sqlx::query("DELETE FROM sessions WHERE expiry_date <= unixepoch()")
+                .execute(&self.db_pool)
+                .await?
+                .rows_affected()
+        )
+    }
+
+    /// A user's live sessions, most recently used first.
+    pub async fn list(&self, user_id: &str) -> AppResult<Vec<ActiveSession>> {
+        let rows: Vec<SessionRow> = sqlx::query_as(
+            "SELECT rowid,id,provider,user_agent,created_at,last_active FROM sessions
+            WHERE user_id=? AND expiry_date > unixepoch()
+            ORDER BY last_active DESC"
+        )
+            .bind(user_id)
+            .fetch_all(&self.db_pool)
+            .await?;
+
+        Ok(
+            rows.into_iter()
+                .map(|(key, id, provider, user_agent, created_at, last_active)| ActiveSession {
+                    key,
+                    id: id.parse().ok(),
+                    provider,
+                    user_agent,
+                    created_at,
+                    last_active,
+                })
+                .collect()
+        )
+    }
+
+    /// Ends one of the user's sessions by its `key`, returns whether it existed.
+    pub async fn revoke(&self, user_id: &str, key: i64) -> AppResult<bool> {
+        let Some((id,)): Option<(String,)> = sqlx::query_as("SELECT id FROM sessions WHERE rowid=? AND user_id=?")
+            .bind(key)
+            .bind(user_id)
+            .fetch_optional(&self.db_pool)
+            .await?
+        else {
+            return Ok(false);
+        };
+
+        self.delete(&parse_id(&id)?).await?;
+        Ok(true)
+    }
+
+    /// Ends every session of the user, returns how many went.
+    pub async fn revoke_all(&self, user_id: &str) -> AppResult<usize> {
+        let ids: Vec<(String,)> = sqlx::query_as("SELECT id FROM sessions WHERE user_id=?")
+            .bind(user_id)
+            .fetch_all(&self.db_pool)
+            .await?;
+
+        for (id,) in &ids {
+            self.delete(&parse_id(id)?).await?;
+        }
+        Ok(ids.len())
+    }
+
+    /// Writes the record, returns whether a row was. Only `create` inserts, so
+    /// a request still holding a revoked session can't bring it back.
+    async fn write(&self, record: &Record, create: bool) -> session_store::Result<bool> {
+        let field = |key: &str| record.data.get(key).and_then(|value| value.as_str()).map(str::to_owned);
+        let data = serde_json::to_string(&record.data)
+            .map_err(|err| session_store::Error::Encode(err.to_string()))?;
+
+        let query = if create {
+            "INSERT INTO sessions (data,expiry_date,user_id,provider,user_agent,id,created_at,last_active)
+            VALUES (?,?,?,?,?,?,unixepoch(),unixepoch())
+            ON CONFLICT(id) DO NOTHING"
+        } else {
+            "UPDATE sessions SET data=?,expiry_date=?,user_id=?,provider=?,user_agent=?,last_active=unixepoch()
+            WHERE id=?"
+        };
+
+        Ok(
+            sqlx::query(query)
+                .bind(data)
+                .bind(record.expiry_date.unix_timestamp())
+                .bind(field(USER_ID))
+                .bind(field(PROVIDER))
+                .bind(field(USER_AGENT))
+                .bind(record.id.to_string())
+                .execute(&self.db_pool)
+                .await
+                .map_err(backend)?
+                .rows_affected() > 0
+        )
+    }
+}
+
+fn parse_id(id: &str) -> AppResult<Id> {
+    Ok(id.parse().map_err(|_| format!("bad session id in store: {id}"))?)
+}
+
+fn backend(err: sqlx::Error) -> session_store::Error {
+    session_store::Error::Backend(err.to_string())
+}
+
+#[async_trait]
+impl SessionStore for SqliteStore {
+    async fn create(&self, record: &mut Record) -> session_store::Result<()> {
+        while !self.write(record, true).await? {
+            record.id = Id::default();
+        }
+        Ok(())
+    }
+
+    async fn save(&self, record: &Record) -> session_store::Result<()> {
+        self.write(record, false).await?;
+        Ok(())
+    }
+
+    async fn load(&self, session_id: &Id) -> session_store::Result<Option<Record>> {
+        let Some((data, expiry_date)): Option<(String, i64)> =
+            sqlx::query_as("SELECT data,expiry_date FROM sessions WHERE id=? AND expiry_date > unixepoch()")
+                .bind(session_id.to_string())
+                .fetch_optional(&self.db_pool)
+                .await
+                .map_err(backend)?
+        else {
+            return Ok(None);
+        };
+
+        Ok(Some(Record {
+            id: *session_id,
+            data: serde_json::from_str(&data)
+                .map_err(|err| session_store::Error::Decode(err.to_string()))?,
+            expiry_date: OffsetDateTime::from_unix_timestamp(expiry_date)
+                .map_err(|err| session_store::Error::Decode(err.to_string()))?,
+        }))
+    }
+
+    async fn delete(&self, session_id: &Id) -> session_store::Result<()> {
+        sqlx::query("DELETE FROM sessions WHERE id=?")
+            .bind(session_id.to_string())
+            .execute(&self.db_pool)
+            .await
+            .map_err(backend)?;
+
+        // nobody listening is fine
+        let _ = self.revoked.send(*session_id);
+        Ok(())
+    }
+}
+
+pub async fn session_reaper(store: SqliteStore) {
+    let mut interval = tokio::time::interval(Duration::from_secs(60));
+    loop {
+        interval.tick().await;
+        match store.delete_expired().await {
+            Ok(0) => (),
+            Ok(n) => println!("reaped {n} sessions"),
+            Err(err) => println!("session reaper: {:?}", err.0),
+        }
+    }
+}
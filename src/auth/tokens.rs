@@ -0,0 +1,292 @@
+use axum::{
+    debug_handler, extract::{OptionalFromRequestParts, Request, State}, http::{header, request::Parts, StatusCode}, middleware::Next, response::{Html, IntoResponse, Redirect, Response}, Form
+};
+use rand::Rng;
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use sqlx::SqlitePool;
+use tower_sessions::{session::Id, Session};
+use uuid::Uuid;
+
+use crate::{include_res, res, rooms::{room_access, RoomAccess}, session::USER_ID, AppError, AppResult};
+
+use super::{create_profile, csrf_token, is_guest};
+
+/// A valid `Authorization: Bearer` token, put in the request extensions by [`bearer_auth`].
+#[derive(Debug, Clone)]
+pub struct ApiToken {
+    /// also the id of the bot user the token acts as
+    pub id: String,
+    pub owner_id: String,
+    pub can_write: bool,
+}
+
+/// Who is asking, from an API token if there is one, otherwise the cookie session.
+///
+/// Only room routes take this, everything about accounts stays cookie only.
+#[derive(Debug, Clone)]
+pub struct Caller {
+    pub user_id: String,
+    /// false for read-only tokens
+    pub can_write: bool,
+    /// the cookie session, to hang up sockets when it's revoked
+    pub session_id: Option<Id>,
+}
+
+impl<S: Send + Sync> OptionalFromRequestParts<S> for Caller {
+    type Rejection = AppError;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Option<Self>, Self::Rejection> {
+        if let Some(token) = parts.extensions.get::<ApiToken>() {
+            return Ok(Some(Caller {
+                user_id: token.id.clone(),
+                can_write: token.can_write,
+                session_id: None,
+            }));
+        }
+
+        let Some(session) = parts.extensions.get::<Session>() else {
+            return Ok(None);
+        };
+        Ok(session.get::<String>(USER_ID).await?.map(|user_id| Caller {
+            user_id,
+            can_write: true,
+            session_id: session.id(),
+        }))
+    }
+}
+
+fn hash(secret: &str) -> String {
+    res::hex(&Sha256::digest(secret.as_bytes()))
+}
+
+/// Longest token name, see [`create_token`].
+const MAX_NAME_LEN: usize = 32;
+
+fn bad_request(reason: &str) -> AppError {
+    (StatusCode::BAD_REQUEST, reason.to_owned()).into_response().into()
+}
+
+/// Makes a token acting as a new bot user, with a profile in each of `rooms`
+/// (all of which the owner must be in). Returns its id and the secret, which
+/// isn't kept anywhere.
+pub async fn create_token(
+    db_pool: &SqlitePool,
+    owner_id: &str,
+    name: &str,
+    rooms: &[Uuid],
+    can_write: bool,
+) -> AppResult<(String, String)> {
+    let name = name.trim();
+    if name.is_empty() {
+        return Err(bad_request("Tokens need a name"));
+    }
+    // the name becomes the bot's alias
+    if name.chars().count() > MAX_NAME_LEN || !name.chars().all(|c| c.is_alphanumeric() || " -_.".contains(c)) {
+        return Err(bad_request("Token names are up to 32 letters, digits, spaces, dashes, dots and underscores"));
+    }
+    if rooms.is_empty() {
+        return Err(bad_request("Pick at least one room for the token"));
+    }
+    if is_guest(db_pool, owner_id).await? {
+        return Err(bad_request("Guests can't make tokens, sign in first"));
+    }
+    for &room_id in rooms {
+        if !matches!(room_access(db_pool, Some(owner_id), room_id).await?, Some(RoomAccess::Member(_))) {
+            return Err(bad_request("Tokens can only get rooms you're in"));
+        }
+    }
+
+    let id = Uuid::now_v7().to_string();
+    let secret = format!("sk_{}", res::hex(&rand::rng().random::<[u8; 32]>()));
+
+    let mut tx = db_pool.begin().await?;
+    sqlx::query("INSERT INTO users (id,bot_owner_id,created_at) VALUES (?,?,unixepoch())")
+        .bind(&id)
+        .bind(owner_id)
+        .execute(&mut *tx)
+        .await?;
+    sqlx::query("INSERT INTO api_tokens (id,owner_id,name,token_hash,can_write,created_at) VALUES (?,?,?,?,?,unixepoch())")
+        .bind(&id)
+        .bind(owner_id)
+        .bind(name)
+        .bind(hash(&secret))
+        .bind(can_write)
+        .execute(&mut *tx)
+        .await?;
+    tx.commit().await?;
+
+    for room_id in rooms {
+        let (profile_id, _) = create_profile(db_pool, &id, &room_id.to_string()).await?;
+        sqlx::query("UPDATE profiles SET alias=? WHERE uuid=?")
+            .bind(format!("{name} [bot]"))
+            .bind(profile_id.to_string())
+            .execute(db_pool)
+            .await?;
+    }
+
+    println!("u/{owner_id} made token {id}");
+    Ok((id, secret))
+}
+
+/// The token for `secret`, if any, noting that it was used.
+pub async fn find_token(db_pool: &SqlitePool, secret: &str) -> AppResult<Option<ApiToken>> {
+    Ok(
+        sqlx::query_as::<_, (String, String, bool)>(
+            "UPDATE api_tokens SET last_used=unixepoch() WHERE token_hash=? RETURNING id,owner_id,can_write"
+        )
+            .bind(hash(secret))
+            .fetch_optional(db_pool)
+            .await?
+            .map(|(id, owner_id, can_write)| ApiToken { id, owner_id, can_write })
+    )
+}
+
+/// Stops a token working. Its bot profiles stay, so what it posted does too.
+pub async fn delete_token(db_pool: &SqlitePool, owner_id: &str, id: &str) -> AppResult<bool> {
+    Ok(
+        sqlx::query("DELETE FROM api_tokens WHERE id=? AND owner_id=?")
+            .bind(id)
+            .bind(owner_id)
+            .execute(db_pool)
+            .await?
+            .rows_affected() > 0
+    )
+}
+
+/// Middleware checking `Authorization: Bearer` tokens. Requests without one
+/// pass through untouched, ones with a bad one stop here.
+pub async fn bearer_auth(State(db_pool): State<SqlitePool>, mut request: Request, next: Next) -> AppResult<Response> {
+    let Some(authorization) = request.headers().get(header::AUTHORIZATION) else {
+        return Ok(next.run(request).await);
+    };
+
+    let token = match authorization.to_str().ok().and_then(|value| value.strip_prefix("Bearer ")) {
+        Some(secret) => find_token(&db_pool, secret.trim()).await?,
+        None => None,
+    };
+    let Some(token) = token else {
+        return Ok((
+            StatusCode::UNAUTHORIZED,
+            [(header::WWW_AUTHENTICATE, "Bearer")],
+            "Invalid API token"
+        ).into_response());
+    };
+
+    request.extensions_mut().insert(token);
+    Ok(next.run(request).await)
+}
+
+#[derive(Deserialize)]
+pub(crate) struct DeleteTokenQuery {
+    id: String,
+}
+
+#[debug_handler]
+pub(crate) async fn tokens(
+    State(db_pool): State<SqlitePool>,
+    session: Session,
+) -> AppResult<Response> {
+    let Some(user_id) = session.get::<String>(USER_ID).await? else {
+        return Ok(Redirect::to("/login?return_url=/account/tokens").into_response());
+    };
+    tokens_page(&db_pool, &session, &user_id, None).await
+}
+
+async fn tokens_page(db_pool: &SqlitePool, session: &Session, user_id: &str, created: Option<&str>) -> AppResult<Response> {
+    let csrf_token = csrf_token(session).await?;
+
+    let tokens: Vec<(String, String, bool, i64, Option<i64>)> = sqlx::query_as(
+        "SELECT id,name,can_write,created_at,last_used FROM api_tokens WHERE owner_id=? ORDER BY created_at DESC"
+    )
+        .bind(user_id)
+        .fetch_all(db_pool)
+        .await?;
+
+    let mut token_items = String::new();
+    for (id, name, can_write, created_at, last_used) in tokens {
+        let (rooms,): (String,) = sqlx::query_as(
+            "SELECT coalesce(group_concat(rooms.name, ', '), '') FROM profiles JOIN rooms ON rooms.uuid=profiles.room_id WHERE profiles.user_id=?"
+        )
+            .bind(&id)
+            .fetch_one(db_pool)
+            .await?;
+
+        token_items += &include_res!(str, "pages/auth/token_item.html")
+            .replace("{csrf_token}", &csrf_token)
+            .replace("{id}", &id)
+            .replace("{name}", &res::escape(&name))
+            .replace("{access}", if can_write { "read & write" } else { "read only" })
+            .replace("{rooms}", &res::escape(&rooms))
+            .replace("{created_at}", &res::ago(created_at))
+            .replace("{last_used}", &last_used.map(res::ago).unwrap_or("never".to_owned()));
+    }
+
+    let rooms: Vec<(String, String)> = sqlx::query_as(
+        "SELECT rooms.uuid,rooms.name FROM profiles JOIN rooms ON rooms.uuid=profiles.room_id WHERE profiles.user_id=? ORDER BY rooms.name"
+    )
+        .bind(user_id)
+        .fetch_all(db_pool)
+        .await?;
+
+    let mut room_choices = String::new();
+    for (room_id, name) in rooms {
+        room_choices += &format!(
+            "<label><input name=\"room\" type=\"checkbox\" value=\"{room_id}\"/>{}</label><br>\n",
+            res::escape(&name),
+        );
+    }
+
+    Ok(Html(
+        include_res!(str, "pages/auth/tokens.html")
+            .replace("{csrf_token}", &csrf_token)
+            .replace("{created}", if created.is_some() { "" } else { "display: none;" })
+            .replace("{secret}", created.unwrap_or_default())
+            .replace("{tokens}", &token_items)
+            .replace("{room_choices}", &room_choices)
+    ).into_response())
+}
+
+/// Rooms come as repeated `room` fields, which a struct can't take.
+#[debug_handler]
+pub(crate) async fn create(
+    State(db_pool): State<SqlitePool>,
+    session: Session,
+    Form(fields): Form<Vec<(String, String)>>,
+) -> AppResult<Response> {
+    let Some(user_id) = session.get::<String>(USER_ID).await? else {
+        return res::sorry("tokens");
+    };
+
+    let field = |key: &str| fields.iter().find(|(k, _)| k == key).map(|(_, v)| v.as_str());
+    let rooms = fields.iter()
+        .filter(|(key, _)| key == "room")
+        .map(|(_, room_id)| Uuid::parse_str(room_id))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let (_, secret) = create_token(
+        &db_pool,
+        &user_id,
+        field("name").unwrap_or_default(),
+        &rooms,
+        field("access") == Some("write"),
+    ).await?;
+
+    tokens_page(&db_pool, &session, &user_id, Some(&secret)).await
+}
+
+#[debug_handler]
+pub(crate) async fn delete(
+    State(db_pool): State<SqlitePool>,
+    session: Session,
+    Form(DeleteTokenQuery { id }): Form<DeleteTokenQuery>,
+) -> AppResult<Response> {
+    let Some(user_id) = session.get::<String>(USER_ID).await? else {
+        return res::sorry("tokens");
+    };
+
+    if !delete_token(&db_pool, &user_id, &id).await? {
+        return res::sorry("token");
+    }
+    Ok(Redirect::to("/account/tokens").into_response())
+}
@@ -0,0 +1,126 @@
+use sqlx::SqlitePool;
+use uuid::Uuid;
+
+use crate::AppResult;
+
+/// Maps `provider` + `subject` to our own stable user id, creating the user
+/// on first sign in.
+///
+/// New users get a fresh id unless `new_id` is given; Firebase passes its
+/// `localId` there so users from before the local table keep their profiles.
+pub async fn resolve_user(
+    db_pool: &SqlitePool,
+    provider: &str,
+    subject: &str,
+    new_id: Option<&str>,
+) -> AppResult<String> {
+    if let Some((user_id,)) = sqlx::query_as::<_, (String,)>("SELECT user_id FROM identities WHERE provider=? AND subject=?")
+        .bind(provider)
+        .bind(subject)
+        .fetch_optional(db_pool)
+        .await? {
+        return Ok(user_id);
+    }
+
+    let user_id = new_id.map(str::to_owned).unwrap_or(Uuid::now_v7().to_string());
+    let mut tx = db_pool.begin().await?;
+    sqlx::query("INSERT OR IGNORE INTO users (id,created_at) VALUES (?,unixepoch())")
+        .bind(&user_id)
+        .execute(&mut *tx)
+        .await?;
+    sqlx::query("INSERT INTO identities (user_id,provider,subject) VALUES (?,?,?)")
+        .bind(&user_id)
+        .bind(provider)
+        .bind(subject)
+        .execute(&mut *tx)
+        .await?;
+    tx.commit().await?;
+
+    println!("new user u/{user_id} from {provider}");
+    Ok(user_id)
+}
+
+/// Attaches another provider's identity to an existing user.
+pub async fn link_identity(
+    db_pool: &SqlitePool,
+    user_id: &str,
+    provider: &str,
+    subject: &str,
+) -> AppResult<()> {
+    if let Some((owner,)) = sqlx::query_as::<_, (String,)>("SELECT user_id FROM identities WHERE provider=? AND subject=?")
+        .bind(provider)
+        .bind(subject)
+        .fetch_optional(db_pool)
+        .await? {
+        if owner == user_id {
+            return Ok(());
+        }
+        return Err(format!("this {provider} account is already linked to someone else"))?;
+    }
+
+    sqlx::query("INSERT INTO identities (user_id,provider,subject) VALUES (?,?,?)")
+        .bind(user_id)
+        .bind(provider)
+        .bind(subject)
+        .execute(db_pool)
+        .await?;
+    Ok(())
+}
+
+/// Remembers what the identity goes by at its provider, the only part of it a
+/// reveal shows. The subject may be our own user id (Firebase), never show it.
+pub async fn set_identity_handle(
+    db_pool: &SqlitePool,
+    provider: &str,
+    subject: &str,
+    handle: Option<&str>,
+) -> AppResult<()> {
+    sqlx::query("UPDATE identities SET handle=? WHERE provider=? AND subject=?")
+        .bind(handle)
+        .bind(provider)
+        .bind(subject)
+        .execute(db_pool)
+        .await?;
+    Ok(())
+}
+
+/// Detaches an identity, refusing to remove a user's last way to sign in.
+pub async fn unlink_identity(
+    db_pool: &SqlitePool,
+    user_id: &str,
+    provider: &str,
+    subject: &str,
+) -> AppResult<()> {
+    let mut tx = db_pool.begin().await?;
+    let (count,): (i64,) = sqlx::query_as("SELECT count(*) FROM identities WHERE user_id=?")
+        .bind(user_id)
+        .fetch_one(&mut *tx)
+        .await?;
+    if count <= 1 {
+        return Err("can't unlink your last sign in method")?;
+    }
+
+    let unlinked = sqlx::query("DELETE FROM identities WHERE user_id=? AND provider=? AND subject=?")
+        .bind(user_id)
+        .bind(provider)
+        .bind(subject)
+        .execute(&mut *tx)
+        .await?
+        .rows_affected();
+    if unlinked == 0 {
+        return Err("no such linked account")?;
+    }
+
+    tx.commit().await?;
+    Ok(())
+}
+
+/// `(provider, subject)` for every identity linked to the user
+pub async fn identities(db_pool: &SqlitePool, user_id: &str) -> AppResult<Vec<(String, String)>> {
+    Ok(
+        sqlx::query_as("SELECT provider,subject FROM identities WHERE user_id=? ORDER BY provider")
+            .bind(user_id)
+            .fetch_all(db_pool)
+            .await?
+    )
+}
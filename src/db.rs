@@ -1,3 +1,6 @@
+// the schema as Rust, for reference, nothing constructs these yet
+#![allow(dead_code)]
+
 use uuid::Uuid;
 
 struct Profile {
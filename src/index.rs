@@ -1,15 +1,16 @@
 use axum::{debug_handler, extract::State, response::{Html, IntoResponse, Redirect, Response}};
 use sqlx::SqlitePool;
 use tower_sessions::Session;
+use uuid::Uuid;
 
-use crate::{include_res, AppResult};
+use crate::{auth, include_res, res, rooms, session::USER_ID, AppResult};
 
 #[debug_handler]
 pub async fn index(
     State(db_pool): State<SqlitePool>,
     session: Session
 ) -> AppResult<Response> {
-    let Some(user_id) = session.get::<String>("user_id").await? else {
+    let Some(user_id) = session.get::<String>(USER_ID).await? else {
         return Ok(
             Redirect::to("/login")
                 .into_response()
@@ -17,24 +18,26 @@ pub async fn index(
     };
 
     let mut room_items = String::new();
-    let room_ids = sqlx::query_as::<_, (String,)>("SELECT room_id,alias FROM profiles WHERE user_id=?")
+    let rooms = sqlx::query_as::<_, (String, String, String, String)>(
+        "SELECT profiles.room_id,profiles.uuid,rooms.name,profiles.alias FROM profiles JOIN rooms ON rooms.uuid=profiles.room_id WHERE profiles.user_id=?"
+    )
         .bind(&user_id)
         .fetch_all(&db_pool)
         .await?;
-    for (room_id,) in room_ids {
-        let (name,): (String,) = sqlx::query_as("SELECT name FROM rooms WHERE uuid=?")
-            .bind(&room_id)
-            .fetch_one(&db_pool)
-            .await?;
-
+    for (room_id, profile_id, name, alias) in rooms {
+        let unread = rooms::unread_count(&db_pool, Uuid::parse_str(&profile_id)?).await?;
         room_items += &include_res!(str, "pages/index/room_item.html")
             .replace("{id}", &room_id)
-            .replace("{name}", &name);
+            .replace("{name}", &res::escape(&name))
+            .replace("{alias}", &res::escape(&alias))
+            .replace("{unread}", &if unread > 0 { format!("({unread} unread)") } else { String::new() });
     }
 
     Ok(
         Html(
             include_res!(str, "/pages/index/index.html")
+                .replace("{csrf_token}", &auth::csrf_token(&session).await?)
+                .replace("{admin}", if rooms::is_admin(&db_pool, &user_id).await? { "" } else { "display: none;" })
                 .replace("{room_items}", &room_items)
         ).into_response()
     )
@@ -1,3 +1,4 @@
+pub mod api;
 pub mod auth;
 pub mod db;
 pub mod index;
@@ -18,7 +19,12 @@ use tokio::sync::broadcast;
 pub struct AppState {
     pub db_pool: SqlitePool,
     pub clients: auth::Clients,
-    pub tx: broadcast::Sender<String>,
+    pub guests: auth::GuestConfig,
+    pub origins: auth::AllowedOrigins,
+    pub sessions: auth::SqliteStore,
+    pub presence: rooms::Presence,
+    pub limits: rooms::Limits,
+    pub tx: broadcast::Sender<rooms::RoomEvent>,
 }
 
 pub trait GetField {
@@ -46,8 +52,9 @@ impl GetField for serde_json::Value {
 
 pub type AppResult<T> = Result<T, AppError>;
 
+/// The response is boxed, results carry errors around a lot more than they hit them.
 #[derive(Debug)]
-pub struct AppError(pub anyhow::Error, pub Response);
+pub struct AppError(pub anyhow::Error, pub Box<Response>);
 
 impl From<anyhow::Error> for AppError {
     fn from(error: anyhow::Error) -> Self {
@@ -55,19 +62,19 @@ impl From<anyhow::Error> for AppError {
             StatusCode::INTERNAL_SERVER_ERROR,
             format!("{}\n\n{}", error, error.backtrace()),
         ).into_response();
-        Self(error,response)
+        Self(error, Box::new(response))
     }
 }
 
 impl From<Response> for AppError {
     fn from(response: Response) -> Self {
-        Self(anyhow::Error::msg("[error sent as response]"), response)
+        Self(anyhow::Error::msg("[error sent as response]"), Box::new(response))
     }
 }
 
 impl IntoResponse for AppError {
     fn into_response(self) -> Response {
-        self.1
+        *self.1
     }
 }
 
@@ -96,10 +103,16 @@ macro_rules! apperr_impl {
 apperr_impl!(serde_json::Error);
 apperr_impl!(sqlx::Error);
 apperr_impl!(tower_sessions::session::Error);
+apperr_impl!(tower_sessions::session_store::Error);
 apperr_impl!(axum::Error);
 apperr_impl!(reqwest::Error);
 apperr_impl!(uuid::Error);
 apperr_impl!(dotenv::Error);
+apperr_impl!(openidconnect::url::ParseError);
+apperr_impl!(openidconnect::ConfigurationError);
+apperr_impl!(openidconnect::ClaimsVerificationError);
+apperr_impl!(openidconnect::SignatureVerificationError);
+apperr_impl!(openidconnect::SigningError);
 
 // bc rust macros fucking suck
 // apperr_impl!(oauth2::RequestTokenError<E: core::error::Error + Send + Sync + 'static, R: oauth2::ErrorResponse + Send + Sync + 'static>);
@@ -110,6 +123,12 @@ impl<E: core::error::Error + Send + Sync + 'static, R: oauth2::ErrorResponse + S
     }
 }
 
+impl<E: core::error::Error + Send + Sync + 'static> From<openidconnect::DiscoveryError<E>> for AppError {
+    fn from(err: openidconnect::DiscoveryError<E>) -> Self {
+        Self::from(anyhow::Error::from(err))
+    }
+}
+
 pub struct Markdown<T>(pub T);
 
 impl<T> IntoResponse for Markdown<T>
@@ -119,7 +138,7 @@ where
     fn into_response(self) -> axum::response::Response {
         use pulldown_cmark::{Event, Parser, Options};
 
-        let parser = Parser::new_ext(&*self.0, Options::ENABLE_MATH)
+        let parser = Parser::new_ext(&self.0, Options::ENABLE_MATH)
             .map(|event| match event {
             Event::InlineMath(name) => Event::InlineMath(name),
             _ => event,
@@ -1,31 +1,42 @@
-use std::str::FromStr;
-use silentkisses::{auth, include_res, index, profiles, rooms, AppState, Markdown};
+use std::{net::SocketAddr, str::FromStr};
+use silentkisses::{api, auth, include_res, index, profiles, rooms, AppState, Markdown};
 use axum::{
-    debug_handler, extract::Request, response::IntoResponse, routing::get, Router
+    debug_handler, extract::Request, middleware, response::IntoResponse, routing::get, Router
 };
 use sqlx::sqlite::SqlitePoolOptions;
 use tokio::sync::broadcast;
-use tower_sessions::{cookie::SameSite, Expiry, MemoryStore, SessionManagerLayer};
+use tower_sessions::{cookie::SameSite, Expiry, SessionManagerLayer};
 
 #[tokio::main]
 async fn main() {
-    let session_store = MemoryStore::default();
-    let session_layer = SessionManagerLayer::new(session_store)
-        .with_secure(false)
-        .with_same_site(SameSite::Lax)
-        .with_expiry(Expiry::OnInactivity(time::Duration::minutes(5)));
-
     let db_pool = SqlitePoolOptions::new()
         .max_connections(16)
         .connect(dotenv::var("DATABASE_URL").unwrap().as_str())
         .await.unwrap();
 
+    let session_store = auth::SqliteStore::new(db_pool.clone());
+    let session_layer = SessionManagerLayer::new(session_store.clone())
+        .with_secure(false)
+        .with_same_site(SameSite::Lax)
+        .with_expiry(Expiry::OnInactivity(time::Duration::minutes(5)))
+        // keeps last activity on the sessions page (and the inactivity expiry) honest
+        .with_always_save(true);
+    tokio::spawn(auth::session_reaper(session_store.clone()));
+
     let clients = auth::Clients::from_json(serde_json::Value::from_str(include_str!("../client_secret.json")).unwrap()).unwrap();
+    tokio::spawn(rooms::room_reaper(db_pool.clone()));
+
     let app_state = AppState {
         db_pool,
         clients,
+        guests: auth::GuestConfig::from_env(),
+        origins: auth::AllowedOrigins::from_env(),
+        sessions: session_store,
+        presence: rooms::Presence::default(),
+        limits: rooms::Limits::from_env(),
         tx: broadcast::channel(69).0
     };
+    tokio::spawn(rooms::message_sweeper(app_state.db_pool.clone(), app_state.tx.clone()));
 
     let app = Router::new()
         .route("/", get(index::index))
@@ -35,12 +46,15 @@ async fn main() {
         .merge(auth::router())
         .nest("/r", rooms::router())
         .nest("/p", profiles::router())
+        .nest("/api/v1", api::router())
 
-        .with_state(app_state)
+        .with_state(app_state.clone())
+        .layer(middleware::from_fn(auth::verify_csrf))
+        .layer(middleware::from_fn_with_state(app_state, auth::bearer_auth))
         .layer(session_layer);
     let listener = tokio::net::TcpListener::bind("0.0.0.0:8080").await.unwrap();
     println!("running on port http://localhost:8080");
-    axum::serve(listener, app).await.unwrap();
+    axum::serve(listener, app.into_make_service_with_connect_info::<SocketAddr>()).await.unwrap();
 }
 
 #[debug_handler]
@@ -3,7 +3,7 @@ use sqlx::SqlitePool;
 use tower_sessions::Session;
 use uuid::Uuid;
 
-use crate::{include_res, res, session::USER_ID, AppResult};
+use crate::{auth, include_res, res, rooms::{moderation_html, room_access}, session::USER_ID, AppResult};
 
 #[debug_handler]
 pub(crate) async fn profile(
@@ -17,29 +17,38 @@ pub(crate) async fn profile(
         return sorry;
     };
 
-    let (room_id, handle, alias): (String, String, String) = sqlx::query_as("SELECT room_id,handle,alias FROM profiles WHERE uuid=?")
-        .bind(profile_id.to_string())
-        .fetch_one(&db_pool)
-        .await?;
+    let Some((room_id, handle, alias)): Option<(String, String, String)> =
+        sqlx::query_as("SELECT room_id,handle,alias FROM profiles WHERE uuid=?")
+            .bind(profile_id.to_string())
+            .fetch_optional(&db_pool)
+            .await?
+    else {
+        return sorry;
+    };
 
-    if sqlx::query("SELECT 1 FROM profiles WHERE user_id=? AND room_id=?")
-        .bind(&user_id)
-        .bind(&room_id)
-        .fetch_optional(&db_pool)
-        .await?.is_none() {
+    let room_uuid = Uuid::parse_str(&room_id)?;
+    let Some(access) = room_access(&db_pool, Some(&user_id), room_uuid).await? else {
         return sorry;
-    }
+    };
+    // only members can DM, and not themselves
+    let can_dm = access.profile_id().is_some_and(|own_id| own_id != profile_id);
 
     let (room_name,): (String,) = sqlx::query_as("SELECT name FROM rooms WHERE uuid=?")
         .bind(&room_id)
         .fetch_one(&db_pool)
         .await?;
 
+    let moderation = moderation_html(&db_pool, &user_id, room_uuid, profile_id, &auth::csrf_token(&session).await?).await?;
+
     Ok(Html(
         include_res!(str, "pages/profiles/profile.html")
         .replace("{alias}", &alias)
         .replace("{handle}", &handle)
         .replace("{room_id}", &room_id)
         .replace("{room_name}", &room_name)
+        .replace("{profile_id}", &profile_id.to_string())
+        .replace("{dm}", if can_dm { "" } else { "display: none;" })
+        .replace("{report}", if can_dm { "" } else { "display: none;" })
+        .replace("{moderation}", &moderation)
     ).into_response())
 }
\ No newline at end of file
@@ -24,4 +24,32 @@ pub fn sorry(service: &str) -> AppResult<Response> {
             .replace("{service}", service)
         )
     ).into_response().into())
-}
\ No newline at end of file
+}
+
+/// Makes user text safe to put in a page, as text or in an attribute.
+pub fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&#39;")
+        // or it could fill in a template's placeholders
+        .replace('{', "&#123;")
+        .replace('}', "&#125;")
+}
+
+/// `1712345678` -> "3h ago", relative to now
+pub fn ago(unix_secs: i64) -> String {
+    let secs = (time::OffsetDateTime::now_utc().unix_timestamp() - unix_secs).max(0);
+    match secs {
+        0..60 => "just now".to_owned(),
+        60..3600 => format!("{}m ago", secs / 60),
+        3600..86400 => format!("{}h ago", secs / 3600),
+        _ => format!("{}d ago", secs / 86400),
+    }
+}
+
+/// Lowercase hex, for tokens and hashes.
+pub fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+}
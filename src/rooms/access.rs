@@ -0,0 +1,211 @@
+use std::time::Duration;
+
+use axum::http::StatusCode;
+use sqlx::{sqlite::SqliteRow, FromRow, SqlitePool};
+use tower_sessions::Session;
+use uuid::Uuid;
+
+use crate::{auth::{self, guest_can_join, guest_can_post, is_guest, GuestConfig}, session::USER_ID, AppResult};
+
+use super::{limits::too_soon, msg::refuse, Limits};
+
+/// What a user may do in a room, as decided by [`room_access`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RoomAccess {
+    /// user already has a profile in the room
+    Member(Uuid),
+    /// room is public, user has no profile (yet)
+    Visitor,
+}
+
+impl RoomAccess {
+    pub fn profile_id(&self) -> Option<Uuid> {
+        match self {
+            RoomAccess::Member(profile_id) => Some(*profile_id),
+            RoomAccess::Visitor => None,
+        }
+    }
+}
+
+/// The one place that decides who may see a room.
+///
+/// Returns `None` if the room doesn't exist or the user isn't allowed in.
+/// Private rooms are members only (never guests); public rooms let anyone look.
+/// Bots only get their own rooms. Banned users get nothing.
+pub async fn room_access(
+    db_pool: &SqlitePool,
+    user_id: Option<&str>,
+    room_id: Uuid,
+) -> AppResult<Option<RoomAccess>> {
+    let Some((is_public,)): Option<(bool,)> =
+        sqlx::query_as("SELECT is_public FROM rooms WHERE uuid=?")
+            .bind(room_id.to_string())
+            .fetch_optional(db_pool)
+            .await?
+    else {
+        return Ok(None);
+    };
+
+    if let Some(user_id) = user_id && is_banned(db_pool, user_id, room_id).await? {
+        return Ok(None);
+    }
+
+    let profile_id = match user_id {
+        Some(user_id) => sqlx::query_as::<_, (String,)>("SELECT uuid FROM profiles WHERE user_id=? AND room_id=?")
+            .bind(user_id)
+            .bind(room_id.to_string())
+            .fetch_optional(db_pool)
+            .await?,
+        None => None,
+    };
+
+    // guests only ever get public rooms, even ones that went private after they joined
+    if !is_public && profile_id.is_some() && is_guest(db_pool, user_id.unwrap_or_default()).await? {
+        return Ok(None);
+    }
+
+    // bots (API tokens) only ever get the rooms they were made for, and only
+    // while whoever made them is still in there too
+    if let Some(user_id) = user_id {
+        let bot_owner: Option<(Option<String>,)> = sqlx::query_as("SELECT bot_owner_id FROM users WHERE id=?")
+            .bind(user_id)
+            .fetch_optional(db_pool)
+            .await?;
+        if let Some((Some(owner_id),)) = bot_owner {
+            let owner_is_member = sqlx::query("SELECT 1 FROM profiles WHERE user_id=? AND room_id=?")
+                .bind(&owner_id)
+                .bind(room_id.to_string())
+                .fetch_optional(db_pool)
+                .await?
+                .is_some() && !is_banned(db_pool, &owner_id, room_id).await?;
+            if profile_id.is_none() || !owner_is_member {
+                return Ok(None);
+            }
+        }
+    }
+
+    Ok(match profile_id {
+        Some((profile_id,)) => Some(RoomAccess::Member(Uuid::parse_str(&profile_id)?)),
+        None if is_public => Some(RoomAccess::Visitor),
+        None => None,
+    })
+}
+
+/// The user's profile in a room they may see, made on the spot for visitors.
+/// Guests who already joined as many rooms as they may are refused.
+pub(crate) async fn member_profile(
+    db_pool: &SqlitePool,
+    guests: &GuestConfig,
+    user_id: &str,
+    room_id: Uuid,
+    access: RoomAccess,
+) -> AppResult<Uuid> {
+    match access {
+        RoomAccess::Member(profile_id) => Ok(profile_id),
+        RoomAccess::Visitor if guest_can_join(db_pool, guests, user_id).await? =>
+            Ok(auth::create_profile(db_pool, user_id, &room_id.to_string()).await?.0),
+        RoomAccess::Visitor => Err(refuse(StatusCode::FORBIDDEN, "Guests can only join a few rooms")),
+    }
+}
+
+/// Refuses guests over their messages a minute and profiles sending too fast,
+/// see [`Limits::take`]. Takes one message's worth when it doesn't.
+pub(crate) async fn can_send(
+    db_pool: &SqlitePool,
+    guests: &GuestConfig,
+    limits: &Limits,
+    user_id: &str,
+    profile_id: Uuid,
+) -> AppResult<()> {
+    if !guest_can_post(db_pool, guests, user_id).await? {
+        return Err(too_soon(Duration::from_secs(60), "Guests can only send a few messages a minute"));
+    }
+    limits.take(profile_id)
+}
+
+/// [`member_profile`] then [`can_send`], for sending one message.
+pub(crate) async fn sender_profile(
+    db_pool: &SqlitePool,
+    guests: &GuestConfig,
+    limits: &Limits,
+    user_id: &str,
+    room_id: Uuid,
+    access: RoomAccess,
+) -> AppResult<Uuid> {
+    let profile_id = member_profile(db_pool, guests, user_id, room_id, access).await?;
+    can_send(db_pool, guests, limits, user_id, profile_id).await?;
+    Ok(profile_id)
+}
+
+/// Only the user who made the room may change its settings.
+pub async fn is_room_owner(
+    db_pool: &SqlitePool,
+    user_id: &str,
+    room_id: Uuid,
+) -> AppResult<bool> {
+    Ok(
+        sqlx::query("SELECT 1 FROM rooms WHERE uuid=? AND owner_id=?")
+            .bind(room_id.to_string())
+            .bind(user_id)
+            .fetch_optional(db_pool)
+            .await?
+            .is_some()
+    )
+}
+
+/// The room's `columns`, if the signed in user owns it. For the owner's pages.
+pub(super) async fn owned_room<T>(db_pool: &SqlitePool, session: &Session, room_id: Uuid, columns: &str) -> AppResult<Option<T>>
+where
+    T: for<'r> FromRow<'r, SqliteRow> + Send + Unpin,
+{
+    let Some(user_id) = session.get::<String>(USER_ID).await? else {
+        return Ok(None);
+    };
+
+    if !is_room_owner(db_pool, &user_id, room_id).await? {
+        return Ok(None);
+    }
+
+    Ok(Some(
+        sqlx::query_as(&format!("SELECT {columns} FROM rooms WHERE uuid=?"))
+            .bind(room_id.to_string())
+            .fetch_one(db_pool)
+            .await?
+    ))
+}
+
+/// Bans are per user, whichever profile they had or would get.
+pub async fn is_banned(
+    db_pool: &SqlitePool,
+    user_id: &str,
+    room_id: Uuid,
+) -> AppResult<bool> {
+    Ok(
+        sqlx::query("SELECT 1 FROM room_bans WHERE room_id=? AND user_id=?")
+            .bind(room_id.to_string())
+            .bind(user_id)
+            .fetch_optional(db_pool)
+            .await?
+            .is_some()
+    )
+}
+
+/// Instance admins, set by hand in the database.
+pub async fn is_admin(db_pool: &SqlitePool, user_id: &str) -> AppResult<bool> {
+    Ok(
+        sqlx::query("SELECT 1 FROM users WHERE id=? AND is_admin")
+            .bind(user_id)
+            .fetch_optional(db_pool)
+            .await?
+            .is_some()
+    )
+}
+
+/// Room owners moderate their own rooms, admins any room.
+pub async fn can_moderate(
+    db_pool: &SqlitePool,
+    user_id: &str,
+    room_id: Uuid,
+) -> AppResult<bool> {
+    Ok(is_room_owner(db_pool, user_id, room_id).await? || is_admin(db_pool, user_id).await?)
+}
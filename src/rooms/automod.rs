@@ -0,0 +1,600 @@
+use std::sync::LazyLock;
+
+use axum::{debug_handler, extract::{Path, State}, http::StatusCode, response::{Html, IntoResponse, Redirect, Response}, Form};
+use regex::{Regex, RegexBuilder};
+use serde::Deserialize;
+use sqlx::SqlitePool;
+use tokio::sync::broadcast;
+use tower_sessions::Session;
+use uuid::Uuid;
+
+use crate::{auth, include_res, res, session::USER_ID, AppResult, AppState};
+
+use super::{can_moderate, is_banned, is_muted, msg::{self, refuse}, RoomEvent};
+
+/// What the sender hears when a rule holds their message.
+pub(crate) const HELD: &str = "Your message is waiting for a moderator to approve it";
+
+/// Room-supplied patterns don't get to make huge automata.
+const REGEX_SIZE_LIMIT: usize = 1 << 16;
+
+static LINK: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"(?i)\bhttps?://(?:[^\s/?#@]*@)?([^\s/?#:]+)[^\s]*").unwrap());
+static MENTION: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"(?:^|[^\w@])@[0-9a-z_]+").unwrap());
+
+/// What a rule looks at.
+#[derive(Debug, Clone, Copy, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum RuleKind {
+    /// a word or phrase, any case
+    Word,
+    Regex,
+    /// links are only allowed to these domains (space separated)
+    LinkAllow,
+    /// links to these domains aren't
+    LinkDeny,
+    /// characters
+    MaxLength,
+    /// `@handle`s in one message
+    MaxMentions,
+}
+
+impl RuleKind {
+    pub const ALL: [RuleKind; 6] = [RuleKind::Word, RuleKind::Regex, RuleKind::LinkAllow, RuleKind::LinkDeny, RuleKind::MaxLength, RuleKind::MaxMentions];
+
+    pub fn from_slug(slug: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|kind| kind.slug() == slug)
+    }
+
+    pub fn slug(self) -> &'static str {
+        use RuleKind::*;
+        match self {
+            Word => "word",
+            Regex => "regex",
+            LinkAllow => "link_allow",
+            LinkDeny => "link_deny",
+            MaxLength => "max_length",
+            MaxMentions => "max_mentions",
+        }
+    }
+
+    pub fn label(self) -> &'static str {
+        use RuleKind::*;
+        match self {
+            Word => "Blocked word",
+            Regex => "Pattern (regex)",
+            LinkAllow => "Only links to",
+            LinkDeny => "No links to",
+            MaxLength => "Longest message",
+            MaxMentions => "Most mentions",
+        }
+    }
+
+    /// Limits can't be masked, there's nothing in particular to hide.
+    fn can_mask(self) -> bool {
+        !matches!(self, RuleKind::MaxLength | RuleKind::MaxMentions)
+    }
+}
+
+/// What happens to a message a rule caught.
+#[derive(Debug, Clone, Copy, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum RuleAction {
+    Reject,
+    /// kept back until a moderator approves it
+    Hold,
+    /// what the rule caught is replaced with asterisks
+    Mask,
+}
+
+impl RuleAction {
+    pub const ALL: [RuleAction; 3] = [RuleAction::Reject, RuleAction::Hold, RuleAction::Mask];
+
+    pub fn from_slug(slug: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|action| action.slug() == slug)
+    }
+
+    pub fn slug(self) -> &'static str {
+        use RuleAction::*;
+        match self {
+            Reject => "reject",
+            Hold => "hold",
+            Mask => "mask",
+        }
+    }
+
+    pub fn label(self) -> &'static str {
+        use RuleAction::*;
+        match self {
+            Reject => "Refuse the message",
+            Hold => "Hold it for a moderator",
+            Mask => "Replace it with asterisks",
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Rule {
+    pub id: Uuid,
+    pub kind: RuleKind,
+    pub pattern: String,
+    pub action: RuleAction,
+}
+
+/// What the room's rules make of a message.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Verdict {
+    /// post it, masked if a rule said so
+    Pass(String),
+    /// keep it back for a moderator, caught by this rule, masked if a rule said so
+    Hold(Uuid, String),
+    /// refuse it, saying why
+    Reject(String),
+}
+
+fn domains(pattern: &str) -> Vec<String> {
+    pattern.split(|c: char| c.is_whitespace() || c == ',')
+        .map(|domain| domain.trim().trim_start_matches("*.").to_lowercase())
+        .filter(|domain| !domain.is_empty())
+        .collect()
+}
+
+fn on_list(host: &str, domains: &[String]) -> bool {
+    let host = host.to_lowercase();
+    domains.iter().any(|domain| host == *domain || host.ends_with(&format!(".{domain}")))
+}
+
+fn limit(pattern: &str) -> Option<usize> {
+    pattern.trim().parse().ok()
+}
+
+fn word_regex(word: &str) -> Option<Regex> {
+    RegexBuilder::new(&format!(r"\b{}\b", regex::escape(word.trim())))
+        .case_insensitive(true)
+        .build()
+        .ok()
+}
+
+fn user_regex(pattern: &str) -> Result<Regex, regex::Error> {
+    RegexBuilder::new(pattern)
+        .size_limit(REGEX_SIZE_LIMIT)
+        .build()
+}
+
+/// The byte ranges of `content` a rule caught, or `None` if it didn't. Limit
+/// rules catch the whole message.
+fn caught(rule: &Rule, content: &str) -> Option<Vec<(usize, usize)>> {
+    let ranges: Vec<(usize, usize)> = match rule.kind {
+        RuleKind::Word => word_regex(&rule.pattern)?
+            .find_iter(content)
+            .map(|found| (found.start(), found.end()))
+            .collect(),
+        RuleKind::Regex => user_regex(&rule.pattern).ok()?
+            .find_iter(content)
+            .filter(|found| !found.is_empty())
+            .map(|found| (found.start(), found.end()))
+            .collect(),
+        RuleKind::LinkAllow | RuleKind::LinkDeny => {
+            let domains = domains(&rule.pattern);
+            LINK.captures_iter(content)
+                .filter(|link| on_list(&link[1], &domains) == (rule.kind == RuleKind::LinkDeny))
+                .map(|link| { let link = link.get(0).unwrap(); (link.start(), link.end()) })
+                .collect()
+        },
+        RuleKind::MaxLength => match content.chars().count() > limit(&rule.pattern)? {
+            true => vec![(0, content.len())],
+            false => Vec::new(),
+        },
+        RuleKind::MaxMentions => match MENTION.find_iter(content).count() > limit(&rule.pattern)? {
+            true => vec![(0, content.len())],
+            false => Vec::new(),
+        },
+    };
+    (!ranges.is_empty()).then_some(ranges)
+}
+
+fn mask(content: &str, ranges: &[(usize, usize)]) -> String {
+    let mut masked = String::new();
+    let mut at = 0;
+    for &(start, end) in ranges {
+        masked += &content[at..start];
+        masked += &"*".repeat(content[start..end].chars().count());
+        at = end;
+    }
+    masked + &content[at..]
+}
+
+fn rejection(rule: &Rule) -> String {
+    match rule.kind {
+        RuleKind::Word | RuleKind::Regex => "Your message has something this room doesn't allow".to_owned(),
+        RuleKind::LinkAllow | RuleKind::LinkDeny => "This room doesn't allow that link".to_owned(),
+        RuleKind::MaxLength => format!("Messages here are at most {} characters", rule.pattern.trim()),
+        RuleKind::MaxMentions => format!("Mention at most {} profiles per message", rule.pattern.trim()),
+    }
+}
+
+/// Adds a rule to the room, checking its pattern makes sense for its kind.
+pub async fn add_rule(db_pool: &SqlitePool, room_id: Uuid, kind: RuleKind, pattern: &str, action: RuleAction) -> AppResult<Uuid> {
+    let pattern = pattern.trim();
+    if pattern.is_empty() {
+        return Err(refuse(StatusCode::BAD_REQUEST, "Rules need something to look for"));
+    }
+    if action == RuleAction::Mask && !kind.can_mask() {
+        return Err(refuse(StatusCode::BAD_REQUEST, "Only words, patterns and links can be masked"));
+    }
+    match kind {
+        RuleKind::Word if word_regex(pattern).is_none() =>
+            return Err(refuse(StatusCode::BAD_REQUEST, "That word can't be matched")),
+        RuleKind::Regex => if let Err(err) = user_regex(pattern) {
+            return Err(refuse(StatusCode::BAD_REQUEST, &format!("That pattern doesn't work: {err}")));
+        },
+        RuleKind::MaxLength | RuleKind::MaxMentions if limit(pattern).is_none() =>
+            return Err(refuse(StatusCode::BAD_REQUEST, "Limits are whole numbers")),
+        _ => (),
+    }
+
+    let id = Uuid::now_v7();
+    sqlx::query("INSERT INTO automod_rules (id,room_id,kind,pattern,action,created_at) VALUES (?,?,?,?,?,unixepoch())")
+        .bind(id.to_string())
+        .bind(room_id.to_string())
+        .bind(kind.slug())
+        .bind(pattern)
+        .bind(action.slug())
+        .execute(db_pool)
+        .await?;
+    Ok(id)
+}
+
+pub async fn remove_rule(db_pool: &SqlitePool, room_id: Uuid, id: Uuid) -> AppResult<bool> {
+    Ok(
+        sqlx::query("DELETE FROM automod_rules WHERE id=? AND room_id=?")
+            .bind(id.to_string())
+            .bind(room_id.to_string())
+            .execute(db_pool)
+            .await?
+            .rows_affected() > 0
+    )
+}
+
+/// The room's rules, oldest first.
+pub async fn rules(db_pool: &SqlitePool, room_id: Uuid) -> AppResult<Vec<Rule>> {
+    let rows: Vec<(String, String, String, String)> = sqlx::query_as("SELECT id,kind,pattern,action FROM automod_rules WHERE room_id=? ORDER BY created_at,id")
+        .bind(room_id.to_string())
+        .fetch_all(db_pool)
+        .await?;
+
+    let mut rules = Vec::new();
+    for (id, kind, pattern, action) in rows {
+        let (Some(kind), Some(action)) = (RuleKind::from_slug(&kind), RuleAction::from_slug(&action)) else {
+            continue;
+        };
+        rules.push(Rule { id: Uuid::parse_str(&id)?, kind, pattern, action });
+    }
+    Ok(rules)
+}
+
+/// Runs the room's rules over a message from `profile_id`, noting every hit
+/// in the moderation log. Refusing beats holding beats masking.
+pub async fn check(db_pool: &SqlitePool, room_id: Uuid, profile_id: Uuid, content: &str) -> AppResult<Verdict> {
+    let mut content = content.to_owned();
+    let mut held = None;
+    let mut rejected = None;
+
+    for rule in rules(db_pool, room_id).await? {
+        let Some(ranges) = caught(&rule, &content) else {
+            continue;
+        };
+
+        sqlx::query("INSERT INTO automod_log (id,room_id,rule_id,profile_id,action,created_at) VALUES (?,?,?,?,?,unixepoch())")
+            .bind(Uuid::now_v7().to_string())
+            .bind(room_id.to_string())
+            .bind(rule.id.to_string())
+            .bind(profile_id.to_string())
+            .bind(rule.action.slug())
+            .execute(db_pool)
+            .await?;
+
+        match rule.action {
+            RuleAction::Reject => { rejected.get_or_insert_with(|| rejection(&rule)); },
+            RuleAction::Hold => { held.get_or_insert(rule.id); },
+            RuleAction::Mask => content = mask(&content, &ranges),
+        }
+    }
+
+    Ok(match (rejected, held) {
+        (Some(reason), _) => Verdict::Reject(reason),
+        (None, Some(rule_id)) => Verdict::Hold(rule_id, content),
+        (None, None) => Verdict::Pass(content),
+    })
+}
+
+/// Keeps a message back for a moderator, see [`approve_held`].
+pub(crate) async fn hold_msg(
+    db_pool: &SqlitePool,
+    room_id: Uuid,
+    profile_id: Uuid,
+    reply_to_id: Option<Uuid>,
+    content: &str,
+    ttl_secs: Option<i64>,
+    rule_id: Uuid,
+) -> AppResult<Uuid> {
+    let id = Uuid::now_v7();
+    sqlx::query("INSERT INTO held_messages (id,room_id,profile_id,reply_to_id,content,ttl_secs,rule_id,created_at) VALUES (?,?,?,?,?,?,?,unixepoch())")
+        .bind(id.to_string())
+        .bind(room_id.to_string())
+        .bind(profile_id.to_string())
+        .bind(reply_to_id.as_ref().map(Uuid::to_string))
+        .bind(content)
+        .bind(ttl_secs)
+        .bind(rule_id.to_string())
+        .execute(db_pool)
+        .await?;
+    Ok(id)
+}
+
+/// A message waiting for a moderator.
+#[derive(Debug, Clone)]
+pub struct Held {
+    pub id: Uuid,
+    pub profile_id: Uuid,
+    pub alias: String,
+    pub content: String,
+    pub created_at: i64,
+}
+
+pub async fn held_messages(db_pool: &SqlitePool, room_id: Uuid) -> AppResult<Vec<Held>> {
+    let rows: Vec<(String, String, String, String, i64)> = sqlx::query_as(
+        "SELECT id,profile_id,alias,content,created_at FROM held_messages
+        JOIN profiles ON profiles.uuid=held_messages.profile_id
+        WHERE held_messages.room_id=?
+        ORDER BY created_at,id"
+    )
+        .bind(room_id.to_string())
+        .fetch_all(db_pool)
+        .await?;
+
+    let mut held = Vec::new();
+    for (id, profile_id, alias, content, created_at) in rows {
+        held.push(Held { id: Uuid::parse_str(&id)?, profile_id: Uuid::parse_str(&profile_id)?, alias, content, created_at });
+    }
+    Ok(held)
+}
+
+type HeldRow = (String, String, Option<String>, String, Option<i64>);
+
+/// Posts a held message as it was sent, now, unless the room was archived or
+/// its sender banned or muted in the meantime. Returns the new message's id.
+pub async fn approve_held(db_pool: &SqlitePool, tx: &broadcast::Sender<RoomEvent>, room_id: Uuid, id: Uuid) -> AppResult<Uuid> {
+    let Some((profile_id, user_id, reply_to_id, content, ttl_secs)): Option<HeldRow> =
+        sqlx::query_as(
+            "SELECT held_messages.profile_id,profiles.user_id,reply_to_id,content,ttl_secs
+            FROM held_messages JOIN profiles ON profiles.uuid=held_messages.profile_id
+            WHERE held_messages.id=? AND held_messages.room_id=?"
+        )
+            .bind(id.to_string())
+            .bind(room_id.to_string())
+            .fetch_optional(db_pool)
+            .await?
+    else {
+        return Err(refuse(StatusCode::NOT_FOUND, "No such held message"));
+    };
+    let profile_id = Uuid::parse_str(&profile_id)?;
+
+    // the room and the sender may have changed while it waited
+    msg::check_not_archived(db_pool, room_id).await?;
+    if is_banned(db_pool, &user_id, room_id).await? {
+        return Err(refuse(StatusCode::FORBIDDEN, "Its sender was banned since, reject it instead"));
+    }
+    if is_muted(db_pool, profile_id).await? {
+        return Err(refuse(StatusCode::FORBIDDEN, "Its sender is muted for now"));
+    }
+
+    let taken = sqlx::query("DELETE FROM held_messages WHERE id=? AND room_id=?")
+        .bind(id.to_string())
+        .bind(room_id.to_string())
+        .execute(db_pool)
+        .await?
+        .rows_affected();
+    if taken == 0 {
+        return Err(refuse(StatusCode::NOT_FOUND, "No such held message"));
+    }
+
+    // the message it replied to may be gone by now
+    let reply_to_id = match reply_to_id {
+        Some(reply_to_id) => sqlx::query_scalar::<_, String>("SELECT id FROM messages WHERE id=? AND room_id=?")
+            .bind(reply_to_id)
+            .bind(room_id.to_string())
+            .fetch_optional(db_pool)
+            .await?
+            .map(|id| Uuid::parse_str(&id))
+            .transpose()?,
+        None => None,
+    };
+    msg::post_msg(db_pool, tx, profile_id, room_id, reply_to_id, content, ttl_secs).await
+}
+
+pub async fn reject_held(db_pool: &SqlitePool, room_id: Uuid, id: Uuid) -> AppResult<bool> {
+    Ok(
+        sqlx::query("DELETE FROM held_messages WHERE id=? AND room_id=?")
+            .bind(id.to_string())
+            .bind(room_id.to_string())
+            .execute(db_pool)
+            .await?
+            .rows_affected() > 0
+    )
+}
+
+/// How many messages a room has waiting for a moderator.
+pub(crate) async fn held_count(db_pool: &SqlitePool, room_id: Uuid) -> AppResult<i64> {
+    Ok(
+        sqlx::query_scalar("SELECT count(*) FROM held_messages WHERE room_id=?")
+            .bind(room_id.to_string())
+            .fetch_one(db_pool)
+            .await?
+    )
+}
+
+/// action, kind, pattern, alias, created_at; rules and profiles may be gone
+type LogRow = (String, Option<String>, Option<String>, Option<String>, i64);
+
+async fn moderator(db_pool: &SqlitePool, session: &Session, room_id: Uuid) -> AppResult<bool> {
+    Ok(match session.get::<String>(USER_ID).await? {
+        Some(user_id) => can_moderate(db_pool, &user_id, room_id).await?,
+        None => false,
+    })
+}
+
+#[debug_handler]
+pub(crate) async fn automod_page(
+    State(db_pool): State<SqlitePool>,
+    session: Session,
+    Path(room_id): Path<Uuid>,
+) -> AppResult<Response> {
+    if !moderator(&db_pool, &session, room_id).await? {
+        return res::sorry("automod");
+    }
+    let csrf_token = auth::csrf_token(&session).await?;
+
+    let (name,): (String,) = sqlx::query_as("SELECT name FROM rooms WHERE uuid=?")
+        .bind(room_id.to_string())
+        .fetch_one(&db_pool)
+        .await?;
+
+    let hits: Vec<(String, i64)> = sqlx::query_as("SELECT rule_id,count(*) FROM automod_log WHERE room_id=? AND rule_id IS NOT NULL GROUP BY rule_id")
+        .bind(room_id.to_string())
+        .fetch_all(&db_pool)
+        .await?;
+    let mut rule_items = String::new();
+    for rule in rules(&db_pool, room_id).await? {
+        let id = rule.id.to_string();
+        let hits = hits.iter().find(|(rule_id, _)| *rule_id == id).map(|(_, hits)| *hits).unwrap_or_default();
+        rule_items += &include_res!(str, "pages/rooms/automod_rule.html")
+            .replace("{csrf_token}", &csrf_token)
+            .replace("{room_id}", &room_id.to_string())
+            .replace("{id}", &id)
+            .replace("{kind}", rule.kind.label())
+            .replace("{action}", rule.action.label())
+            .replace("{hits}", &hits.to_string())
+            .replace("{pattern}", &res::escape(&rule.pattern));
+    }
+
+    let mut held_items = String::new();
+    for held in held_messages(&db_pool, room_id).await? {
+        held_items += &include_res!(str, "pages/rooms/held_message.html")
+            .replace("{csrf_token}", &csrf_token)
+            .replace("{room_id}", &room_id.to_string())
+            .replace("{id}", &held.id.to_string())
+            .replace("{profile_id}", &held.profile_id.to_string())
+            .replace("{ago}", &res::ago(held.created_at))
+            .replace("{alias}", &res::escape(&held.alias))
+            .replace("{content}", &res::escape(&held.content));
+    }
+
+    let log: Vec<LogRow> = sqlx::query_as(
+        "SELECT automod_log.action,automod_rules.kind,automod_rules.pattern,profiles.alias,automod_log.created_at FROM automod_log
+        LEFT JOIN automod_rules ON automod_rules.id=automod_log.rule_id
+        LEFT JOIN profiles ON profiles.uuid=automod_log.profile_id
+        WHERE automod_log.room_id=?
+        ORDER BY automod_log.created_at DESC, automod_log.id DESC
+        LIMIT 50"
+    )
+        .bind(room_id.to_string())
+        .fetch_all(&db_pool)
+        .await?;
+    let log_items: String = log.into_iter()
+        .map(|(action, kind, pattern, alias, created_at)| format!(
+            "<li>{} · {} {} · {} · {}</li>\n",
+            res::escape(&alias.unwrap_or("(gone)".to_owned())),
+            kind.as_deref().and_then(RuleKind::from_slug).map(RuleKind::label).unwrap_or("(deleted rule)"),
+            res::escape(&pattern.unwrap_or_default()),
+            RuleAction::from_slug(&action).map(RuleAction::label).unwrap_or_default(),
+            res::ago(created_at),
+        ))
+        .collect();
+
+    let kinds: String = RuleKind::ALL.into_iter()
+        .map(|kind| format!("<option value=\"{}\">{}</option>\n", kind.slug(), kind.label()))
+        .collect();
+    let actions: String = RuleAction::ALL.into_iter()
+        .map(|action| format!("<option value=\"{}\">{}</option>\n", action.slug(), action.label()))
+        .collect();
+
+    Ok(Html(
+        include_res!(str, "pages/rooms/automod.html")
+            .replace("{csrf_token}", &csrf_token)
+            .replace("{room_id}", &room_id.to_string())
+            .replace("{kinds}", &kinds)
+            .replace("{actions}", &actions)
+            .replace("{no_rules}", if rule_items.is_empty() { "" } else { "display: none;" })
+            .replace("{no_held}", if held_items.is_empty() { "" } else { "display: none;" })
+            .replace("{no_log}", if log_items.is_empty() { "" } else { "display: none;" })
+            .replace("{room_name}", &res::escape(&name))
+            .replace("{rules}", &rule_items)
+            .replace("{held}", &held_items)
+            .replace("{log}", &log_items)
+    ).into_response())
+}
+
+#[derive(Deserialize)]
+pub(crate) struct RuleForm {
+    kind: RuleKind,
+    pattern: String,
+    action: RuleAction,
+}
+
+#[debug_handler]
+pub(crate) async fn add(
+    State(db_pool): State<SqlitePool>,
+    session: Session,
+    Path(room_id): Path<Uuid>,
+    Form(RuleForm { kind, pattern, action }): Form<RuleForm>,
+) -> AppResult<Response> {
+    if !moderator(&db_pool, &session, room_id).await? {
+        return res::sorry("automod");
+    }
+
+    add_rule(&db_pool, room_id, kind, &pattern, action).await?;
+    Ok(Redirect::to(&format!("/r/{room_id}/automod")).into_response())
+}
+
+#[debug_handler]
+pub(crate) async fn remove(
+    State(db_pool): State<SqlitePool>,
+    session: Session,
+    Path((room_id, id)): Path<(Uuid, Uuid)>,
+) -> AppResult<Response> {
+    if !moderator(&db_pool, &session, room_id).await? {
+        return res::sorry("automod");
+    }
+
+    remove_rule(&db_pool, room_id, id).await?;
+    Ok(Redirect::to(&format!("/r/{room_id}/automod")).into_response())
+}
+
+#[debug_handler(state = AppState)]
+pub(crate) async fn approve(
+    State(db_pool): State<SqlitePool>,
+    State(tx): State<broadcast::Sender<RoomEvent>>,
+    session: Session,
+    Path((room_id, id)): Path<(Uuid, Uuid)>,
+) -> AppResult<Response> {
+    if !moderator(&db_pool, &session, room_id).await? {
+        return res::sorry("automod");
+    }
+
+    approve_held(&db_pool, &tx, room_id, id).await?;
+    Ok(Redirect::to(&format!("/r/{room_id}/automod")).into_response())
+}
+
+#[debug_handler]
+pub(crate) async fn reject(
+    State(db_pool): State<SqlitePool>,
+    session: Session,
+    Path((room_id, id)): Path<(Uuid, Uuid)>,
+) -> AppResult<Response> {
+    if !moderator(&db_pool, &session, room_id).await? {
+        return res::sorry("automod");
+    }
+
+    reject_held(&db_pool, room_id, id).await?;
+    Ok(Redirect::to(&format!("/r/{room_id}/automod")).into_response())
+}
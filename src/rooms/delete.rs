@@ -0,0 +1,136 @@
+use std::time::Duration;
+
+use axum::{debug_handler, extract::{Path, State}, response::{Html, IntoResponse, Redirect, Response}, Form};
+use reqwest::StatusCode;
+use serde::Deserialize;
+use sqlx::SqlitePool;
+use tokio::sync::broadcast;
+use tower_sessions::Session;
+use uuid::Uuid;
+
+use crate::{auth, include_res, res, AppResult, AppState};
+
+use super::{msg, owned_room, RoomEvent};
+
+type DeletionRow = (String, Option<i64>);
+const DELETION: &str = "name,delete_at";
+
+/// Longest a deletion can be put off, 30 days.
+const MAX_GRACE_HOURS: i64 = 720;
+
+#[derive(Debug, Deserialize)]
+pub(crate) struct DeleteRoomQuery {
+    confirm_name: String,
+    grace_hours: i64,
+}
+
+/// Removes a room, its profiles and messages go with it (`on delete cascade`).
+pub async fn delete_room(db_pool: &SqlitePool, room_id: Uuid) -> AppResult<()> {
+    sqlx::query("DELETE FROM rooms WHERE uuid=?")
+        .bind(room_id.to_string())
+        .execute(db_pool)
+        .await?;
+    Ok(())
+}
+
+/// Deletes every room whose grace period ran out, returns how many went.
+pub async fn purge_due_rooms(db_pool: &SqlitePool) -> AppResult<u64> {
+    Ok(
+        sqlx::query("DELETE FROM rooms WHERE delete_at IS NOT NULL AND delete_at <= unixepoch()")
+            .execute(db_pool)
+            .await?
+            .rows_affected()
+    )
+}
+
+pub async fn room_reaper(db_pool: SqlitePool) {
+    let mut interval = tokio::time::interval(Duration::from_secs(60));
+    loop {
+        interval.tick().await;
+        match purge_due_rooms(&db_pool).await {
+            Ok(0) => (),
+            Ok(n) => println!("reaped {n} rooms"),
+            Err(err) => println!("room reaper: {:?}", err.0),
+        }
+    }
+}
+
+#[debug_handler]
+pub(crate) async fn delete_page(
+    State(db_pool): State<SqlitePool>,
+    session: Session,
+    Path(room_id): Path<Uuid>,
+) -> AppResult<Response> {
+    let Some((name, delete_at)) = owned_room::<DeletionRow>(&db_pool, &session, room_id, DELETION).await? else {
+        return res::sorry("room");
+    };
+
+    let now = time::OffsetDateTime::now_utc().unix_timestamp();
+    Ok(Html(
+        include_res!(str, "pages/rooms/delete.html")
+        .replace("{csrf_token}", &auth::csrf_token(&session).await?)
+        .replace("{room_id}", &room_id.to_string())
+        .replace("{room_name}", &res::escape(&name))
+        .replace("{pending}", if delete_at.is_some() { "" } else { "display: none;" })
+        .replace("{delete_at}", &delete_at.map(|t| format!("in {}h", (t - now).max(0) / 3600)).unwrap_or_default())
+    ).into_response())
+}
+
+#[debug_handler(state = AppState)]
+pub(crate) async fn delete(
+    State(db_pool): State<SqlitePool>,
+    State(tx): State<broadcast::Sender<RoomEvent>>,
+    session: Session,
+    Path(room_id): Path<Uuid>,
+
+    Form(DeleteRoomQuery { confirm_name, grace_hours }): Form<DeleteRoomQuery>,
+) -> AppResult<Response> {
+    let Some((name, _)) = owned_room::<DeletionRow>(&db_pool, &session, room_id, DELETION).await? else {
+        return res::sorry("room");
+    };
+
+    if confirm_name != name {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            "Room name doesn't match, nothing was deleted"
+        ).into_response().into());
+    }
+
+    if !(0..=MAX_GRACE_HOURS).contains(&grace_hours) {
+        return Err(msg::refuse(StatusCode::BAD_REQUEST, "Pick a grace period of up to 30 days"));
+    }
+
+    if grace_hours == 0 {
+        delete_room(&db_pool, room_id).await?;
+        return Ok(Redirect::to("/").into_response());
+    }
+
+    sqlx::query("UPDATE rooms SET delete_at=unixepoch()+?*3600 WHERE uuid=?")
+        .bind(grace_hours)
+        .bind(room_id.to_string())
+        .execute(&db_pool)
+        .await?;
+    msg::send_system_msg(&db_pool, &tx, room_id, format!("This room will be deleted in {grace_hours} hours")).await?;
+
+    Ok(Redirect::to(&format!("/r/{room_id}/delete")).into_response())
+}
+
+#[debug_handler(state = AppState)]
+pub(crate) async fn cancel_delete(
+    State(db_pool): State<SqlitePool>,
+    State(tx): State<broadcast::Sender<RoomEvent>>,
+    session: Session,
+    Path(room_id): Path<Uuid>,
+) -> AppResult<Response> {
+    let Some((_, Some(_))) = owned_room::<DeletionRow>(&db_pool, &session, room_id, DELETION).await? else {
+        return res::sorry("room");
+    };
+
+    sqlx::query("UPDATE rooms SET delete_at=NULL WHERE uuid=?")
+        .bind(room_id.to_string())
+        .execute(&db_pool)
+        .await?;
+    msg::send_system_msg(&db_pool, &tx, room_id, "Room deletion was cancelled".to_owned()).await?;
+
+    Ok(Redirect::to(&format!("/r/{room_id}")).into_response())
+}
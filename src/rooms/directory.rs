@@ -0,0 +1,114 @@
+use axum::{debug_handler, extract::{Path, Query, State}, response::{Html, IntoResponse, Redirect, Response}};
+use serde::Deserialize;
+use sqlx::SqlitePool;
+use tower_sessions::Session;
+use uuid::Uuid;
+
+use crate::{auth::{self, guest_can_join, GuestConfig}, include_res, res, session::USER_ID, AppResult, AppState};
+
+use super::{room_access, RoomAccess};
+
+#[derive(Debug, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum DirectorySort {
+    #[default]
+    Activity,
+    Members,
+    Busy,
+    Name,
+}
+
+impl DirectorySort {
+    fn order_by(&self) -> &str {
+        use DirectorySort::*;
+        match self {
+            Activity => "last_active DESC",
+            Members => "members DESC",
+            Busy => "recent DESC",
+            Name => "name COLLATE NOCASE ASC",
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub(crate) struct DirectoryQuery {
+    q: Option<String>,
+    #[serde(default)]
+    sort: DirectorySort,
+}
+
+#[debug_handler]
+pub(crate) async fn directory(
+    State(db_pool): State<SqlitePool>,
+    session: Session,
+    Query(DirectoryQuery { q, sort }): Query<DirectoryQuery>,
+) -> AppResult<Response> {
+    let user_id = session.get::<String>(USER_ID).await?;
+    let q = q.unwrap_or_default();
+
+    // recent = messages in the last week, for messages/day
+    let rooms: Vec<(String, String, String, i64, Option<i64>, i64)> = sqlx::query_as(&format!(
+        "SELECT uuid,name,description,
+            (SELECT count(*) FROM profiles WHERE profiles.room_id=rooms.uuid) AS members,
+            (SELECT max(created_at) FROM messages WHERE messages.room_id=rooms.uuid) AS last_active,
+            (SELECT count(*) FROM messages WHERE messages.room_id=rooms.uuid AND messages.created_at > unixepoch()-7*86400) AS recent
+        FROM rooms WHERE is_public=1 AND instr(lower(name), lower(?)) > 0
+        ORDER BY {}", sort.order_by()
+    ))
+        .bind(&q)
+        .fetch_all(&db_pool)
+        .await?;
+
+    let csrf_token = auth::csrf_token(&session).await?;
+    let mut room_entries = String::new();
+    for (room_id, name, description, members, last_active, recent) in rooms {
+        let is_member = matches!(
+            room_access(&db_pool, user_id.as_deref(), Uuid::parse_str(&room_id)?).await?,
+            Some(RoomAccess::Member(_))
+        );
+
+        room_entries += &include_res!(str, "pages/rooms/directory_item.html")
+            .replace("{csrf_token}", &csrf_token)
+            .replace("{id}", &room_id)
+            .replace("{name}", &res::escape(&name))
+            .replace("{description}", &res::escape(&description))
+            .replace("{members}", &members.to_string())
+            .replace("{last_active}", &last_active.filter(|&t| t > 0).map(res::ago).unwrap_or("never".to_owned()))
+            .replace("{per_day}", &format!("{:.1}", recent as f64 / 7.0))
+            .replace("{join}", if is_member { "Open" } else { "Join" });
+    }
+
+    let selected = |s: DirectorySort| if sort == s { "selected" } else { "" };
+    Ok(Html(
+        include_res!(str, "pages/rooms/directory.html")
+        .replace("{q}", &res::escape(&q))
+        .replace("{sort_activity}", selected(DirectorySort::Activity))
+        .replace("{sort_members}", selected(DirectorySort::Members))
+        .replace("{sort_busy}", selected(DirectorySort::Busy))
+        .replace("{sort_name}", selected(DirectorySort::Name))
+        .replace("{room_entries}", &room_entries)
+    ).into_response())
+}
+
+#[debug_handler(state = AppState)]
+pub(crate) async fn join(
+    State(db_pool): State<SqlitePool>,
+    State(guests): State<GuestConfig>,
+    session: Session,
+    Path(room_id): Path<Uuid>,
+) -> AppResult<Response> {
+    let Some(user_id) = session.get::<String>(USER_ID).await? else {
+        return Ok(Redirect::to(&format!("/login?return_url=/r/{room_id}")).into_response());
+    };
+
+    match room_access(&db_pool, Some(&user_id), room_id).await? {
+        Some(RoomAccess::Member(_)) => (),
+        Some(RoomAccess::Visitor) if guest_can_join(&db_pool, &guests, &user_id).await? => {
+            auth::create_profile(&db_pool, &user_id, &room_id.to_string()).await?;
+        },
+        Some(RoomAccess::Visitor) => return res::sorry("room (guests can only join a few)"),
+        None => return res::sorry("room"),
+    }
+
+    Ok(Redirect::to(&format!("/r/{room_id}")).into_response())
+}
@@ -0,0 +1,328 @@
+use axum::{debug_handler, extract::{Path, Query, State}, http::StatusCode, response::{Html, IntoResponse, Redirect, Response}, Form};
+use serde::Deserialize;
+use sqlx::SqlitePool;
+use tokio::sync::broadcast;
+use tower_sessions::Session;
+use uuid::Uuid;
+
+use crate::{auth::{self, Caller, GuestConfig}, include_res, res, AppResult, AppState};
+
+use super::{can_send, is_muted, msg::{check_not_archived, refuse}, reveal, room_access, Limits, RoomAccess, RoomEvent};
+
+/// A profile in the room by uuid or `@handle`.
+pub async fn find_profile(db_pool: &SqlitePool, room_id: Uuid, who: &str) -> AppResult<Option<Uuid>> {
+    let profile_id: Option<String> = match Uuid::parse_str(who) {
+        Ok(profile_id) => sqlx::query_scalar("SELECT uuid FROM profiles WHERE uuid=? AND room_id=?")
+            .bind(profile_id.to_string()),
+        Err(_) => sqlx::query_scalar("SELECT uuid FROM profiles WHERE handle=? AND room_id=?")
+            .bind(who.trim_start_matches('@').to_owned()),
+    }
+        .bind(room_id.to_string())
+        .fetch_optional(db_pool)
+        .await?;
+
+    Ok(match profile_id {
+        Some(profile_id) => Some(Uuid::parse_str(&profile_id)?),
+        None => None,
+    })
+}
+
+pub async fn block_profile(db_pool: &SqlitePool, blocker_id: Uuid, blocked_id: Uuid) -> AppResult<()> {
+    sqlx::query("INSERT INTO profile_blocks (blocker_id,blocked_id) VALUES (?,?) ON CONFLICT DO NOTHING")
+        .bind(blocker_id.to_string())
+        .bind(blocked_id.to_string())
+        .execute(db_pool)
+        .await?;
+    Ok(())
+}
+
+pub async fn unblock_profile(db_pool: &SqlitePool, blocker_id: Uuid, blocked_id: Uuid) -> AppResult<()> {
+    sqlx::query("DELETE FROM profile_blocks WHERE blocker_id=? AND blocked_id=?")
+        .bind(blocker_id.to_string())
+        .bind(blocked_id.to_string())
+        .execute(db_pool)
+        .await?;
+    Ok(())
+}
+
+/// Whether `blocker_id` blocked `blocked_id`, one way only.
+pub async fn has_blocked(db_pool: &SqlitePool, blocker_id: Uuid, blocked_id: Uuid) -> AppResult<bool> {
+    Ok(
+        sqlx::query("SELECT 1 FROM profile_blocks WHERE blocker_id=? AND blocked_id=?")
+            .bind(blocker_id.to_string())
+            .bind(blocked_id.to_string())
+            .fetch_optional(db_pool)
+            .await?
+            .is_some()
+    )
+}
+
+fn dm_to_html(id: Uuid, from_id: Uuid, to_id: Uuid, alias: &str, content: &str, created_at: i64) -> String {
+    include_res!(str, "pages/rooms/dm_message.html")
+        .replace("{id}", &id.to_string())
+        .replace("{from_id}", &from_id.to_string())
+        .replace("{to_id}", &to_id.to_string())
+        .replace("{alias}", &res::escape(alias))
+        .replace("{ago}", &res::ago(created_at))
+        .replace("{content}", &res::escape(content))
+}
+
+/// Sends a DM between two profiles of the same room, only their sockets hear
+/// of it. A block either way stops it, and so does a mute, which silences the
+/// profile in the whole room.
+pub async fn send_dm(
+    db_pool: &SqlitePool,
+    tx: &broadcast::Sender<RoomEvent>,
+
+    from_id: Uuid,
+    to_id: Uuid,
+    content: &str,
+) -> AppResult<Uuid> {
+    if from_id == to_id {
+        return Err(refuse(StatusCode::BAD_REQUEST, "That's you"));
+    }
+    if content.trim().is_empty() {
+        return Err(refuse(StatusCode::BAD_REQUEST, "Say something"));
+    }
+
+    let rooms: Vec<(String, String)> = sqlx::query_as("SELECT uuid,room_id FROM profiles WHERE uuid IN (?,?)")
+        .bind(from_id.to_string())
+        .bind(to_id.to_string())
+        .fetch_all(db_pool)
+        .await?;
+    let [(_, room_id), (_, other_room_id)] = rooms.as_slice() else {
+        return Err(refuse(StatusCode::NOT_FOUND, "No such profile"));
+    };
+    if room_id != other_room_id {
+        return Err(refuse(StatusCode::NOT_FOUND, "No such profile"));
+    }
+    let room_id = Uuid::parse_str(room_id)?;
+    check_not_archived(db_pool, room_id).await?;
+
+    if is_muted(db_pool, from_id).await? {
+        return Err(refuse(StatusCode::FORBIDDEN, "You're muted in this room for now"));
+    }
+    if has_blocked(db_pool, from_id, to_id).await? || has_blocked(db_pool, to_id, from_id).await? {
+        return Err(refuse(StatusCode::FORBIDDEN, "You can't message this profile"));
+    }
+
+    let id = Uuid::now_v7();
+    let (created_at,): (i64,) = sqlx::query_as(
+        "INSERT INTO direct_messages (id,room_id,from_id,to_id,content,created_at) VALUES (?,?,?,?,?,unixepoch()) RETURNING created_at"
+    )
+        .bind(id.to_string())
+        .bind(room_id.to_string())
+        .bind(from_id.to_string())
+        .bind(to_id.to_string())
+        .bind(content)
+        .fetch_one(db_pool)
+        .await?;
+
+    let (alias,): (String,) = sqlx::query_as("SELECT alias FROM profiles WHERE uuid=?")
+        .bind(from_id.to_string())
+        .fetch_one(db_pool)
+        .await?;
+
+    let _ = tx.send(RoomEvent {
+        room_id,
+        message_id: None,
+        data: dm_to_html(id, from_id, to_id, &alias, content, created_at),
+        only_to: Some([from_id, to_id]),
+    });
+
+    Ok(id)
+}
+
+/// The caller's profile in the room, DMs need one.
+pub(super) async fn own_profile(db_pool: &SqlitePool, caller: Option<Caller>, room_id: Uuid) -> AppResult<Option<Uuid>> {
+    let Some(caller) = caller else {
+        return Ok(None);
+    };
+    Ok(match room_access(db_pool, Some(&caller.user_id), room_id).await? {
+        Some(RoomAccess::Member(profile_id)) => Some(profile_id),
+        _ => None,
+    })
+}
+
+/// Read-only tokens can read DMs, but not send them or change blocks.
+pub(super) fn can_write(caller: &Option<Caller>) -> AppResult<()> {
+    if caller.as_ref().is_some_and(|caller| !caller.can_write) {
+        return Err(refuse(StatusCode::FORBIDDEN, "This token is read only"));
+    }
+    Ok(())
+}
+
+#[derive(Deserialize)]
+pub(crate) struct InboxQuery {
+    who: Option<String>,
+}
+
+/// Everyone you've talked to in the room, most recent first.
+#[debug_handler]
+pub(crate) async fn inbox(
+    State(db_pool): State<SqlitePool>,
+    caller: Option<Caller>,
+    Path(room_id): Path<Uuid>,
+    Query(InboxQuery { who }): Query<InboxQuery>,
+) -> AppResult<Response> {
+    let Some(profile_id) = own_profile(&db_pool, caller, room_id).await? else {
+        return res::sorry("room");
+    };
+
+    if let Some(who) = who {
+        return match find_profile(&db_pool, room_id, who.trim()).await? {
+            Some(other_id) => Ok(Redirect::to(&format!("/r/{room_id}/dm/{other_id}")).into_response()),
+            None => res::sorry("profile"),
+        };
+    }
+
+    let conversations: Vec<(String, String, String, i64)> = sqlx::query_as(
+        "SELECT profiles.uuid,profiles.handle,profiles.alias,max(direct_messages.created_at) AS last
+        FROM direct_messages
+        JOIN profiles ON profiles.uuid=CASE WHEN direct_messages.from_id=?1 THEN direct_messages.to_id ELSE direct_messages.from_id END
+        WHERE direct_messages.from_id=?1 OR direct_messages.to_id=?1
+        GROUP BY profiles.uuid
+        ORDER BY last DESC"
+    )
+        .bind(profile_id.to_string())
+        .fetch_all(&db_pool)
+        .await?;
+
+    let mut items = String::new();
+    for (other_id, handle, alias, last) in conversations {
+        items += &include_res!(str, "pages/rooms/dm_item.html")
+            .replace("{room_id}", &room_id.to_string())
+            .replace("{profile_id}", &other_id)
+            .replace("{alias}", &res::escape(&alias))
+            .replace("{handle}", &res::escape(&handle))
+            .replace("{ago}", &res::ago(last));
+    }
+
+    Ok(Html(
+        include_res!(str, "pages/rooms/dms.html")
+            .replace("{room_id}", &room_id.to_string())
+            .replace("{conversations}", &items)
+    ).into_response())
+}
+
+#[debug_handler]
+pub(crate) async fn conversation(
+    State(db_pool): State<SqlitePool>,
+    session: Session,
+    caller: Option<Caller>,
+    Path((room_id, who)): Path<(Uuid, String)>,
+) -> AppResult<Response> {
+    let Some(profile_id) = own_profile(&db_pool, caller, room_id).await? else {
+        return res::sorry("room");
+    };
+    let Some(other_id) = find_profile(&db_pool, room_id, &who).await? else {
+        return res::sorry("profile");
+    };
+
+    let (handle, alias): (String, String) = sqlx::query_as("SELECT handle,alias FROM profiles WHERE uuid=?")
+        .bind(other_id.to_string())
+        .fetch_one(&db_pool)
+        .await?;
+
+    let rows: Vec<(String, String, String, String, String, i64)> = sqlx::query_as(
+        "SELECT direct_messages.id,from_id,to_id,profiles.alias,content,created_at
+        FROM direct_messages JOIN profiles ON profiles.uuid=from_id
+        WHERE (from_id=?1 AND to_id=?2) OR (from_id=?2 AND to_id=?1)
+        ORDER BY created_at,direct_messages.id"
+    )
+        .bind(profile_id.to_string())
+        .bind(other_id.to_string())
+        .fetch_all(&db_pool)
+        .await?;
+
+    let mut messages = String::new();
+    for (id, from_id, to_id, from_alias, content, created_at) in rows {
+        messages += &dm_to_html(Uuid::parse_str(&id)?, Uuid::parse_str(&from_id)?, Uuid::parse_str(&to_id)?, &from_alias, &content, created_at);
+    }
+
+    let blocked = has_blocked(&db_pool, profile_id, other_id).await?;
+    let csrf_token = auth::csrf_token(&session).await?;
+    let reveal = reveal::reveal_html(&db_pool, room_id, profile_id, other_id, &csrf_token).await?;
+
+    Ok(Html(
+        include_res!(str, "pages/rooms/dm.html")
+            .replace("{csrf_token}", &csrf_token)
+            .replace("{room_id}", &room_id.to_string())
+            .replace("{profile_id}", &profile_id.to_string())
+            .replace("{other_id}", &other_id.to_string())
+            .replace("{alias}", &res::escape(&alias))
+            .replace("{handle}", &res::escape(&handle))
+            .replace("{block}", if blocked { "unblock" } else { "block" })
+            .replace("{block_label}", if blocked { "Unblock" } else { "Block" })
+            .replace("{blocked}", if blocked { "" } else { "display: none;" })
+            .replace("{reveal}", &reveal)
+            .replace("{messages}", &messages)
+    ).into_response())
+}
+
+#[derive(Deserialize)]
+pub(crate) struct DmForm {
+    content: String,
+}
+
+#[debug_handler(state = AppState)]
+pub(crate) async fn send(
+    State(db_pool): State<SqlitePool>,
+    State(tx): State<broadcast::Sender<RoomEvent>>,
+    State(guests): State<GuestConfig>,
+    State(limits): State<Limits>,
+    caller: Option<Caller>,
+    Path((room_id, who)): Path<(Uuid, String)>,
+    Form(DmForm { content }): Form<DmForm>,
+) -> AppResult<Response> {
+    can_write(&caller)?;
+    let user_id = caller.as_ref().map(|caller| caller.user_id.clone()).unwrap_or_default();
+    let Some(profile_id) = own_profile(&db_pool, caller, room_id).await? else {
+        return res::sorry("room");
+    };
+    let Some(other_id) = find_profile(&db_pool, room_id, &who).await? else {
+        return res::sorry("profile");
+    };
+
+    can_send(&db_pool, &guests, &limits, &user_id, profile_id).await?;
+    send_dm(&db_pool, &tx, profile_id, other_id, &content).await?;
+    Ok(Redirect::to(&format!("/r/{room_id}/dm/{other_id}")).into_response())
+}
+
+#[debug_handler]
+pub(crate) async fn block(
+    State(db_pool): State<SqlitePool>,
+    caller: Option<Caller>,
+    Path((room_id, who)): Path<(Uuid, String)>,
+) -> AppResult<Response> {
+    can_write(&caller)?;
+    let Some(profile_id) = own_profile(&db_pool, caller, room_id).await? else {
+        return res::sorry("room");
+    };
+    let Some(other_id) = find_profile(&db_pool, room_id, &who).await? else {
+        return res::sorry("profile");
+    };
+
+    if other_id != profile_id {
+        block_profile(&db_pool, profile_id, other_id).await?;
+    }
+    Ok(Redirect::to(&format!("/r/{room_id}/dm/{other_id}")).into_response())
+}
+
+#[debug_handler]
+pub(crate) async fn unblock(
+    State(db_pool): State<SqlitePool>,
+    caller: Option<Caller>,
+    Path((room_id, who)): Path<(Uuid, String)>,
+) -> AppResult<Response> {
+    can_write(&caller)?;
+    let Some(profile_id) = own_profile(&db_pool, caller, room_id).await? else {
+        return res::sorry("room");
+    };
+    let Some(other_id) = find_profile(&db_pool, room_id, &who).await? else {
+        return res::sorry("profile");
+    };
+
+    unblock_profile(&db_pool, profile_id, other_id).await?;
+    Ok(Redirect::to(&format!("/r/{room_id}/dm/{other_id}")).into_response())
+}
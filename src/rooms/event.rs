@@ -0,0 +1,40 @@
+use uuid::Uuid;
+
+/// Sent only to a kicked profile, its sockets and streams hang up on it.
+const KICKED: &str = r#"{"type":"kicked"}"#;
+
+/// Something that happened in a room, fanned out to every socket and event
+/// stream in it.
+#[derive(Debug, Clone)]
+pub struct RoomEvent {
+    pub room_id: Uuid,
+    /// set for new messages, not for edits or deletes of older ones, so it can
+    /// be the SSE event id clients resume from
+    pub message_id: Option<Uuid>,
+    /// a rendered message, or a JSON object for everything else, see
+    /// [`super::Presence`]
+    pub data: String,
+    /// set for direct messages, only these two profiles' sockets get it
+    pub only_to: Option<[Uuid; 2]>,
+}
+
+impl RoomEvent {
+    /// Hangs up on every socket and stream `profile_id` has open in the room.
+    pub fn kick(room_id: Uuid, profile_id: Uuid) -> Self {
+        RoomEvent { room_id, message_id: None, data: KICKED.to_owned(), only_to: Some([profile_id; 2]) }
+    }
+
+    /// Whether a socket or stream opened as `profile_id` (`None` for
+    /// visitors) should get this event.
+    pub fn is_for(&self, profile_id: Option<Uuid>) -> bool {
+        match self.only_to {
+            Some(only_to) => profile_id.is_some_and(|profile_id| only_to.contains(&profile_id)),
+            None => true,
+        }
+    }
+
+    /// Whether this kicks `profile_id` out of the room.
+    pub fn kicks(&self, profile_id: Option<Uuid>) -> bool {
+        self.only_to.is_some() && self.data == KICKED && self.is_for(profile_id)
+    }
+}
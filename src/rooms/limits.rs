@@ -0,0 +1,159 @@
+use std::{collections::HashMap, net::IpAddr, sync::{Arc, Mutex}, time::{Duration, Instant}};
+
+use axum::{http::{header, StatusCode}, response::IntoResponse};
+use sqlx::SqlitePool;
+use uuid::Uuid;
+
+use crate::{AppError, AppResult};
+
+use super::can_moderate;
+
+/// A profile can send this many messages in a row...
+const BURST: f64 = 5.0;
+/// ...then one more each this long.
+const REFILL_EVERY: Duration = Duration::from_secs(1);
+/// Buckets are only tidied up once there are this many.
+const PRUNE_AT: usize = 1024;
+
+/// How often a profile may post in a room with slow mode on, with how the
+/// room is told. `0` turns it off.
+pub const SLOW_MODE: [(i64, &str); 5] = [
+    (0, "off"),
+    (5, "5 seconds"),
+    (30, "30 seconds"),
+    (60, "a minute"),
+    (5 * 60, "5 minutes"),
+];
+
+/// In-memory caps on how fast profiles send and how many sockets and event
+/// streams one address keeps open. `MAX_CONNECTIONS_PER_IP` in .env
+/// (default 16). The address is the peer's, so behind a reverse proxy it's
+/// the proxy's and everyone shares the one cap.
+#[derive(Clone)]
+pub struct Limits {
+    max_connections_per_ip: usize,
+    inner: Arc<Mutex<Inner>>,
+}
+
+#[derive(Default)]
+struct Inner {
+    buckets: HashMap<Uuid, Bucket>,
+    connections: HashMap<IpAddr, usize>,
+}
+
+struct Bucket {
+    tokens: f64,
+    at: Instant,
+}
+
+impl Bucket {
+    fn refill(&mut self, now: Instant) {
+        let refilled = (now - self.at).as_secs_f64() / REFILL_EVERY.as_secs_f64();
+        self.tokens = (self.tokens + refilled).min(BURST);
+        self.at = now;
+    }
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Limits::new(16)
+    }
+}
+
+/// A 429 saying how long to wait, as `Retry-After` and in the reason.
+pub(crate) fn too_soon(wait: Duration, reason: &str) -> AppError {
+    let secs = wait.as_secs_f64().ceil().max(1.0) as u64;
+    let reason = format!("{reason}, try again in {secs}s");
+    AppError(
+        anyhow::Error::msg(reason.clone()),
+        Box::new((StatusCode::TOO_MANY_REQUESTS, [(header::RETRY_AFTER, secs.to_string())], reason).into_response()),
+    )
+}
+
+impl Limits {
+    pub fn new(max_connections_per_ip: usize) -> Self {
+        Limits { max_connections_per_ip, inner: Arc::default() }
+    }
+
+    pub fn from_env() -> Self {
+        Limits::new(
+            dotenv::var("MAX_CONNECTIONS_PER_IP").ok()
+                .and_then(|value| value.parse().ok())
+                .unwrap_or(16)
+        )
+    }
+
+    /// Takes one message's worth from the profile's bucket, or refuses with
+    /// how long until there's one again.
+    pub fn take(&self, profile_id: Uuid) -> AppResult<()> {
+        let mut inner = self.inner.lock().unwrap();
+        let now = Instant::now();
+
+        if inner.buckets.len() >= PRUNE_AT {
+            // full buckets are the same as none
+            inner.buckets.retain(|_, bucket| { bucket.refill(now); bucket.tokens < BURST });
+        }
+
+        let bucket = inner.buckets.entry(profile_id).or_insert(Bucket { tokens: BURST, at: now });
+        bucket.refill(now);
+        if bucket.tokens < 1.0 {
+            let wait = REFILL_EVERY.mul_f64(1.0 - bucket.tokens);
+            return Err(too_soon(wait, "You're sending messages too fast"));
+        }
+        bucket.tokens -= 1.0;
+        Ok(())
+    }
+
+    /// Counts a connection from `ip` in for as long as the returned guard
+    /// lives, `None` if the address already has too many.
+    pub fn connect(&self, ip: IpAddr) -> Option<Connection> {
+        let mut inner = self.inner.lock().unwrap();
+        let connections = inner.connections.entry(ip).or_default();
+        if *connections >= self.max_connections_per_ip {
+            return None;
+        }
+        *connections += 1;
+        Some(Connection { inner: self.inner.clone(), ip })
+    }
+}
+
+/// One open socket or event stream, see [`Limits::connect`].
+pub struct Connection {
+    inner: Arc<Mutex<Inner>>,
+    ip: IpAddr,
+}
+
+impl Drop for Connection {
+    fn drop(&mut self) {
+        let mut inner = self.inner.lock().unwrap();
+        if let Some(connections) = inner.connections.get_mut(&self.ip) {
+            *connections -= 1;
+            if *connections == 0 {
+                inner.connections.remove(&self.ip);
+            }
+        }
+    }
+}
+
+/// How long `profile_id` still has to wait under the room's slow mode.
+/// Moderators don't.
+pub(super) async fn slow_mode_wait(db_pool: &SqlitePool, room_id: Uuid, profile_id: Uuid) -> AppResult<Option<Duration>> {
+    let row: Option<(String, Option<i64>)> = sqlx::query_as(
+        "SELECT profiles.user_id,
+            rooms.slow_mode_secs - (unixepoch() - (SELECT max(created_at) FROM messages WHERE room_id=rooms.uuid AND profile_id=profiles.uuid))
+        FROM profiles JOIN rooms ON rooms.uuid=profiles.room_id
+        WHERE profiles.uuid=? AND rooms.uuid=? AND rooms.slow_mode_secs IS NOT NULL"
+    )
+        .bind(profile_id.to_string())
+        .bind(room_id.to_string())
+        .fetch_optional(db_pool)
+        .await?;
+
+    let Some((user_id, Some(wait))) = row else {
+        return Ok(None);
+    };
+    if wait <= 0 || can_moderate(db_pool, &user_id, room_id).await? {
+        return Ok(None);
+    }
+    Ok(Some(Duration::from_secs(wait as u64)))
+}
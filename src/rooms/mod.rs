@@ -1,15 +1,79 @@
+mod access;
+mod automod;
+mod delete;
+mod directory;
+mod dm;
+mod event;
+mod limits;
+mod moderation;
 mod room;
-mod msg;
+pub(crate) mod msg;
 mod new;
+mod presence;
+mod read;
+mod report;
+mod retention;
+mod reveal;
+mod send;
+mod settings;
+mod sse;
+mod thread;
 mod ws;
 
-use axum::{routing::get, Router};
+use axum::{routing::{get, post}, Router};
 
 use crate::AppState;
 
+pub use access::{can_moderate, is_admin, is_banned, is_room_owner, room_access, RoomAccess};
+pub use automod::{add_rule, approve_held, check, held_messages, reject_held, remove_rule, rules, Held, Rule, RuleAction, RuleKind, Verdict};
+pub use delete::{delete_room, purge_due_rooms, room_reaper};
+pub use dm::{block_profile, find_profile, has_blocked, send_dm, unblock_profile};
+pub use event::RoomEvent;
+pub use limits::{Connection, Limits, SLOW_MODE};
+pub use moderation::{ban_profile, is_muted, kick_profile, mute_profile, unban_profile, unmute_profile, MUTE_FOR};
+pub(crate) use access::{can_send, member_profile, sender_profile};
+use access::owned_room;
+pub(crate) use automod::HELD;
+pub(crate) use moderation::moderation_html;
+pub use presence::Presence;
+pub use reveal::{accept_reveal, request_reveal, reveal_between, reveal_choices, withdraw_reveal, Reveal};
+pub use retention::{message_sweeper, sweep_expired, Retention, MAX_TTL_SECS};
+pub use read::{mark_read, mark_room_read, unread_count};
+pub use report::{all_reports, report, resolve_report, room_reports, Report, ReportAction, ReportReason, Reported};
+
 pub fn router() -> Router<AppState> {
     Router::new()
+        .route("/", get(directory::directory))
         .route("/new", get(new::new_room_page).post(new::new_room))
+        .route("/read", post(read::mark_all_read))
+        .route("/reports", get(report::admin_queue))
         .route("/{uuid}", get(room::room))
+        .route("/{uuid}/join", post(directory::join))
+        .route("/{uuid}/settings", get(settings::settings_page).post(settings::settings))
+        .route("/{uuid}/delete", get(delete::delete_page).post(delete::delete))
+        .route("/{uuid}/delete/cancel", post(delete::cancel_delete))
+        .route("/{uuid}/t/{mid}", get(thread::thread))
+        .route("/{uuid}/dm", get(dm::inbox))
+        .route("/{uuid}/dm/{who}", get(dm::conversation).post(dm::send))
+        .route("/{uuid}/dm/{who}/block", post(dm::block))
+        .route("/{uuid}/dm/{who}/unblock", post(dm::unblock))
+        .route("/{uuid}/dm/{who}/reveal", post(reveal::request))
+        .route("/{uuid}/dm/{who}/reveal/accept", post(reveal::accept))
+        .route("/{uuid}/dm/{who}/reveal/withdraw", post(reveal::withdraw))
+        .route("/{uuid}/mod/{who}/kick", post(moderation::kick))
+        .route("/{uuid}/mod/{who}/mute", post(moderation::mute))
+        .route("/{uuid}/mod/{who}/unmute", post(moderation::unmute))
+        .route("/{uuid}/mod/{who}/ban", post(moderation::ban))
+        .route("/{uuid}/mod/{who}/unban", post(moderation::unban))
+        .route("/{uuid}/report", get(report::report_page).post(report::send_report))
+        .route("/{uuid}/reports", get(report::room_queue))
+        .route("/{uuid}/reports/{id}", post(report::resolve))
+        .route("/{uuid}/automod", get(automod::automod_page))
+        .route("/{uuid}/automod/rules", post(automod::add))
+        .route("/{uuid}/automod/rules/{id}/delete", post(automod::remove))
+        .route("/{uuid}/automod/held/{id}/approve", post(automod::approve))
+        .route("/{uuid}/automod/held/{id}/reject", post(automod::reject))
         .route("/{uuid}/ws", get(ws::room_ws))
-}
\ No newline at end of file
+        .route("/{uuid}/events", get(sse::events))
+        .route("/{uuid}/messages", post(send::send))
+}
@@ -0,0 +1,265 @@
+use axum::{debug_handler, extract::{Path, State}, http::StatusCode, response::{IntoResponse, Redirect, Response}, Form};
+use serde::Deserialize;
+use sqlx::SqlitePool;
+use tokio::sync::broadcast;
+use tower_sessions::Session;
+use uuid::Uuid;
+
+use crate::{include_res, res, session::USER_ID, AppResult, AppState};
+
+use super::{can_moderate, find_profile, msg::{self, refuse}, RoomEvent};
+
+/// How long a profile can be muted for, with how the room is told.
+pub const MUTE_FOR: [(i64, &str); 3] = [
+    (10 * 60, "10 minutes"),
+    (60 * 60, "an hour"),
+    (24 * 60 * 60, "a day"),
+];
+
+/// The user and alias behind a profile a moderator may act on: in the room,
+/// and not the owner's.
+async fn target(db_pool: &SqlitePool, room_id: Uuid, profile_id: Uuid) -> AppResult<(String, String)> {
+    let row: Option<(String, String, bool)> = sqlx::query_as(
+        "SELECT profiles.user_id,alias,profiles.user_id IS rooms.owner_id FROM profiles
+        JOIN rooms ON rooms.uuid=profiles.room_id
+        WHERE profiles.uuid=? AND profiles.room_id=?"
+    )
+        .bind(profile_id.to_string())
+        .bind(room_id.to_string())
+        .fetch_optional(db_pool)
+        .await?;
+    match row {
+        Some((_, _, true)) => Err(refuse(StatusCode::FORBIDDEN, "That's the room's owner")),
+        Some((user_id, alias, false)) => Ok((user_id, alias)),
+        None => Err(refuse(StatusCode::NOT_FOUND, "No such profile")),
+    }
+}
+
+/// Hangs up on the profile's sockets and streams. They can come back, unless
+/// they're banned too.
+pub async fn kick_profile(db_pool: &SqlitePool, tx: &broadcast::Sender<RoomEvent>, room_id: Uuid, profile_id: Uuid) -> AppResult<()> {
+    let (_, alias) = target(db_pool, room_id, profile_id).await?;
+
+    let _ = tx.send(RoomEvent::kick(room_id, profile_id));
+    msg::send_system_msg(db_pool, tx, room_id, format!("{alias} was kicked")).await
+}
+
+/// Stops the profile posting for one of the [`MUTE_FOR`] choices.
+pub async fn mute_profile(db_pool: &SqlitePool, tx: &broadcast::Sender<RoomEvent>, room_id: Uuid, profile_id: Uuid, secs: i64) -> AppResult<()> {
+    let Some((_, label)) = MUTE_FOR.into_iter().find(|(choice, _)| *choice == secs) else {
+        return Err(refuse(StatusCode::BAD_REQUEST, "Mutes are for 10 minutes, an hour or a day"));
+    };
+    let (_, alias) = target(db_pool, room_id, profile_id).await?;
+
+    sqlx::query("UPDATE profiles SET muted_until=unixepoch()+? WHERE uuid=?")
+        .bind(secs)
+        .bind(profile_id.to_string())
+        .execute(db_pool)
+        .await?;
+    msg::send_system_msg(db_pool, tx, room_id, format!("{alias} was muted for {label}")).await
+}
+
+pub async fn unmute_profile(db_pool: &SqlitePool, tx: &broadcast::Sender<RoomEvent>, room_id: Uuid, profile_id: Uuid) -> AppResult<()> {
+    let (_, alias) = target(db_pool, room_id, profile_id).await?;
+
+    let unmuted = sqlx::query("UPDATE profiles SET muted_until=NULL WHERE uuid=? AND muted_until > unixepoch()")
+        .bind(profile_id.to_string())
+        .execute(db_pool)
+        .await?
+        .rows_affected();
+    if unmuted > 0 {
+        msg::send_system_msg(db_pool, tx, room_id, format!("{alias} can post again")).await?;
+    }
+    Ok(())
+}
+
+/// Whether the profile is muted right now.
+pub async fn is_muted(db_pool: &SqlitePool, profile_id: Uuid) -> AppResult<bool> {
+    Ok(
+        sqlx::query("SELECT 1 FROM profiles WHERE uuid=? AND muted_until > unixepoch()")
+            .bind(profile_id.to_string())
+            .fetch_optional(db_pool)
+            .await?
+            .is_some()
+    )
+}
+
+/// Bans the user behind the profile and kicks them and their bots. Their
+/// profile and messages stay, but [`super::room_access`] turns them away and
+/// they can't get a new profile in the room.
+pub async fn ban_profile(db_pool: &SqlitePool, tx: &broadcast::Sender<RoomEvent>, room_id: Uuid, profile_id: Uuid) -> AppResult<()> {
+    let (user_id, alias) = target(db_pool, room_id, profile_id).await?;
+
+    let banned = sqlx::query("INSERT INTO room_bans (room_id,user_id) VALUES (?,?) ON CONFLICT DO NOTHING")
+        .bind(room_id.to_string())
+        .bind(&user_id)
+        .execute(db_pool)
+        .await?
+        .rows_affected();
+    if banned == 0 {
+        return Ok(());
+    }
+
+    println!("banned u/{user_id} from {room_id}");
+    let _ = tx.send(RoomEvent::kick(room_id, profile_id));
+    // their bots lose the room with them
+    let bots: Vec<(String,)> = sqlx::query_as(
+        "SELECT profiles.uuid FROM profiles JOIN users ON users.id=profiles.user_id
+        WHERE users.bot_owner_id=? AND profiles.room_id=?"
+    )
+        .bind(&user_id)
+        .bind(room_id.to_string())
+        .fetch_all(db_pool)
+        .await?;
+    for (bot,) in bots {
+        let _ = tx.send(RoomEvent::kick(room_id, Uuid::parse_str(&bot)?));
+    }
+    msg::send_system_msg(db_pool, tx, room_id, format!("{alias} was banned")).await
+}
+
+pub async fn unban_profile(db_pool: &SqlitePool, tx: &broadcast::Sender<RoomEvent>, room_id: Uuid, profile_id: Uuid) -> AppResult<()> {
+    let (user_id, alias) = target(db_pool, room_id, profile_id).await?;
+
+    let unbanned = sqlx::query("DELETE FROM room_bans WHERE room_id=? AND user_id=?")
+        .bind(room_id.to_string())
+        .bind(&user_id)
+        .execute(db_pool)
+        .await?
+        .rows_affected();
+    if unbanned > 0 {
+        println!("unbanned u/{user_id} from {room_id}");
+        msg::send_system_msg(db_pool, tx, room_id, format!("{alias} was unbanned")).await?;
+    }
+    Ok(())
+}
+
+/// The moderation controls on a profile page, empty for anyone who can't
+/// moderate the room and on the owner's profile.
+pub(crate) async fn moderation_html(db_pool: &SqlitePool, user_id: &str, room_id: Uuid, profile_id: Uuid, csrf_token: &str) -> AppResult<String> {
+    if !can_moderate(db_pool, user_id, room_id).await? {
+        return Ok(String::new());
+    }
+
+    let Some((is_owner, is_banned)): Option<(bool, bool)> = sqlx::query_as(
+        "SELECT profiles.user_id IS rooms.owner_id,
+            EXISTS (SELECT 1 FROM room_bans WHERE room_bans.room_id=rooms.uuid AND room_bans.user_id=profiles.user_id)
+        FROM profiles JOIN rooms ON rooms.uuid=profiles.room_id
+        WHERE profiles.uuid=?"
+    )
+        .bind(profile_id.to_string())
+        .fetch_optional(db_pool)
+        .await?
+    else {
+        return Ok(String::new());
+    };
+    if is_owner {
+        return Ok(String::new());
+    }
+    let is_muted = is_muted(db_pool, profile_id).await?;
+
+    let mute_options: String = MUTE_FOR.into_iter()
+        .map(|(secs, label)| format!("<option value=\"{secs}\">for {label}</option>\n"))
+        .collect();
+    let show = |visible: bool| if visible { "" } else { "display: none;" };
+
+    Ok(
+        include_res!(str, "pages/rooms/moderation.html")
+            .replace("{csrf_token}", csrf_token)
+            .replace("{room_id}", &room_id.to_string())
+            .replace("{profile_id}", &profile_id.to_string())
+            .replace("{mute_options}", &mute_options)
+            .replace("{mute}", show(!is_muted && !is_banned))
+            .replace("{muted}", show(is_muted && !is_banned))
+            .replace("{ban}", show(!is_banned))
+            .replace("{banned}", show(is_banned))
+    )
+}
+
+#[derive(Deserialize)]
+pub(crate) struct MuteForm {
+    secs: i64,
+}
+
+/// Checks the caller may moderate the room and finds the profile, then hands
+/// it to `act` and goes back to the profile page.
+async fn mod_action(
+    db_pool: &SqlitePool,
+    session: &Session,
+    room_id: Uuid,
+    who: &str,
+    act: impl AsyncFnOnce(Uuid) -> AppResult<()>,
+) -> AppResult<Response> {
+    let Some(user_id) = session.get::<String>(USER_ID).await? else {
+        return res::sorry("room");
+    };
+    if !can_moderate(db_pool, &user_id, room_id).await? {
+        return res::sorry("room");
+    }
+    let Some(profile_id) = find_profile(db_pool, room_id, who).await? else {
+        return res::sorry("profile");
+    };
+
+    act(profile_id).await?;
+    Ok(Redirect::to(&format!("/p/{profile_id}")).into_response())
+}
+
+#[debug_handler(state = AppState)]
+pub(crate) async fn kick(
+    State(db_pool): State<SqlitePool>,
+    State(tx): State<broadcast::Sender<RoomEvent>>,
+    session: Session,
+    Path((room_id, who)): Path<(Uuid, String)>,
+) -> AppResult<Response> {
+    mod_action(&db_pool, &session, room_id, &who, async |profile_id| {
+        kick_profile(&db_pool, &tx, room_id, profile_id).await
+    }).await
+}
+
+#[debug_handler(state = AppState)]
+pub(crate) async fn mute(
+    State(db_pool): State<SqlitePool>,
+    State(tx): State<broadcast::Sender<RoomEvent>>,
+    session: Session,
+    Path((room_id, who)): Path<(Uuid, String)>,
+    Form(MuteForm { secs }): Form<MuteForm>,
+) -> AppResult<Response> {
+    mod_action(&db_pool, &session, room_id, &who, async |profile_id| {
+        mute_profile(&db_pool, &tx, room_id, profile_id, secs).await
+    }).await
+}
+
+#[debug_handler(state = AppState)]
+pub(crate) async fn unmute(
+    State(db_pool): State<SqlitePool>,
+    State(tx): State<broadcast::Sender<RoomEvent>>,
+    session: Session,
+    Path((room_id, who)): Path<(Uuid, String)>,
+) -> AppResult<Response> {
+    mod_action(&db_pool, &session, room_id, &who, async |profile_id| {
+        unmute_profile(&db_pool, &tx, room_id, profile_id).await
+    }).await
+}
+
+#[debug_handler(state = AppState)]
+pub(crate) async fn ban(
+    State(db_pool): State<SqlitePool>,
+    State(tx): State<broadcast::Sender<RoomEvent>>,
+    session: Session,
+    Path((room_id, who)): Path<(Uuid, String)>,
+) -> AppResult<Response> {
+    mod_action(&db_pool, &session, room_id, &who, async |profile_id| {
+        ban_profile(&db_pool, &tx, room_id, profile_id).await
+    }).await
+}
+
+#[debug_handler(state = AppState)]
+pub(crate) async fn unban(
+    State(db_pool): State<SqlitePool>,
+    State(tx): State<broadcast::Sender<RoomEvent>>,
+    session: Session,
+    Path((room_id, who)): Path<(Uuid, String)>,
+) -> AppResult<Response> {
+    mod_action(&db_pool, &session, room_id, &who, async |profile_id| {
+        unban_profile(&db_pool, &tx, room_id, profile_id).await
+    }).await
+}
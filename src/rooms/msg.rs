@@ -1,41 +1,233 @@
-use axum::response::{IntoResponse, Response};
+use axum::{http::StatusCode, response::IntoResponse};
 use serde::Deserialize;
 use sqlx::SqlitePool;
 use tokio::sync::broadcast;
 use uuid::Uuid;
 
-use crate::{include_res, AppResult};
+use crate::{include_res, res, AppError, AppResult};
+
+use super::{automod::{self, Verdict}, is_muted, limits::{self, too_soon}, retention::MAX_TTL_SECS, RoomEvent};
 
 #[derive(Deserialize)]
 pub(crate) struct SendMessageQuery {
-    reply_to_id: Option<Uuid>,
-    content: String,
+    pub(crate) reply_to_id: Option<Uuid>,
+    pub(crate) content: String,
+    /// the message disappears this long after it's sent, see [`super::retention`]
+    #[serde(default)]
+    pub(crate) ttl_secs: Option<i64>,
+}
+
+/// Keeps `reason` on the error too, sockets have no response to put it in.
+pub(super) fn refuse(status: StatusCode, reason: &str) -> AppError {
+    AppError(anyhow::Error::msg(reason.to_owned()), Box::new((status, reason.to_owned()).into_response()))
+}
+
+pub(super) async fn check_not_archived(db_pool: &SqlitePool, room_id: Uuid) -> AppResult<()> {
+    let (is_archived,): (bool,) = sqlx::query_as("SELECT is_archived FROM rooms WHERE uuid=?")
+        .bind(room_id.to_string())
+        .fetch_one(db_pool)
+        .await?;
+    if is_archived {
+        return Err(refuse(StatusCode::FORBIDDEN, "Room is archived"));
+    }
+    Ok(())
 }
 
+/// What became of a message that passed the checks.
+pub(crate) enum Sent {
+    Posted(Uuid),
+    /// kept back for a moderator by an automod rule, see [`super::automod`]
+    Held(Uuid),
+}
+
+/// Slow mode refuses with a `429` saying how long to wait in `Retry-After`.
 pub(crate) async fn send_msg(
     db_pool: &SqlitePool,
-    tx: &broadcast::Sender<String>,
+    tx: &broadcast::Sender<RoomEvent>,
     
     profile_id: Uuid,
     room_id: Uuid,
 
-    SendMessageQuery { reply_to_id, content }: SendMessageQuery,
-) -> AppResult<Response> {
+    SendMessageQuery { reply_to_id, content, ttl_secs }: SendMessageQuery,
+) -> AppResult<Sent> {
+    check_not_archived(db_pool, room_id).await?;
+
+    if is_muted(db_pool, profile_id).await? {
+        return Err(refuse(StatusCode::FORBIDDEN, "You're muted in this room for now"));
+    }
+
+    if let Some(wait) = limits::slow_mode_wait(db_pool, room_id, profile_id).await? {
+        return Err(too_soon(wait, "Slow mode is on"));
+    }
+
+    if ttl_secs.is_some_and(|ttl_secs| !(1..=MAX_TTL_SECS).contains(&ttl_secs)) {
+        return Err(refuse(StatusCode::BAD_REQUEST, "Messages can disappear after a second up to 30 days"));
+    }
+
+    if let Some(reply_to_id) = reply_to_id {
+        let exists = sqlx::query("SELECT 1 FROM messages WHERE id=? AND room_id=?")
+            .bind(reply_to_id.to_string())
+            .bind(room_id.to_string())
+            .fetch_optional(db_pool)
+            .await?
+            .is_some();
+        if !exists {
+            return Err(refuse(StatusCode::BAD_REQUEST, "No such message to reply to"));
+        }
+    }
+
+    let content = match automod::check(db_pool, room_id, profile_id, &content).await? {
+        Verdict::Pass(content) => content,
+        Verdict::Reject(reason) => return Err(refuse(StatusCode::BAD_REQUEST, &reason)),
+        Verdict::Hold(rule_id, content) => return Ok(Sent::Held(
+            automod::hold_msg(db_pool, room_id, profile_id, reply_to_id, &content, ttl_secs, rule_id).await?
+        )),
+    };
+
+    Ok(Sent::Posted(post_msg(db_pool, tx, profile_id, room_id, reply_to_id, content, ttl_secs).await?))
+}
+
+/// Stores and broadcasts a message that passed every check.
+pub(super) async fn post_msg(
+    db_pool: &SqlitePool,
+    tx: &broadcast::Sender<RoomEvent>,
+
+    profile_id: Uuid,
+    room_id: Uuid,
+    reply_to_id: Option<Uuid>,
+    content: String,
+    ttl_secs: Option<i64>,
+) -> AppResult<Uuid> {
     let id = Uuid::now_v7();
-    sqlx::query("INSERT INTO messages (id,room_id,profile_id,reply_to_id,content) values (?,?,?,?,?)")
+    sqlx::query("INSERT INTO messages (id,room_id,profile_id,reply_to_id,content,created_at,expires_at) values (?,?,?,?,?,unixepoch(),unixepoch()+?)")
         .bind(id.to_string())
         .bind(room_id.to_string())
         .bind(profile_id.to_string())
         .bind(reply_to_id.as_ref().map(Uuid::to_string))
         .bind(&content)
+        .bind(ttl_secs)
         .execute(db_pool)
         .await?;
 
-    let _ = tx.send(
-        msg_to_html(id, room_id, profile_id, reply_to_id, content, &db_pool).await?
-    );
+    let _ = tx.send(RoomEvent {
+        room_id,
+        message_id: Some(id),
+        data: msg_to_html(id, room_id, profile_id, reply_to_id, content, db_pool).await?,
+        only_to: None,
+    });
+
+    Ok(id)
+}
+
+/// Re-renders a message for everyone watching, after an edit or delete.
+/// Clients swap it in by id.
+async fn rebroadcast(db_pool: &SqlitePool, tx: &broadcast::Sender<RoomEvent>, room_id: Uuid, id: Uuid) -> AppResult<()> {
+    let (profile_id, reply_to_id, content): (String, Option<String>, String) =
+        sqlx::query_as("SELECT profile_id,reply_to_id,content FROM messages WHERE id=? AND room_id=?")
+            .bind(id.to_string())
+            .bind(room_id.to_string())
+            .fetch_one(db_pool)
+            .await?;
+
+    let reply_to_id = match reply_to_id {
+        Some(reply_to_id) => Some(Uuid::parse_str(&reply_to_id)?),
+        None => None,
+    };
+    let _ = tx.send(RoomEvent {
+        room_id,
+        message_id: None,
+        data: msg_to_html(id, room_id, Uuid::parse_str(&profile_id)?, reply_to_id, content, db_pool).await?,
+        only_to: None,
+    });
+    Ok(())
+}
+
+/// Changes the content of one of `profile_id`'s messages.
+pub(crate) async fn edit_msg(
+    db_pool: &SqlitePool,
+    tx: &broadcast::Sender<RoomEvent>,
+
+    profile_id: Uuid,
+    room_id: Uuid,
+    id: Uuid,
+    content: String,
+) -> AppResult<()> {
+    check_not_archived(db_pool, room_id).await?;
+
+    if is_muted(db_pool, profile_id).await? {
+        return Err(refuse(StatusCode::FORBIDDEN, "You're muted in this room for now"));
+    }
+
+    // there's nothing to hold an edit back as, so holding rules refuse it
+    let content = match automod::check(db_pool, room_id, profile_id, &content).await? {
+        Verdict::Pass(content) => content,
+        Verdict::Reject(reason) => return Err(refuse(StatusCode::BAD_REQUEST, &reason)),
+        Verdict::Hold(..) => return Err(refuse(StatusCode::BAD_REQUEST, "A moderator would have to approve that, send it as a new message instead")),
+    };
+
+    let edited = sqlx::query("UPDATE messages SET content=?,edited_at=unixepoch() WHERE id=? AND room_id=? AND profile_id=? AND deleted_at IS NULL")
+        .bind(&content)
+        .bind(id.to_string())
+        .bind(room_id.to_string())
+        .bind(profile_id.to_string())
+        .execute(db_pool)
+        .await?
+        .rows_affected();
+    if edited == 0 {
+        return Err(refuse(StatusCode::NOT_FOUND, "No such message of yours"));
+    }
 
-    Ok(().into_response())
+    rebroadcast(db_pool, tx, room_id, id).await
+}
+
+/// Blanks a message but keeps its row, so replies still have something to point at.
+/// Whoever calls this checks the caller may.
+pub(crate) async fn delete_msg(
+    db_pool: &SqlitePool,
+    tx: &broadcast::Sender<RoomEvent>,
+
+    room_id: Uuid,
+    id: Uuid,
+) -> AppResult<()> {
+    let deleted = sqlx::query("UPDATE messages SET content='',deleted_at=unixepoch() WHERE id=? AND room_id=? AND deleted_at IS NULL")
+        .bind(id.to_string())
+        .bind(room_id.to_string())
+        .execute(db_pool)
+        .await?
+        .rows_affected();
+    if deleted == 0 {
+        return Err(refuse(StatusCode::NOT_FOUND, "No such message"));
+    }
+
+    rebroadcast(db_pool, tx, room_id, id).await
+}
+
+/// Posts a notice from the room itself, e.g. "room renamed to ...".
+/// System messages are stored under the nil profile.
+pub(crate) async fn send_system_msg(
+    db_pool: &SqlitePool,
+    tx: &broadcast::Sender<RoomEvent>,
+
+    room_id: Uuid,
+    content: String,
+) -> AppResult<()> {
+    let id = Uuid::now_v7();
+    sqlx::query("INSERT INTO messages (id,room_id,profile_id,reply_to_id,content,created_at) values (?,?,?,NULL,?,unixepoch())")
+        .bind(id.to_string())
+        .bind(room_id.to_string())
+        .bind(Uuid::nil().to_string())
+        .bind(&content)
+        .execute(db_pool)
+        .await?;
+
+    let _ = tx.send(RoomEvent {
+        room_id,
+        message_id: Some(id),
+        data: msg_to_html(id, room_id, Uuid::nil(), None, content, db_pool).await?,
+        only_to: None,
+    });
+
+    Ok(())
 }
 
 pub(crate) async fn msg_to_html(
@@ -47,39 +239,131 @@ pub(crate) async fn msg_to_html(
 
     db_pool: &SqlitePool,
 ) -> AppResult<String> {
+    if profile_id.is_nil() {
+        return Ok(
+            include_res!(str, "pages/rooms/system_message.html")
+                .replace("{id}", &id.to_string())
+                .replace("{content}", &res::escape(&content))
+        );
+    }
+
     let (handle, alias): (String, String) =
         sqlx::query_as("SELECT handle,alias FROM profiles WHERE uuid=?")
-            .bind(&profile_id.to_string())
+            .bind(profile_id.to_string())
             .fetch_optional(db_pool)
             .await?
             .unwrap_or(("?".to_owned(), "Anonymous".to_owned()));
 
+    let (edited_at, deleted_at): (Option<i64>, Option<i64>) =
+        sqlx::query_as("SELECT edited_at,deleted_at FROM messages WHERE id=?")
+            .bind(id.to_string())
+            .fetch_optional(db_pool)
+            .await?
+            .unwrap_or_default();
+
     let mut content_html = String::new();
-    pulldown_cmark::html::push_html(&mut content_html, pulldown_cmark::Parser::new(&content));
+    if deleted_at.is_some() {
+        content_html += "<p class=\"msg-deleted\">message deleted</p>";
+    } else {
+        pulldown_cmark::html::push_html(&mut content_html, pulldown_cmark::Parser::new(&content));
+    }
+
+    let (reply_count,): (i64,) =
+        sqlx::query_as("SELECT count(*) FROM messages WHERE reply_to_id=? AND room_id=?")
+            .bind(id.to_string())
+            .bind(room_id.to_string())
+            .fetch_one(db_pool)
+            .await?;
+
+    // the message replied to may have expired since
+    let reply_to = match reply_to_id {
+        Some(reply_to_id) => Some(
+            sqlx::query_scalar::<_, String>("SELECT content FROM messages WHERE id=? AND room_id=?")
+                .bind(reply_to_id.to_string())
+                .bind(room_id.to_string())
+                .fetch_optional(db_pool)
+                .await?
+                .map_or_else(|| "<i>message expired</i>".to_owned(), |reply_to| res::escape(&reply_to))
+        ),
+        None => None,
+    };
 
-    let mut message = include_res!(str, "pages/rooms/message.html")
-        .replace("{alias}", &alias)
-        .replace("{handle}", &handle)
+    let message = include_res!(str, "pages/rooms/message.html")
+        .replace("{room_id}", &room_id.to_string())
+        .replace("{reply_count}", &reply_count.to_string())
+        .replace("{edited}", if edited_at.is_some() && deleted_at.is_none() { "(edited)" } else { "" })
         .replace("{id}", &id.to_string())
         .replace("{profile_id}", &profile_id.to_string())
+        .replace("{reply_to_id}", &reply_to_id.map(|id| id.to_string()).unwrap_or_default())
+        // what people wrote goes in last, so it can't fill in the placeholders
+        .replace("{alias}", &res::escape(&alias))
+        .replace("{handle}", &res::escape(&handle))
+        .replace("{reply_to}", &reply_to.unwrap_or_default())
         .replace("{content}", &content_html);
 
-    if let Some(reply_to_id) = reply_to_id {
-        let (reply_to,): (String,) =
-            sqlx::query_as("SELECT content FROM messages WHERE id=? AND room_id=?")
-                .bind(reply_to_id.to_string())
-                .bind(room_id.to_string())
-                .fetch_one(db_pool)
-                .await?;
+    Ok(message)
+}
 
-        message = message
-            .replace("{reply_to_id}", &reply_to_id.to_string())
-            .replace("{reply_to}", &reply_to);
-    } else {
-        message = message
-            .replace("{reply_to_id}", "")
-            .replace("{reply_to}", "");
+/// Renders `(id, profile_id, reply_to_id, content)` rows as fetched from `messages`.
+pub(crate) async fn rows_to_html(
+    rows: Vec<(String, String, Option<String>, String)>,
+    room_id: Uuid,
+
+    db_pool: &SqlitePool,
+) -> AppResult<String> {
+    let mut messages = String::new();
+    for (id, profile_id, reply_to_id, content) in rows {
+        messages += &msg_to_html(
+            Uuid::parse_str(&id)?,
+            room_id, 
+            Uuid::parse_str(&profile_id)?,
+            match reply_to_id {
+                Some(x) => Some(Uuid::parse_str(&x)?),
+                None => None,
+            },
+            content, 
+            db_pool
+        ).await?;
     }
+    Ok(messages)
+}
 
-    Ok(message)
-}
\ No newline at end of file
+/// At most this many missed messages are replayed when a client resumes.
+const REPLAY_LIMIT: i64 = 500;
+
+/// Messages sent after `since`, oldest first, for clients resuming a socket or
+/// event stream. Edits and deletes in the meantime aren't replayed. If `since`
+/// has expired since, ids are v7 and go by when they were sent instead.
+pub(crate) async fn missed_msgs(db_pool: &SqlitePool, room_id: Uuid, since: Uuid) -> AppResult<Vec<RoomEvent>> {
+    let rows: Vec<(String, String, Option<String>, String)> = sqlx::query_as(
+        "SELECT id,profile_id,reply_to_id,content FROM messages
+        WHERE room_id=?1 AND CASE
+            WHEN EXISTS (SELECT 1 FROM messages WHERE id=?2 AND room_id=?1)
+                THEN (created_at,id) > (SELECT created_at,id FROM messages WHERE id=?2 AND room_id=?1)
+            ELSE id > ?2
+        END
+        ORDER BY created_at,id
+        LIMIT ?3"
+    )
+        .bind(room_id.to_string())
+        .bind(since.to_string())
+        .bind(REPLAY_LIMIT)
+        .fetch_all(db_pool)
+        .await?;
+
+    let mut events = Vec::new();
+    for (id, profile_id, reply_to_id, content) in rows {
+        let id = Uuid::parse_str(&id)?;
+        let reply_to_id = match reply_to_id {
+            Some(reply_to_id) => Some(Uuid::parse_str(&reply_to_id)?),
+            None => None,
+        };
+        events.push(RoomEvent {
+            room_id,
+            message_id: Some(id),
+            data: msg_to_html(id, room_id, Uuid::parse_str(&profile_id)?, reply_to_id, content, db_pool).await?,
+            only_to: None,
+        });
+    }
+    Ok(events)
+}
@@ -5,7 +5,7 @@ use sqlx::SqlitePool;
 use tower_sessions::Session;
 use uuid::Uuid;
 
-use crate::{include_res, session::USER_ID, AppResult};
+use crate::{auth, include_res, session::USER_ID, AppResult};
 
 #[derive(Debug, Deserialize)]
 pub(crate) struct NewRoomQuery {
@@ -15,7 +15,6 @@ pub(crate) struct NewRoomQuery {
 
 #[debug_handler]
 pub(crate) async fn new_room_page(
-    State(db_pool): State<SqlitePool>,
     session: Session,
 ) -> AppResult<Response> {
     if session.get::<String>(USER_ID).await?.is_none() {
@@ -24,6 +23,7 @@ pub(crate) async fn new_room_page(
 
     Ok(Html(
         include_res!(str, "pages/rooms/new.html")
+        .replace("{csrf_token}", &auth::csrf_token(&session).await?)
     ).into_response())
 }
 
@@ -34,20 +34,29 @@ pub(crate) async fn new_room(
 
     Form(NewRoomQuery { name, is_public }): Form<NewRoomQuery>,
 ) -> AppResult<Response> {
-    if session.get::<String>(USER_ID).await?.is_none() {
+    let Some(user_id) = session.get::<String>(USER_ID).await? else {
         return Err((
             StatusCode::UNAUTHORIZED,
             "Sign in to use this feature"
         ).into_response().into());
+    };
+
+    if auth::is_guest(&db_pool, &user_id).await? {
+        return Err((
+            StatusCode::FORBIDDEN,
+            "Guests can't make rooms, sign in to keep your profiles and make one"
+        ).into_response().into());
     }
 
     let uuid = Uuid::now_v7();
-    sqlx::query("INSERT INTO rooms (uuid,name,is_public) values (?,?,?)")
+    sqlx::query("INSERT INTO rooms (uuid,name,is_public,owner_id,created_at) values (?,?,?,?,unixepoch())")
         .bind(uuid.to_string())
         .bind(&name)
         .bind(is_public)
+        .bind(&user_id)
         .execute(&db_pool)
         .await?;
+    auth::create_profile(&db_pool, &user_id, &uuid.to_string()).await?;
 
     Ok(Redirect::to(
         &format!("/r/{uuid}")
@@ -0,0 +1,84 @@
+use std::{collections::HashMap, sync::{Arc, Mutex}, time::{Duration, Instant}};
+
+use serde_json::json;
+use uuid::Uuid;
+
+use super::RoomEvent;
+
+/// Typing events go out at most this often per profile.
+const TYPING_EVERY: Duration = Duration::from_secs(3);
+
+/// Who has a socket open in which room, by profile. Only aliases ever leave
+/// this, never user ids.
+///
+/// Changes go out to the room as JSON events:
+/// `{"type": "join" | "leave" | "typing", "profile_id", "alias"}`.
+#[derive(Clone, Default)]
+pub struct Presence(Arc<Mutex<HashMap<Uuid, HashMap<Uuid, Here>>>>);
+
+struct Here {
+    alias: String,
+    /// one profile can have several tabs open
+    sockets: usize,
+    last_typing: Option<Instant>,
+}
+
+fn event(kind: &str, room_id: Uuid, profile_id: Uuid, alias: &str) -> RoomEvent {
+    RoomEvent {
+        room_id,
+        message_id: None,
+        data: json!({ "type": kind, "profile_id": profile_id, "alias": alias }).to_string(),
+        only_to: None,
+    }
+}
+
+impl Presence {
+    /// Counts a socket in, the join event is only for the profile's first one.
+    pub fn join(&self, room_id: Uuid, profile_id: Uuid, alias: &str) -> Option<RoomEvent> {
+        let mut rooms = self.0.lock().unwrap();
+        let here = rooms.entry(room_id).or_default()
+            .entry(profile_id)
+            .or_insert_with(|| Here { alias: alias.to_owned(), sockets: 0, last_typing: None });
+        here.sockets += 1;
+        (here.sockets == 1).then(|| event("join", room_id, profile_id, alias))
+    }
+
+    /// Counts a socket out, the leave event is only for the profile's last one.
+    pub fn leave(&self, room_id: Uuid, profile_id: Uuid) -> Option<RoomEvent> {
+        let mut rooms = self.0.lock().unwrap();
+        let profiles = rooms.get_mut(&room_id)?;
+        let here = profiles.get_mut(&profile_id)?;
+        here.sockets -= 1;
+        if here.sockets > 0 {
+            return None;
+        }
+
+        let here = profiles.remove(&profile_id)?;
+        if profiles.is_empty() {
+            rooms.remove(&room_id);
+        }
+        Some(event("leave", room_id, profile_id, &here.alias))
+    }
+
+    /// A typing event, unless this profile had one very recently.
+    pub fn typing(&self, room_id: Uuid, profile_id: Uuid) -> Option<RoomEvent> {
+        let mut rooms = self.0.lock().unwrap();
+        let here = rooms.get_mut(&room_id)?.get_mut(&profile_id)?;
+        let now = Instant::now();
+        if here.last_typing.is_some_and(|last| now - last < TYPING_EVERY) {
+            return None;
+        }
+        here.last_typing = Some(now);
+        Some(event("typing", room_id, profile_id, &here.alias))
+    }
+
+    /// `(profile_id, alias)` of everyone here, by alias.
+    pub fn snapshot(&self, room_id: Uuid) -> Vec<(Uuid, String)> {
+        let rooms = self.0.lock().unwrap();
+        let mut here: Vec<_> = rooms.get(&room_id)
+            .map(|profiles| profiles.iter().map(|(id, here)| (*id, here.alias.clone())).collect())
+            .unwrap_or_default();
+        here.sort_by(|a, b| a.1.cmp(&b.1));
+        here
+    }
+}
@@ -0,0 +1,95 @@
+use axum::{debug_handler, extract::State, response::{IntoResponse, Redirect, Response}};
+use sqlx::SqlitePool;
+use tower_sessions::Session;
+use uuid::Uuid;
+
+use crate::{session::USER_ID, AppResult};
+
+/// Moves a profile's read marker up to `message_id`, never back, and only to
+/// messages in its own room.
+pub async fn mark_read(db_pool: &SqlitePool, profile_id: Uuid, message_id: Uuid) -> AppResult<()> {
+    sqlx::query(
+        "UPDATE profiles SET last_read_id=?2
+        WHERE uuid=?1
+            AND EXISTS (SELECT 1 FROM messages WHERE id=?2 AND room_id=profiles.room_id)
+            AND (last_read_id IS NULL
+                OR (SELECT created_at,id FROM messages WHERE id=?2) > (SELECT created_at,id FROM messages WHERE id=profiles.last_read_id))"
+    )
+        .bind(profile_id.to_string())
+        .bind(message_id.to_string())
+        .execute(db_pool)
+        .await?;
+    Ok(())
+}
+
+/// Marks everything in the profile's room as read.
+pub async fn mark_room_read(db_pool: &SqlitePool, profile_id: Uuid) -> AppResult<()> {
+    sqlx::query(
+        "UPDATE profiles SET last_read_id=coalesce(
+            (SELECT id FROM messages WHERE room_id=profiles.room_id ORDER BY created_at DESC, id DESC LIMIT 1),
+            last_read_id
+        )
+        WHERE uuid=?"
+    )
+        .bind(profile_id.to_string())
+        .execute(db_pool)
+        .await?;
+    Ok(())
+}
+
+/// Messages from others after the profile's read marker.
+pub async fn unread_count(db_pool: &SqlitePool, profile_id: Uuid) -> AppResult<i64> {
+    Ok(sqlx::query_scalar(
+        "SELECT count(*) FROM messages, profiles
+        WHERE profiles.uuid=?1
+            AND messages.room_id=profiles.room_id
+            AND messages.profile_id!=profiles.uuid
+            AND messages.deleted_at IS NULL
+            AND (profiles.last_read_id IS NULL
+                OR (messages.created_at,messages.id) > (SELECT created_at,id FROM messages WHERE id=profiles.last_read_id))"
+    )
+        .bind(profile_id.to_string())
+        .fetch_one(db_pool)
+        .await?)
+}
+
+/// The first message after the profile's read marker, where the room page
+/// puts its "new messages" divider.
+pub(crate) async fn first_unread(db_pool: &SqlitePool, profile_id: Uuid) -> AppResult<Option<Uuid>> {
+    let id: Option<String> = sqlx::query_scalar(
+        "SELECT messages.id FROM messages, profiles
+        WHERE profiles.uuid=?1
+            AND messages.room_id=profiles.room_id
+            AND (profiles.last_read_id IS NULL
+                OR (messages.created_at,messages.id) > (SELECT created_at,id FROM messages WHERE id=profiles.last_read_id))
+        ORDER BY messages.created_at, messages.id
+        LIMIT 1"
+    )
+        .bind(profile_id.to_string())
+        .fetch_optional(db_pool)
+        .await?;
+    Ok(match id {
+        Some(id) => Some(Uuid::parse_str(&id)?),
+        None => None,
+    })
+}
+
+#[debug_handler]
+pub(crate) async fn mark_all_read(
+    State(db_pool): State<SqlitePool>,
+    session: Session,
+) -> AppResult<Response> {
+    let Some(user_id) = session.get::<String>(USER_ID).await? else {
+        return Ok(Redirect::to("/login").into_response());
+    };
+
+    let profile_ids: Vec<String> = sqlx::query_scalar("SELECT uuid FROM profiles WHERE user_id=?")
+        .bind(&user_id)
+        .fetch_all(&db_pool)
+        .await?;
+    for profile_id in profile_ids {
+        mark_room_read(&db_pool, Uuid::parse_str(&profile_id)?).await?;
+    }
+
+    Ok(Redirect::to("/").into_response())
+}
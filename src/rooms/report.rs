@@ -0,0 +1,480 @@
+use axum::{debug_handler, extract::{Path, Query, State}, http::StatusCode, response::{Html, IntoResponse, Redirect, Response}, Form};
+use serde::Deserialize;
+use sqlx::SqlitePool;
+use tokio::sync::broadcast;
+use tower_sessions::Session;
+use uuid::Uuid;
+
+use crate::{auth, include_res, res, session::USER_ID, AppResult, AppState};
+
+use super::{ban_profile, can_moderate, is_admin, msg::{self, refuse}, mute_profile, room_access, RoomEvent, MUTE_FOR};
+
+/// Notes are for a few sentences of context, not essays.
+const MAX_NOTE: usize = 1000;
+
+/// Why something was reported.
+#[derive(Debug, Clone, Copy, Default, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ReportReason {
+    Spam,
+    Harassment,
+    Hate,
+    Sexual,
+    #[default]
+    Other,
+}
+
+impl ReportReason {
+    pub const ALL: [ReportReason; 5] = [ReportReason::Spam, ReportReason::Harassment, ReportReason::Hate, ReportReason::Sexual, ReportReason::Other];
+
+    pub fn from_slug(slug: &str) -> Self {
+        Self::ALL.into_iter().find(|reason| reason.slug() == slug).unwrap_or_default()
+    }
+
+    pub fn slug(self) -> &'static str {
+        use ReportReason::*;
+        match self {
+            Spam => "spam",
+            Harassment => "harassment",
+            Hate => "hate",
+            Sexual => "sexual",
+            Other => "other",
+        }
+    }
+
+    pub fn label(self) -> &'static str {
+        use ReportReason::*;
+        match self {
+            Spam => "Spam or flooding",
+            Harassment => "Harassment or threats",
+            Hate => "Hate speech",
+            Sexual => "Unwanted sexual content",
+            Other => "Something else",
+        }
+    }
+}
+
+/// What a moderator does about a report.
+#[derive(Debug, Clone, Copy, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ReportAction {
+    Dismiss,
+    /// only for reported messages
+    Delete,
+    /// for an hour, see [`MUTE_FOR`]
+    Mute,
+    Ban,
+}
+
+impl ReportAction {
+    fn resolution(self) -> &'static str {
+        use ReportAction::*;
+        match self {
+            Dismiss => "dismissed",
+            Delete => "deleted",
+            Mute => "muted",
+            Ban => "banned",
+        }
+    }
+}
+
+/// What's being reported.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Reported {
+    Profile(Uuid),
+    Message(Uuid),
+}
+
+/// An open report, as a moderation queue shows it.
+#[derive(Debug, Clone)]
+pub struct Report {
+    pub id: Uuid,
+    pub room_id: Uuid,
+    pub profile_id: Uuid,
+    pub alias: String,
+    pub message_id: Option<Uuid>,
+    /// what the message says now, if a message was reported and it's still there
+    pub content: Option<String>,
+    pub reason: ReportReason,
+    pub note: String,
+    pub created_at: i64,
+    /// the reporting user, only ever set for admins, see [`all_reports`]
+    pub reporter_id: Option<String>,
+}
+
+/// Files a report by `reporter_id`, who must not be the one reported.
+/// Reporting the same thing twice while the first is open is refused.
+pub async fn report(
+    db_pool: &SqlitePool,
+    reporter_id: &str,
+    room_id: Uuid,
+    reported: Reported,
+    reason: ReportReason,
+    note: &str,
+) -> AppResult<Uuid> {
+    if note.chars().count() > MAX_NOTE {
+        return Err(refuse(StatusCode::BAD_REQUEST, "Keep the note under 1000 characters"));
+    }
+
+    let (profile_id, message_id) = match reported {
+        Reported::Profile(profile_id) => (Some(profile_id.to_string()), None),
+        Reported::Message(message_id) => (
+            // system messages have no profile to report
+            sqlx::query_scalar("SELECT profile_id FROM messages WHERE id=? AND room_id=? AND profile_id!=?")
+                .bind(message_id.to_string())
+                .bind(room_id.to_string())
+                .bind(Uuid::nil().to_string())
+                .fetch_optional(db_pool)
+                .await?,
+            Some(message_id.to_string()),
+        ),
+    };
+    let Some(profile_id) = profile_id else {
+        return Err(refuse(StatusCode::NOT_FOUND, "No such message"));
+    };
+
+    let Some((user_id,)): Option<(String,)> = sqlx::query_as("SELECT user_id FROM profiles WHERE uuid=? AND room_id=?")
+        .bind(&profile_id)
+        .bind(room_id.to_string())
+        .fetch_optional(db_pool)
+        .await?
+    else {
+        return Err(refuse(StatusCode::NOT_FOUND, "No such profile"));
+    };
+    if user_id == reporter_id {
+        return Err(refuse(StatusCode::BAD_REQUEST, "That's you"));
+    }
+
+    let already = sqlx::query("SELECT 1 FROM reports WHERE reporter_id=? AND profile_id=? AND message_id IS ? AND resolved_at IS NULL")
+        .bind(reporter_id)
+        .bind(&profile_id)
+        .bind(&message_id)
+        .fetch_optional(db_pool)
+        .await?
+        .is_some();
+    if already {
+        return Err(refuse(StatusCode::CONFLICT, "You already reported this, a moderator will look at it"));
+    }
+
+    let id = Uuid::now_v7();
+    sqlx::query("INSERT INTO reports (id,room_id,reporter_id,profile_id,message_id,reason,note,created_at) VALUES (?,?,?,?,?,?,?,unixepoch())")
+        .bind(id.to_string())
+        .bind(room_id.to_string())
+        .bind(reporter_id)
+        .bind(&profile_id)
+        .bind(&message_id)
+        .bind(reason.slug())
+        .bind(note)
+        .execute(db_pool)
+        .await?;
+    Ok(id)
+}
+
+/// id, room_id, profile_id, alias, message_id, content, reason, note, created_at, reporter_id
+type ReportRow = (String, String, String, String, Option<String>, Option<String>, String, String, i64, String);
+
+async fn open_reports(db_pool: &SqlitePool, room_id: Option<Uuid>, with_reporter: bool) -> AppResult<Vec<Report>> {
+    let rows: Vec<ReportRow> = sqlx::query_as(
+        "SELECT reports.id,reports.room_id,reports.profile_id,profiles.alias,reports.message_id,
+            (SELECT content FROM messages WHERE id=reports.message_id AND room_id=reports.room_id AND deleted_at IS NULL),
+            reason,note,reports.created_at,reporter_id
+        FROM reports JOIN profiles ON profiles.uuid=reports.profile_id
+        WHERE resolved_at IS NULL AND (?1 IS NULL OR reports.room_id=?1)
+        ORDER BY reports.created_at,reports.id"
+    )
+        .bind(room_id.map(|room_id| room_id.to_string()))
+        .fetch_all(db_pool)
+        .await?;
+
+    let mut reports = Vec::new();
+    for (id, room_id, profile_id, alias, message_id, content, reason, note, created_at, reporter_id) in rows {
+        reports.push(Report {
+            id: Uuid::parse_str(&id)?,
+            room_id: Uuid::parse_str(&room_id)?,
+            profile_id: Uuid::parse_str(&profile_id)?,
+            alias,
+            message_id: match message_id {
+                Some(message_id) => Some(Uuid::parse_str(&message_id)?),
+                None => None,
+            },
+            content,
+            reason: ReportReason::from_slug(&reason),
+            note,
+            created_at,
+            reporter_id: with_reporter.then_some(reporter_id),
+        });
+    }
+    Ok(reports)
+}
+
+/// A room's open reports, oldest first, without who made them.
+pub async fn room_reports(db_pool: &SqlitePool, room_id: Uuid) -> AppResult<Vec<Report>> {
+    open_reports(db_pool, Some(room_id), false).await
+}
+
+/// Every room's open reports, with who made them. Admins only.
+pub async fn all_reports(db_pool: &SqlitePool) -> AppResult<Vec<Report>> {
+    open_reports(db_pool, None, true).await
+}
+
+/// Acts on an open report and closes it. Whoever calls this checks the
+/// caller may moderate the room.
+pub async fn resolve_report(
+    db_pool: &SqlitePool,
+    tx: &broadcast::Sender<RoomEvent>,
+
+    room_id: Uuid,
+    id: Uuid,
+    action: ReportAction,
+) -> AppResult<()> {
+    let Some((profile_id, message_id)): Option<(String, Option<String>)> =
+        sqlx::query_as("SELECT profile_id,message_id FROM reports WHERE id=? AND room_id=? AND resolved_at IS NULL")
+            .bind(id.to_string())
+            .bind(room_id.to_string())
+            .fetch_optional(db_pool)
+            .await?
+    else {
+        return Err(refuse(StatusCode::NOT_FOUND, "No such report, it may have been handled already"));
+    };
+    let profile_id = Uuid::parse_str(&profile_id)?;
+
+    match action {
+        ReportAction::Dismiss => (),
+        ReportAction::Delete => {
+            let Some(message_id) = message_id else {
+                return Err(refuse(StatusCode::BAD_REQUEST, "Only messages can be deleted"));
+            };
+            msg::delete_msg(db_pool, tx, room_id, Uuid::parse_str(&message_id)?).await?;
+        },
+        ReportAction::Mute => mute_profile(db_pool, tx, room_id, profile_id, MUTE_FOR[1].0).await?,
+        ReportAction::Ban => ban_profile(db_pool, tx, room_id, profile_id).await?,
+    }
+
+    sqlx::query("UPDATE reports SET resolved_at=unixepoch(),resolution=? WHERE id=?")
+        .bind(action.resolution())
+        .bind(id.to_string())
+        .execute(db_pool)
+        .await?;
+    Ok(())
+}
+
+/// How many reports a room has waiting.
+pub(crate) async fn open_report_count(db_pool: &SqlitePool, room_id: Uuid) -> AppResult<i64> {
+    Ok(
+        sqlx::query_scalar("SELECT count(*) FROM reports WHERE room_id=? AND resolved_at IS NULL")
+            .bind(room_id.to_string())
+            .fetch_one(db_pool)
+            .await?
+    )
+}
+
+#[derive(Deserialize)]
+pub(crate) struct ReportQuery {
+    profile: Option<Uuid>,
+    message: Option<Uuid>,
+}
+
+impl ReportQuery {
+    fn reported(&self) -> Option<Reported> {
+        match (self.message, self.profile) {
+            (Some(message_id), _) => Some(Reported::Message(message_id)),
+            (None, Some(profile_id)) => Some(Reported::Profile(profile_id)),
+            (None, None) => None,
+        }
+    }
+}
+
+#[debug_handler]
+pub(crate) async fn report_page(
+    State(db_pool): State<SqlitePool>,
+    session: Session,
+    Path(room_id): Path<Uuid>,
+    Query(query): Query<ReportQuery>,
+) -> AppResult<Response> {
+    let Some(user_id) = session.get::<String>(USER_ID).await? else {
+        return res::sorry("room");
+    };
+    if room_access(&db_pool, Some(&user_id), room_id).await?.is_none() {
+        return res::sorry("room");
+    }
+    let Some(reported) = query.reported() else {
+        return res::sorry("report");
+    };
+
+    let what: Option<(String, Option<String>)> = match reported {
+        Reported::Profile(profile_id) => sqlx::query_as("SELECT alias,NULL FROM profiles WHERE uuid=? AND room_id=?")
+            .bind(profile_id.to_string()),
+        Reported::Message(message_id) => sqlx::query_as(
+            "SELECT alias,content FROM messages JOIN profiles ON profiles.uuid=messages.profile_id
+            WHERE messages.id=? AND messages.room_id=?"
+        )
+            .bind(message_id.to_string()),
+    }
+        .bind(room_id.to_string())
+        .fetch_optional(&db_pool)
+        .await?;
+    let Some((alias, content)) = what else {
+        return res::sorry("report");
+    };
+
+    let reasons: String = ReportReason::ALL.into_iter()
+        .map(|reason| format!("<option value=\"{}\">{}</option>\n", reason.slug(), reason.label()))
+        .collect();
+    Ok(Html(
+        include_res!(str, "pages/rooms/report.html")
+            .replace("{csrf_token}", &auth::csrf_token(&session).await?)
+            .replace("{room_id}", &room_id.to_string())
+            .replace("{reported}", &match reported {
+                Reported::Profile(profile_id) => format!("<input name=\"profile\" type=\"hidden\" value=\"{profile_id}\"/>"),
+                Reported::Message(message_id) => format!("<input name=\"message\" type=\"hidden\" value=\"{message_id}\"/>"),
+            })
+            .replace("{reasons}", &reasons)
+            .replace("{alias}", &res::escape(&alias))
+            .replace("{content}", &res::escape(&content.unwrap_or_default()))
+    ).into_response())
+}
+
+#[derive(Deserialize)]
+pub(crate) struct ReportForm {
+    profile: Option<Uuid>,
+    message: Option<Uuid>,
+    reason: ReportReason,
+    #[serde(default)]
+    note: String,
+}
+
+#[debug_handler]
+pub(crate) async fn send_report(
+    State(db_pool): State<SqlitePool>,
+    session: Session,
+    Path(room_id): Path<Uuid>,
+    Form(ReportForm { profile, message, reason, note }): Form<ReportForm>,
+) -> AppResult<Response> {
+    let Some(user_id) = session.get::<String>(USER_ID).await? else {
+        return res::sorry("room");
+    };
+    if room_access(&db_pool, Some(&user_id), room_id).await?.is_none() {
+        return res::sorry("room");
+    }
+    let Some(reported) = (ReportQuery { profile, message }).reported() else {
+        return res::sorry("report");
+    };
+
+    report(&db_pool, &user_id, room_id, reported, reason, note.trim()).await?;
+    Ok(Redirect::to(&format!("/r/{room_id}")).into_response())
+}
+
+fn queue_html(title: &str, reports: Vec<Report>, room_names: &[(String, String)], csrf_token: &str, all: bool) -> String {
+    let mut items = String::new();
+    for report in reports {
+        let room_id = report.room_id.to_string();
+        let room_name = room_names.iter()
+            .find(|(id, _)| *id == room_id)
+            .map(|(_, name)| name.as_str())
+            .unwrap_or_default();
+        let what = match (&report.message_id, &report.content) {
+            (Some(_), Some(content)) => format!("<blockquote>{}</blockquote>", res::escape(content)),
+            (Some(_), None) => "<blockquote><i>message gone</i></blockquote>".to_owned(),
+            (None, _) => String::new(),
+        };
+        let reporter = match &report.reporter_id {
+            Some(reporter_id) => format!("<p class=\"report-reporter\">reported by u/{}</p>", res::escape(reporter_id)),
+            None => String::new(),
+        };
+
+        items += &include_res!(str, "pages/rooms/report_item.html")
+            .replace("{csrf_token}", csrf_token)
+            .replace("{id}", &report.id.to_string())
+            .replace("{room_id}", &room_id)
+            .replace("{room_name}", &res::escape(room_name))
+            .replace("{room}", if all { "" } else { "display: none;" })
+            .replace("{all}", if all { "true" } else { "false" })
+            .replace("{profile_id}", &report.profile_id.to_string())
+            .replace("{delete}", if report.message_id.is_some() { "" } else { "display: none;" })
+            .replace("{reason}", report.reason.label())
+            .replace("{ago}", &res::ago(report.created_at))
+            .replace("{reporter}", &reporter)
+            // what people wrote goes in last, so it's never taken for a placeholder
+            .replace("{alias}", &res::escape(&report.alias))
+            .replace("{note}", &res::escape(&report.note))
+            .replace("{what}", &what);
+    }
+
+    include_res!(str, "pages/rooms/reports.html")
+        .replace("{title}", title)
+        .replace("{empty}", if items.is_empty() { "" } else { "display: none;" })
+        .replace("{reports}", &items)
+}
+
+#[debug_handler]
+pub(crate) async fn room_queue(
+    State(db_pool): State<SqlitePool>,
+    session: Session,
+    Path(room_id): Path<Uuid>,
+) -> AppResult<Response> {
+    let Some(user_id) = session.get::<String>(USER_ID).await? else {
+        return res::sorry("reports");
+    };
+    if !can_moderate(&db_pool, &user_id, room_id).await? {
+        return res::sorry("reports");
+    }
+
+    let (name,): (String,) = sqlx::query_as("SELECT name FROM rooms WHERE uuid=?")
+        .bind(room_id.to_string())
+        .fetch_one(&db_pool)
+        .await?;
+    let reports = room_reports(&db_pool, room_id).await?;
+    Ok(Html(queue_html(
+        &format!("Reports in <a href=\"/r/{room_id}\">{}</a>", res::escape(&name)),
+        reports,
+        &[],
+        &auth::csrf_token(&session).await?,
+        false,
+    )).into_response())
+}
+
+#[debug_handler]
+pub(crate) async fn admin_queue(
+    State(db_pool): State<SqlitePool>,
+    session: Session,
+) -> AppResult<Response> {
+    let Some(user_id) = session.get::<String>(USER_ID).await? else {
+        return res::sorry("reports");
+    };
+    if !is_admin(&db_pool, &user_id).await? {
+        return res::sorry("reports");
+    }
+
+    let reports = all_reports(&db_pool).await?;
+    let room_names: Vec<(String, String)> = sqlx::query_as("SELECT uuid,name FROM rooms WHERE uuid IN (SELECT room_id FROM reports WHERE resolved_at IS NULL)")
+        .fetch_all(&db_pool)
+        .await?;
+    Ok(Html(queue_html("All reports", reports, &room_names, &auth::csrf_token(&session).await?, true)).into_response())
+}
+
+#[derive(Deserialize)]
+pub(crate) struct ResolveForm {
+    action: ReportAction,
+    /// came from the admin queue, go back there
+    #[serde(default)]
+    all: bool,
+}
+
+#[debug_handler(state = AppState)]
+pub(crate) async fn resolve(
+    State(db_pool): State<SqlitePool>,
+    State(tx): State<broadcast::Sender<RoomEvent>>,
+    session: Session,
+    Path((room_id, id)): Path<(Uuid, Uuid)>,
+    Form(ResolveForm { action, all }): Form<ResolveForm>,
+) -> AppResult<Response> {
+    let Some(user_id) = session.get::<String>(USER_ID).await? else {
+        return res::sorry("reports");
+    };
+    if !can_moderate(&db_pool, &user_id, room_id).await? {
+        return res::sorry("reports");
+    }
+
+    resolve_report(&db_pool, &tx, room_id, id, action).await?;
+    Ok(Redirect::to(&if all { "/r/reports".to_owned() } else { format!("/r/{room_id}/reports") }).into_response())
+}
@@ -0,0 +1,125 @@
+use std::time::Duration;
+
+use serde::Deserialize;
+use serde_json::json;
+use sqlx::SqlitePool;
+use tokio::sync::broadcast;
+use uuid::Uuid;
+
+use crate::AppResult;
+
+use super::RoomEvent;
+
+/// The longest a message can be given to live, rooms keep longer if they like.
+pub const MAX_TTL_SECS: i64 = 30 * 24 * 60 * 60;
+
+/// How long a room keeps its messages.
+#[derive(Debug, Clone, Copy, Default, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Retention {
+    #[default]
+    Forever,
+    Hour,
+    Day,
+    Week,
+}
+
+impl Retention {
+    pub const ALL: [Retention; 4] = [Retention::Forever, Retention::Hour, Retention::Day, Retention::Week];
+
+    pub fn secs(self) -> Option<i64> {
+        use Retention::*;
+        match self {
+            Forever => None,
+            Hour => Some(60 * 60),
+            Day => Some(24 * 60 * 60),
+            Week => Some(7 * 24 * 60 * 60),
+        }
+    }
+
+    /// Rooms only ever get one of the fixed choices.
+    pub fn from_secs(secs: Option<i64>) -> Self {
+        Self::ALL.into_iter().find(|retention| retention.secs() == secs).unwrap_or_default()
+    }
+
+    pub fn slug(self) -> &'static str {
+        use Retention::*;
+        match self {
+            Forever => "forever",
+            Hour => "hour",
+            Day => "day",
+            Week => "week",
+        }
+    }
+
+    pub fn label(self) -> &'static str {
+        use Retention::*;
+        match self {
+            Forever => "Keep messages forever",
+            Hour => "Messages disappear after an hour",
+            Day => "Messages disappear after a day",
+            Week => "Messages disappear after 7 days",
+        }
+    }
+}
+
+/// Hard-deletes messages past their own TTL or their room's retention, and
+/// tells open clients to drop them. Read markers on them move back to the
+/// newest message that's left before them.
+pub async fn sweep_expired(db_pool: &SqlitePool, tx: &broadcast::Sender<RoomEvent>) -> AppResult<usize> {
+    let expired: Vec<(String, String)> = sqlx::query_as(
+        "SELECT id,room_id FROM messages
+        WHERE expires_at <= unixepoch()
+            OR created_at + (SELECT retention_secs FROM rooms WHERE uuid=messages.room_id) <= unixepoch()
+        ORDER BY created_at DESC, id DESC"
+    )
+        .fetch_all(db_pool)
+        .await?;
+
+    let mut swept = 0;
+    for (id, room_id) in expired {
+        let mut db_tx = db_pool.begin().await?;
+        sqlx::query(
+            "UPDATE profiles SET last_read_id=(
+                SELECT earlier.id FROM messages earlier, messages gone
+                WHERE gone.id=?1 AND earlier.room_id=gone.room_id AND (earlier.created_at,earlier.id) < (gone.created_at,gone.id)
+                ORDER BY earlier.created_at DESC, earlier.id DESC
+                LIMIT 1
+            )
+            WHERE last_read_id=?1"
+        )
+            .bind(&id)
+            .execute(&mut *db_tx)
+            .await?;
+        let deleted = sqlx::query("DELETE FROM messages WHERE id=? AND room_id=?")
+            .bind(&id)
+            .bind(&room_id)
+            .execute(&mut *db_tx)
+            .await?
+            .rows_affected();
+        db_tx.commit().await?;
+
+        if deleted > 0 {
+            swept += 1;
+            let _ = tx.send(RoomEvent {
+                room_id: Uuid::parse_str(&room_id)?,
+                message_id: None,
+                data: json!({ "type": "expired", "id": id }).to_string(),
+                only_to: None,
+            });
+        }
+    }
+    Ok(swept)
+}
+
+pub async fn message_sweeper(db_pool: SqlitePool, tx: broadcast::Sender<RoomEvent>) {
+    let mut interval = tokio::time::interval(Duration::from_secs(30));
+    loop {
+        interval.tick().await;
+        match sweep_expired(&db_pool, &tx).await {
+            Ok(0) => (),
+            Ok(n) => println!("swept {n} expired messages"),
+            Err(err) => println!("message sweeper: {:?}", err.0),
+        }
+    }
+}
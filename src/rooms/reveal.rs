@@ -0,0 +1,294 @@
+use axum::{debug_handler, extract::{Path, State}, http::StatusCode, response::{IntoResponse, Redirect, Response}, Form};
+use serde::Deserialize;
+use sqlx::SqlitePool;
+use uuid::Uuid;
+
+use crate::{auth::Caller, include_res, res, AppResult};
+
+use super::{dm::{can_write, own_profile}, find_profile, has_blocked, msg::refuse};
+
+/// Unanswered requests lapse after a week.
+const REVEAL_TTL: i64 = 7 * 24 * 60 * 60;
+
+/// Where a reveal between two profiles stands, from one side's point of view.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Reveal {
+    /// we asked, they haven't answered yet
+    Asked(Uuid),
+    /// they asked us
+    Asking(Uuid),
+    /// both agreed, this is who they are
+    Revealed(Uuid, String),
+}
+
+/// The shows choices a user can make: `name` once they set a display name,
+/// and each provider they linked.
+pub async fn reveal_choices(db_pool: &SqlitePool, profile_id: Uuid) -> AppResult<Vec<String>> {
+    Ok(sqlx::query_scalar(
+        "SELECT 'name' FROM users JOIN profiles ON profiles.user_id=users.id
+        WHERE profiles.uuid=?1 AND users.display_name IS NOT NULL
+        UNION ALL
+        SELECT DISTINCT provider FROM identities JOIN profiles ON profiles.user_id=identities.user_id
+        WHERE profiles.uuid=?1 AND identities.handle IS NOT NULL"
+    )
+        .bind(profile_id.to_string())
+        .fetch_all(db_pool)
+        .await?)
+}
+
+async fn check_shows(db_pool: &SqlitePool, profile_id: Uuid, shows: &str) -> AppResult<()> {
+    if !reveal_choices(db_pool, profile_id).await?.iter().any(|choice| choice == shows) {
+        return Err(refuse(StatusCode::BAD_REQUEST, "Pick a display name or a linked account to show"));
+    }
+    Ok(())
+}
+
+/// What `shows` stands for: the display name, or what a linked account goes
+/// by at its provider. Never the subject, under Firebase that's the user id.
+async fn shown(db_pool: &SqlitePool, profile_id: Uuid, shows: &str) -> AppResult<String> {
+    let user_id: String = sqlx::query_scalar("SELECT user_id FROM profiles WHERE uuid=?")
+        .bind(profile_id.to_string())
+        .fetch_one(db_pool)
+        .await?;
+
+    let shown: Option<String> = if shows == "name" {
+        sqlx::query_scalar("SELECT display_name FROM users WHERE id=?")
+            .bind(&user_id)
+            .fetch_one(db_pool)
+            .await?
+    } else {
+        sqlx::query_scalar("SELECT handle || ' on ' || provider FROM identities WHERE user_id=? AND provider=? AND handle IS NOT NULL LIMIT 1")
+            .bind(&user_id)
+            .bind(shows)
+            .fetch_optional(db_pool)
+            .await?
+    };
+    // they unlinked or cleared it since
+    Ok(shown.unwrap_or("(no longer shared)".to_owned()))
+}
+
+/// Asks `to_id` for a mutual reveal, showing `shows` of the asker once they
+/// accept. Asking again renews the request.
+pub async fn request_reveal(db_pool: &SqlitePool, from_id: Uuid, to_id: Uuid, shows: &str) -> AppResult<Uuid> {
+    if from_id == to_id {
+        return Err(refuse(StatusCode::BAD_REQUEST, "That's you"));
+    }
+
+    let room_ids: Vec<String> = sqlx::query_scalar("SELECT room_id FROM profiles WHERE uuid IN (?,?)")
+        .bind(from_id.to_string())
+        .bind(to_id.to_string())
+        .fetch_all(db_pool)
+        .await?;
+    let [room_id, other_room_id] = room_ids.as_slice() else {
+        return Err(refuse(StatusCode::NOT_FOUND, "No such profile"));
+    };
+    if room_id != other_room_id {
+        return Err(refuse(StatusCode::NOT_FOUND, "No such profile"));
+    }
+
+    if has_blocked(db_pool, from_id, to_id).await? || has_blocked(db_pool, to_id, from_id).await? {
+        return Err(refuse(StatusCode::FORBIDDEN, "You can't ask this profile"));
+    }
+    check_shows(db_pool, from_id, shows).await?;
+
+    match reveal_between(db_pool, from_id, to_id).await? {
+        Some(Reveal::Asking(_)) => return Err(refuse(StatusCode::CONFLICT, "They already asked, accept theirs instead")),
+        Some(Reveal::Revealed(..)) => return Err(refuse(StatusCode::CONFLICT, "You already know each other")),
+        Some(Reveal::Asked(_)) | None => (),
+    }
+
+    let id = Uuid::now_v7();
+    let id: String = sqlx::query_scalar(
+        "INSERT INTO reveals (id,room_id,from_id,to_id,from_shows,created_at,expires_at)
+        VALUES (?1,?2,?3,?4,?5,unixepoch(),unixepoch()+?6)
+        ON CONFLICT (from_id,to_id) DO UPDATE SET
+            from_shows=excluded.from_shows, to_shows=NULL, accepted_at=NULL,
+            created_at=excluded.created_at, expires_at=excluded.expires_at
+        RETURNING id"
+    )
+        .bind(id.to_string())
+        .bind(room_id)
+        .bind(from_id.to_string())
+        .bind(to_id.to_string())
+        .bind(shows)
+        .bind(REVEAL_TTL)
+        .fetch_one(db_pool)
+        .await?;
+    Ok(Uuid::parse_str(&id)?)
+}
+
+/// Accepts a request made to `profile_id`, showing `shows` back. Expired and
+/// other people's requests can't be accepted.
+pub async fn accept_reveal(db_pool: &SqlitePool, profile_id: Uuid, id: Uuid, shows: &str) -> AppResult<()> {
+    check_shows(db_pool, profile_id, shows).await?;
+
+    // a block since the request calls it off
+    let from_id: Option<String> = sqlx::query_scalar("SELECT from_id FROM reveals WHERE id=? AND to_id=?")
+        .bind(id.to_string())
+        .bind(profile_id.to_string())
+        .fetch_optional(db_pool)
+        .await?;
+    if let Some(from_id) = from_id {
+        let from_id = Uuid::parse_str(&from_id)?;
+        if has_blocked(db_pool, from_id, profile_id).await? || has_blocked(db_pool, profile_id, from_id).await? {
+            return Err(refuse(StatusCode::FORBIDDEN, "You can't reveal to this profile"));
+        }
+    }
+
+    let accepted = sqlx::query(
+        "UPDATE reveals SET to_shows=?3, accepted_at=unixepoch()
+        WHERE id=?1 AND to_id=?2 AND accepted_at IS NULL AND expires_at > unixepoch()"
+    )
+        .bind(id.to_string())
+        .bind(profile_id.to_string())
+        .bind(shows)
+        .execute(db_pool)
+        .await?
+        .rows_affected();
+    if accepted == 0 {
+        return Err(refuse(StatusCode::NOT_FOUND, "No such request, it may have expired"));
+    }
+    Ok(())
+}
+
+/// Either side can call it off: withdraw a request, turn one down, or take
+/// back a reveal.
+pub async fn withdraw_reveal(db_pool: &SqlitePool, profile_id: Uuid, id: Uuid) -> AppResult<bool> {
+    Ok(
+        sqlx::query("DELETE FROM reveals WHERE id=?1 AND (from_id=?2 OR to_id=?2)")
+            .bind(id.to_string())
+            .bind(profile_id.to_string())
+            .execute(db_pool)
+            .await?
+            .rows_affected() > 0
+    )
+}
+
+/// id, from_id, from_shows, to_shows, accepted_at
+type RevealRow = (String, String, String, Option<String>, Option<i64>);
+
+/// The live reveal between two profiles, as seen by `profile_id`.
+pub async fn reveal_between(db_pool: &SqlitePool, profile_id: Uuid, other_id: Uuid) -> AppResult<Option<Reveal>> {
+    let row: Option<RevealRow> = sqlx::query_as(
+        "SELECT id,from_id,from_shows,to_shows,accepted_at FROM reveals
+        WHERE ((from_id=?1 AND to_id=?2) OR (from_id=?2 AND to_id=?1))
+            AND (accepted_at IS NOT NULL OR expires_at > unixepoch())
+        ORDER BY created_at DESC
+        LIMIT 1"
+    )
+        .bind(profile_id.to_string())
+        .bind(other_id.to_string())
+        .fetch_optional(db_pool)
+        .await?;
+    let Some((id, from_id, from_shows, to_shows, accepted_at)) = row else {
+        return Ok(None);
+    };
+
+    let id = Uuid::parse_str(&id)?;
+    let we_asked = from_id == profile_id.to_string();
+    Ok(Some(match (accepted_at, to_shows) {
+        (Some(_), Some(to_shows)) => {
+            let their_shows = if we_asked { to_shows } else { from_shows };
+            Reveal::Revealed(id, shown(db_pool, other_id, &their_shows).await?)
+        },
+        _ if we_asked => Reveal::Asked(id),
+        _ => Reveal::Asking(id),
+    }))
+}
+
+/// The reveal part of a DM conversation page.
+pub(super) async fn reveal_html(db_pool: &SqlitePool, room_id: Uuid, profile_id: Uuid, other_id: Uuid, csrf_token: &str) -> AppResult<String> {
+    let choices = reveal_choices(db_pool, profile_id).await?;
+    let mut options = String::new();
+    for choice in &choices {
+        // spelled out, so it's clear what the other side gets to see
+        let label = format!("my {}", shown(db_pool, profile_id, choice).await?);
+        options += &format!("<option value=\"{}\">{}</option>\n", res::escape(choice), res::escape(&label));
+    }
+
+    let reveal = reveal_between(db_pool, profile_id, other_id).await?;
+    let (id, shown) = match &reveal {
+        Some(Reveal::Asked(id) | Reveal::Asking(id)) => (id.to_string(), String::new()),
+        Some(Reveal::Revealed(id, shown)) => (id.to_string(), shown.clone()),
+        None => (String::new(), String::new()),
+    };
+    let show = |visible: bool| if visible { "" } else { "display: none;" };
+
+    Ok(
+        include_res!(str, "pages/rooms/reveal.html")
+            .replace("{csrf_token}", csrf_token)
+            .replace("{room_id}", &room_id.to_string())
+            .replace("{other_id}", &other_id.to_string())
+            .replace("{id}", &id)
+            .replace("{shown}", &res::escape(&shown))
+            .replace("{options}", &options)
+            .replace("{no_choices}", show(choices.is_empty()))
+            .replace("{ask}", show(reveal.is_none() && !choices.is_empty()))
+            .replace("{asked}", show(matches!(reveal, Some(Reveal::Asked(_)))))
+            .replace("{asking}", show(matches!(reveal, Some(Reveal::Asking(_)))))
+            .replace("{revealed}", show(matches!(reveal, Some(Reveal::Revealed(..)))))
+    )
+}
+
+#[derive(Deserialize)]
+pub(crate) struct RevealForm {
+    id: Option<Uuid>,
+    shows: Option<String>,
+}
+
+/// Checks the caller may write and the other side of a conversation, then hands both
+/// profiles to `act` and goes back to the conversation.
+async fn reveal_action(
+    db_pool: &SqlitePool,
+    caller: Option<Caller>,
+    room_id: Uuid,
+    who: &str,
+    act: impl AsyncFnOnce(Uuid, Uuid) -> AppResult<()>,
+) -> AppResult<Response> {
+    can_write(&caller)?;
+    let Some(profile_id) = own_profile(db_pool, caller, room_id).await? else {
+        return res::sorry("room");
+    };
+    let Some(other_id) = find_profile(db_pool, room_id, who).await? else {
+        return res::sorry("profile");
+    };
+
+    act(profile_id, other_id).await?;
+    Ok(Redirect::to(&format!("/r/{room_id}/dm/{other_id}")).into_response())
+}
+
+#[debug_handler]
+pub(crate) async fn request(
+    State(db_pool): State<SqlitePool>,
+    caller: Option<Caller>,
+    Path((room_id, who)): Path<(Uuid, String)>,
+    Form(RevealForm { shows, .. }): Form<RevealForm>,
+) -> AppResult<Response> {
+    reveal_action(&db_pool, caller, room_id, &who, async |profile_id, other_id| {
+        request_reveal(&db_pool, profile_id, other_id, shows.as_deref().unwrap_or_default()).await.map(|_| ())
+    }).await
+}
+
+#[debug_handler]
+pub(crate) async fn accept(
+    State(db_pool): State<SqlitePool>,
+    caller: Option<Caller>,
+    Path((room_id, who)): Path<(Uuid, String)>,
+    Form(RevealForm { id, shows }): Form<RevealForm>,
+) -> AppResult<Response> {
+    reveal_action(&db_pool, caller, room_id, &who, async |profile_id, _| {
+        accept_reveal(&db_pool, profile_id, id.unwrap_or_default(), shows.as_deref().unwrap_or_default()).await
+    }).await
+}
+
+#[debug_handler]
+pub(crate) async fn withdraw(
+    State(db_pool): State<SqlitePool>,
+    caller: Option<Caller>,
+    Path((room_id, who)): Path<(Uuid, String)>,
+    Form(RevealForm { id, .. }): Form<RevealForm>,
+) -> AppResult<Response> {
+    reveal_action(&db_pool, caller, room_id, &who, async |profile_id, _| {
+        withdraw_reveal(&db_pool, profile_id, id.unwrap_or_default()).await.map(|_| ())
+    }).await
+}
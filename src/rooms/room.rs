@@ -3,67 +3,88 @@ use sqlx::SqlitePool;
 use tower_sessions::Session;
 use uuid::Uuid;
 
-use crate::{include_res, res, session::USER_ID, AppResult};
+use crate::{auth::{self, Caller}, include_res, res, AppResult};
 
-use super::msg;
+use super::{automod, can_moderate, is_room_owner, msg, read, report, room_access, RoomAccess};
 
 #[debug_handler]
 pub(crate) async fn room(
     State(db_pool): State<SqlitePool>,
     session: Session,
+    caller: Option<Caller>,
     Path(room_id): Path<Uuid>,
 ) -> AppResult<Response> {
     let sorry = res::sorry("room");
 
-    let Some((name, is_public)): Option<(String, bool)> =
-        sqlx::query_as("SELECT name,is_public FROM rooms WHERE uuid=?")
-            .bind(room_id.to_string())
-            .fetch_optional(&db_pool)
-            .await?
-    else {
+    let user_id = caller.map(|caller| caller.user_id);
+    let Some(access) = room_access(&db_pool, user_id.as_deref(), room_id).await? else {
         return sorry;
     };
 
-    if !is_public {
-        let Some(client_user_id) = session.get::<String>(USER_ID).await? else {
-            return sorry;
-        };
+    let (name, description, is_archived): (String, String, bool) = sqlx::query_as("SELECT name,description,is_archived FROM rooms WHERE uuid=?")
+        .bind(room_id.to_string())
+        .fetch_one(&db_pool)
+        .await?;
 
-        if sqlx::query_as::<_, ()>("SELECT 1 FROM profiles WHERE uuid=? AND room_id=?")
-            .bind(client_user_id)
-            .bind(room_id.to_string())
-            .fetch_optional(&db_pool)
-            .await?
-            .is_none() {
-            return sorry;
-        }
-    }
-
-    let msgs: Vec<(String, String, Option<String>, String)> =
-        sqlx::query_as("SELECT id,profile_id,reply_to_id,content FROM messages WHERE room_id=?")
+    let mut msgs: Vec<(String, String, Option<String>, String)> =
+        sqlx::query_as("SELECT id,profile_id,reply_to_id,content FROM messages WHERE room_id=? ORDER BY created_at,id")
             .bind(room_id.to_string())
             .fetch_all(&db_pool)
             .await?;
 
-    let mut messages = String::new();
-    for (id, profile_id, reply_to_id, content) in msgs {
-        messages += &msg::msg_to_html(
-            Uuid::parse_str(&id)?,
-            room_id, 
-            Uuid::parse_str(&profile_id)?,
-            match reply_to_id {
-                Some(x) => Some(Uuid::parse_str(&x)?),
-                None => None,
-            },
-            content, 
-            &db_pool
-        ).await?;
+    // members get a divider above what they haven't seen, then it counts as seen
+    let mut unread_msgs = Vec::new();
+    let mut unread = 0;
+    if let RoomAccess::Member(profile_id) = access {
+        unread = read::unread_count(&db_pool, profile_id).await?;
+        if unread > 0 && let Some(first_unread) = read::first_unread(&db_pool, profile_id).await? {
+            let first_unread = first_unread.to_string();
+            let at = msgs.iter().position(|(id, ..)| *id == first_unread).unwrap_or(msgs.len());
+            unread_msgs = msgs.split_off(at);
+        }
+        read::mark_room_read(&db_pool, profile_id).await?;
+    }
+
+    let mut messages = msg::rows_to_html(msgs, room_id, &db_pool).await?;
+    if !unread_msgs.is_empty() {
+        messages += &include_res!(str, "pages/rooms/new_messages.html")
+            .replace("{unread}", &unread.to_string());
+        messages += &msg::rows_to_html(unread_msgs, room_id, &db_pool).await?;
     }
 
+    let is_owner = match &user_id {
+        Some(user_id) => is_room_owner(&db_pool, user_id, room_id).await?,
+        None => false,
+    };
+
+    // (open reports, held messages) for moderators
+    let queues = match &user_id {
+        Some(user_id) if can_moderate(&db_pool, user_id, room_id).await? => Some((
+            report::open_report_count(&db_pool, room_id).await?,
+            automod::held_count(&db_pool, room_id).await?,
+        )),
+        _ => None,
+    };
+
+    // visitors who aren't signed in can't post, so don't start a session for them
+    let csrf_token = match &user_id {
+        Some(_) => auth::csrf_token(&session).await?,
+        None => String::new(),
+    };
+
     let body = include_res!(str, "pages/rooms/room.html")
+        .replace("{csrf_token}", &csrf_token)
         .replace("{room_id}", &room_id.to_string())
-        .replace("{room_name}", &name)
+        .replace("{room_name}", &res::escape(&name))
+        .replace("{description}", &res::escape(&description))
+        .replace("{archived}", if is_archived { "" } else { "display: none;" })
+        .replace("{composer}", if is_archived { "display: none;" } else { "" })
+        .replace("{settings}", if is_owner { "" } else { "display: none;" })
+        .replace("{moderate}", if queues.is_some() { "" } else { "display: none;" })
+        .replace("{open_reports}", &queues.unwrap_or_default().0.to_string())
+        .replace("{held}", &queues.unwrap_or_default().1.to_string())
+        .replace("{dms}", if access.profile_id().is_some() { "" } else { "display: none;" })
         .replace("{messages}", &messages);
 
-    return Ok(Html(body).into_response());
+    Ok(Html(body).into_response())
 }
\ No newline at end of file
@@ -0,0 +1,40 @@
+use axum::{debug_handler, extract::{Path, State}, http::StatusCode, response::{IntoResponse, Response}, Json};
+use sqlx::SqlitePool;
+use tokio::sync::broadcast;
+use uuid::Uuid;
+
+use crate::{auth::{Caller, GuestConfig}, res, AppResult, AppState};
+
+use super::{automod::HELD, msg::{self, SendMessageQuery, Sent}, room_access, sender_profile, Limits, RoomEvent};
+
+/// Sends a message without a socket, takes the same JSON the socket does.
+/// Pages send their CSRF token as `X-CSRF-Token`.
+#[debug_handler(state = AppState)]
+pub(crate) async fn send(
+    Path(room_id): Path<Uuid>,
+    State(db_pool): State<SqlitePool>,
+    State(tx): State<broadcast::Sender<RoomEvent>>,
+    State(guests): State<GuestConfig>,
+    State(limits): State<Limits>,
+    caller: Option<Caller>,
+    Json(query): Json<SendMessageQuery>,
+) -> AppResult<Response> {
+    let sorry = res::sorry("room");
+
+    let Some(Caller { user_id, can_write, .. }) = caller else {
+        return sorry;
+    };
+    if !can_write {
+        return Err((StatusCode::FORBIDDEN, "This token is read only").into_response().into());
+    }
+
+    let Some(access) = room_access(&db_pool, Some(&user_id), room_id).await? else {
+        return sorry;
+    };
+    let profile_id = sender_profile(&db_pool, &guests, &limits, &user_id, room_id, access).await?;
+
+    Ok(match msg::send_msg(&db_pool, &tx, profile_id, room_id, query).await? {
+        Sent::Posted(_) => StatusCode::NO_CONTENT.into_response(),
+        Sent::Held(_) => (StatusCode::ACCEPTED, HELD).into_response(),
+    })
+}
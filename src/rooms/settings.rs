@@ -0,0 +1,132 @@
+use axum::{debug_handler, extract::{Path, State}, http::StatusCode, response::{Html, IntoResponse, Redirect, Response}, Form};
+use serde::Deserialize;
+use sqlx::SqlitePool;
+use tokio::sync::broadcast;
+use tower_sessions::Session;
+use uuid::Uuid;
+
+use crate::{auth, include_res, res, AppResult, AppState};
+
+use super::{msg, owned_room, Retention, RoomEvent, SLOW_MODE};
+
+#[derive(Debug, Deserialize)]
+pub(crate) struct RoomSettingsQuery {
+    name: String,
+    description: String,
+    is_public: bool,
+    is_archived: bool,
+    #[serde(default)]
+    retention: Retention,
+    /// one of [`SLOW_MODE`]
+    #[serde(default)]
+    slow_mode_secs: i64,
+}
+
+type RoomSettingsRow = (String, String, bool, bool, Option<i64>, Option<i64>);
+const ROOM_SETTINGS: &str = "name,description,is_public,is_archived,retention_secs,slow_mode_secs";
+
+#[debug_handler]
+pub(crate) async fn settings_page(
+    State(db_pool): State<SqlitePool>,
+    session: Session,
+    Path(room_id): Path<Uuid>,
+) -> AppResult<Response> {
+    let Some((name, description, is_public, is_archived, retention_secs, slow_mode_secs)) = owned_room::<RoomSettingsRow>(&db_pool, &session, room_id, ROOM_SETTINGS).await? else {
+        return res::sorry("room settings");
+    };
+
+    let checked = |b: bool| if b { "checked" } else { "" };
+    let current = Retention::from_secs(retention_secs);
+    let retention_options: String = Retention::ALL.into_iter()
+        .map(|retention| format!(
+            "<option value=\"{}\" {}>{}</option>\n",
+            retention.slug(),
+            if retention == current { "selected" } else { "" },
+            retention.label(),
+        ))
+        .collect();
+    let slow_mode_options: String = SLOW_MODE.into_iter()
+        .map(|(secs, label)| format!(
+            "<option value=\"{secs}\" {}>{}</option>\n",
+            if secs == slow_mode_secs.unwrap_or(0) { "selected" } else { "" },
+            if secs == 0 { "Off".to_owned() } else { format!("One message every {label}") },
+        ))
+        .collect();
+    Ok(Html(
+        include_res!(str, "pages/rooms/settings.html")
+        .replace("{csrf_token}", &auth::csrf_token(&session).await?)
+        .replace("{room_id}", &room_id.to_string())
+        .replace("{room_name}", &res::escape(&name))
+        .replace("{description}", &res::escape(&description))
+        .replace("{private_checked}", checked(!is_public))
+        .replace("{public_checked}", checked(is_public))
+        .replace("{open_checked}", checked(!is_archived))
+        .replace("{archived_checked}", checked(is_archived))
+        .replace("{retention_options}", &retention_options)
+        .replace("{slow_mode_options}", &slow_mode_options)
+    ).into_response())
+}
+
+#[debug_handler(state = AppState)]
+pub(crate) async fn settings(
+    State(db_pool): State<SqlitePool>,
+    State(tx): State<broadcast::Sender<RoomEvent>>,
+    session: Session,
+    Path(room_id): Path<Uuid>,
+
+    Form(RoomSettingsQuery { name, description, is_public, is_archived, retention, slow_mode_secs }): Form<RoomSettingsQuery>,
+) -> AppResult<Response> {
+    let Some((old_name, old_description, was_public, was_archived, old_retention_secs, old_slow_mode_secs)) = owned_room::<RoomSettingsRow>(&db_pool, &session, room_id, ROOM_SETTINGS).await? else {
+        return res::sorry("room settings");
+    };
+
+    let name = name.trim();
+    if name.is_empty() {
+        return Err(msg::refuse(StatusCode::BAD_REQUEST, "Rooms need a name"));
+    }
+
+    let Some((_, slow_mode)) = SLOW_MODE.into_iter().find(|(secs, _)| *secs == slow_mode_secs) else {
+        return Err(msg::refuse(StatusCode::BAD_REQUEST, "No such slow mode"));
+    };
+    let slow_mode_secs = Some(slow_mode_secs).filter(|secs| *secs > 0);
+
+    sqlx::query("UPDATE rooms SET name=?,description=?,is_public=?,is_archived=?,retention_secs=?,slow_mode_secs=? WHERE uuid=?")
+        .bind(name)
+        .bind(&description)
+        .bind(is_public)
+        .bind(is_archived)
+        .bind(retention.secs())
+        .bind(slow_mode_secs)
+        .bind(room_id.to_string())
+        .execute(&db_pool)
+        .await?;
+
+    let mut notices = Vec::new();
+    if name != old_name {
+        notices.push(format!("Room renamed to \"{name}\""));
+    }
+    if description != old_description {
+        notices.push(format!("Topic changed to \"{description}\""));
+    }
+    if is_public != was_public {
+        notices.push(if is_public { "Room is now public" } else { "Room is now private" }.to_owned());
+    }
+    if is_archived != was_archived {
+        notices.push(if is_archived { "Room archived, it is now read-only" } else { "Room unarchived" }.to_owned());
+    }
+    if retention.secs() != old_retention_secs {
+        notices.push(retention.label().to_owned());
+    }
+    if slow_mode_secs != old_slow_mode_secs {
+        notices.push(match slow_mode_secs {
+            Some(_) => format!("Slow mode on, one message every {slow_mode}"),
+            None => "Slow mode off".to_owned(),
+        });
+    }
+
+    for notice in notices {
+        msg::send_system_msg(&db_pool, &tx, room_id, notice).await?;
+    }
+
+    Ok(Redirect::to(&format!("/r/{room_id}")).into_response())
+}
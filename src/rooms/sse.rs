@@ -0,0 +1,109 @@
+use std::{collections::VecDeque, convert::Infallible, net::SocketAddr};
+
+use axum::{debug_handler, extract::{ConnectInfo, Path, State}, http::{HeaderMap, StatusCode}, response::{sse::{Event, KeepAlive}, IntoResponse, Response, Sse}};
+use futures_util::stream;
+use sqlx::SqlitePool;
+use tokio::sync::broadcast::{self, error::RecvError};
+use tower_sessions::session::Id;
+use uuid::Uuid;
+
+use crate::{auth::{Caller, SqliteStore}, res, AppResult, AppState};
+
+use super::{msg, room_access, Connection, Limits, RoomEvent};
+
+struct Feed {
+    room_id: Uuid,
+    profile_id: Option<Uuid>,
+    backlog: VecDeque<Event>,
+    rx: broadcast::Receiver<RoomEvent>,
+    /// the cookie session, and where to hear it was revoked
+    revoked: Option<(Id, broadcast::Receiver<Id>)>,
+    /// counts against the address until the stream ends
+    _connection: Connection,
+}
+
+fn to_event(event: RoomEvent) -> Event {
+    let sse = Event::default().data(event.data);
+    match event.message_id {
+        Some(id) => sse.id(id.to_string()),
+        None => sse,
+    }
+}
+
+async fn next_event(mut feed: Feed) -> Option<(Result<Event, Infallible>, Feed)> {
+    if let Some(event) = feed.backlog.pop_front() {
+        return Some((Ok(event), feed));
+    }
+
+    loop {
+        let revoked = async {
+            match &mut feed.revoked {
+                Some((session_id, revoked)) => loop {
+                    if let Ok(id) = revoked.recv().await && id == *session_id {
+                        return;
+                    }
+                },
+                None => std::future::pending().await,
+            }
+        };
+
+        tokio::select! {
+            event = feed.rx.recv() => match event {
+                Ok(event) if event.room_id == feed.room_id && event.kicks(feed.profile_id) => return None,
+                Ok(event) if event.room_id == feed.room_id && event.is_for(feed.profile_id) => return Some((Ok(to_event(event)), feed)),
+                Ok(_) => continue,
+                // ending the stream has the browser reconnect with Last-Event-ID,
+                // which replays whatever was missed
+                Err(RecvError::Lagged(_)) | Err(RecvError::Closed) => return None,
+            },
+            _ = revoked => return None,
+        }
+    }
+}
+
+/// The room socket's events as Server-Sent Events, for networks that break
+/// WebSockets. Sending goes through [`super::send::send`] instead.
+#[debug_handler(state = AppState)]
+#[allow(clippy::too_many_arguments)]
+pub(crate) async fn events(
+    Path(room_id): Path<Uuid>,
+    State(db_pool): State<SqlitePool>,
+    State(tx): State<broadcast::Sender<RoomEvent>>,
+    State(store): State<SqliteStore>,
+    State(limits): State<Limits>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    caller: Option<Caller>,
+    headers: HeaderMap,
+) -> AppResult<Response> {
+    let user_id = caller.as_ref().map(|caller| caller.user_id.as_str());
+    let Some(access) = room_access(&db_pool, user_id, room_id).await? else {
+        return res::sorry("room");
+    };
+
+    let Some(connection) = limits.connect(addr.ip()) else {
+        return Err((StatusCode::TOO_MANY_REQUESTS, "Too many connections from your address").into_response().into());
+    };
+
+    // subscribe before replaying so nothing falls in between, doubles are
+    // replaced by id on the page
+    let rx = tx.subscribe();
+    let revoked = caller.and_then(|caller| caller.session_id).map(|session_id| (session_id, store.subscribe()));
+
+    let last_event_id = headers.get("last-event-id")
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| Uuid::parse_str(value).ok());
+    let backlog = match last_event_id {
+        Some(last_event_id) => msg::missed_msgs(&db_pool, room_id, last_event_id).await?
+            .into_iter()
+            .map(to_event)
+            .collect(),
+        None => VecDeque::new(),
+    };
+
+    let feed = Feed { room_id, profile_id: access.profile_id(), backlog, rx, revoked, _connection: connection };
+    Ok(
+        Sse::new(stream::unfold(feed, next_event))
+            .keep_alive(KeepAlive::default())
+            .into_response()
+    )
+}
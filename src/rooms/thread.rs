@@ -0,0 +1,73 @@
+use axum::{debug_handler, extract::{Path, State}, response::{Html, IntoResponse, Response}};
+use sqlx::SqlitePool;
+use uuid::Uuid;
+
+use crate::{auth::Caller, include_res, res, AppResult};
+
+use super::{msg, room_access};
+
+/// Every message on the same reply chain as `message_id`: its ancestors up to
+/// the root and all replies below it, oldest first.
+pub(crate) async fn thread_rows(
+    db_pool: &SqlitePool,
+    room_id: Uuid,
+    message_id: Uuid,
+) -> AppResult<Vec<(String, String, Option<String>, String)>> {
+    Ok(
+        sqlx::query_as("
+            WITH RECURSIVE
+                up(id, reply_to_id) AS (
+                    SELECT id,reply_to_id FROM messages WHERE id=?2 AND room_id=?1
+                    UNION
+                    SELECT messages.id,messages.reply_to_id FROM messages JOIN up ON messages.id=up.reply_to_id
+                    WHERE messages.room_id=?1
+                ),
+                down(id) AS (
+                    SELECT id FROM messages WHERE id=?2 AND room_id=?1
+                    UNION
+                    SELECT messages.id FROM messages JOIN down ON messages.reply_to_id=down.id
+                    WHERE messages.room_id=?1
+                )
+            SELECT id,profile_id,reply_to_id,content FROM messages
+            WHERE room_id=?1 AND id IN (SELECT id FROM up UNION SELECT id FROM down)
+            ORDER BY created_at,id
+        ")
+            .bind(room_id.to_string())
+            .bind(message_id.to_string())
+            .fetch_all(db_pool)
+            .await?
+    )
+}
+
+#[debug_handler]
+pub(crate) async fn thread(
+    State(db_pool): State<SqlitePool>,
+    caller: Option<Caller>,
+    Path((room_id, message_id)): Path<(Uuid, Uuid)>,
+) -> AppResult<Response> {
+    let sorry = res::sorry("thread");
+
+    let user_id = caller.map(|caller| caller.user_id);
+    if room_access(&db_pool, user_id.as_deref(), room_id).await?.is_none() {
+        return sorry;
+    }
+
+    let rows = thread_rows(&db_pool, room_id, message_id).await?;
+    if rows.is_empty() {
+        return sorry;
+    }
+
+    let (name, is_archived): (String, bool) = sqlx::query_as("SELECT name,is_archived FROM rooms WHERE uuid=?")
+        .bind(room_id.to_string())
+        .fetch_one(&db_pool)
+        .await?;
+
+    let body = include_res!(str, "pages/rooms/thread.html")
+        .replace("{room_id}", &room_id.to_string())
+        .replace("{room_name}", &res::escape(&name))
+        .replace("{thread_id}", &message_id.to_string())
+        .replace("{composer}", if is_archived { "display: none;" } else { "" })
+        .replace("{messages}", &msg::rows_to_html(rows, room_id, &db_pool).await?);
+
+    Ok(Html(body).into_response())
+}
@@ -1,67 +1,210 @@
-use axum::{debug_handler, extract::{Path, State, WebSocketUpgrade}, response::IntoResponse};
+use std::net::SocketAddr;
+
+use axum::{debug_handler, extract::{ws::{close_code, CloseFrame, Message}, ConnectInfo, Path, Query, State, WebSocketUpgrade}, http::{header, StatusCode}, response::{IntoResponse, Response}};
 use futures_util::{SinkExt, StreamExt};
+use serde::Deserialize;
+use serde_json::json;
 use sqlx::SqlitePool;
-use tokio::sync::broadcast;
-use tower_sessions::Session;
+use tokio::sync::{broadcast::{self, error::RecvError}, mpsc};
 use uuid::Uuid;
 
-use crate::{auth, rooms::msg, session::USER_ID};
+use crate::{auth::{AllowedOrigin, Caller, GuestConfig, SqliteStore}, res, rooms::msg::{self, Sent}, AppError, AppResult};
+
+use super::{automod::HELD, can_send, mark_read, member_profile, room_access, Limits, Presence, RoomEvent};
+
+#[derive(Deserialize)]
+pub(crate) struct WsQuery {
+    /// the last message the client has, everything after it is replayed first
+    since: Option<Uuid>,
+}
+
+/// Sent instead of events once the socket fell too far behind, the client
+/// should reconnect with `since` set to the last message it has.
+const RESYNC: &str = r#"{"type":"resync"}"#;
+
+/// JSON the client sends besides messages.
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum Signal {
+    Typing,
+    /// the client has shown this message, see [`super::mark_read`]
+    Read { id: Uuid },
+}
+
+/// What the client hears when a message it sent didn't go out:
+/// `{"type": "error", "code", "message", "retry_after_secs"}` where `code` is
+/// one of `rate_limited`, `forbidden`, `invalid`, `not_found` or `internal`,
+/// and `retry_after_secs` is only set for `rate_limited`. Held messages get
+/// `{"type": "held", "message"}` instead.
+fn refusal(err: &AppError) -> String {
+    let status = err.1.status();
+    let code = match status {
+        StatusCode::TOO_MANY_REQUESTS => "rate_limited",
+        StatusCode::FORBIDDEN => "forbidden",
+        StatusCode::NOT_FOUND => "not_found",
+        _ if status.is_client_error() => "invalid",
+        _ => "internal",
+    };
+    let message = if status.is_client_error() { err.0.to_string() } else { "Something went wrong".to_owned() };
+    let retry_after_secs = err.1.headers().get(header::RETRY_AFTER)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.parse::<u64>().ok());
+    json!({ "type": "error", "code": code, "message": message, "retry_after_secs": retry_after_secs }).to_string()
+}
 
 #[debug_handler(state = crate::AppState)]
+#[allow(clippy::too_many_arguments)]
 pub async fn room_ws(
     Path(room_id): Path<Uuid>,
     State(db_pool): State<SqlitePool>,
-    State(tx): State<broadcast::Sender<String>>,
-    session: Session,
+    State(tx): State<broadcast::Sender<RoomEvent>>,
+    State(guests): State<GuestConfig>,
+    State(store): State<SqliteStore>,
+    State(presence): State<Presence>,
+    State(limits): State<Limits>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    caller: Option<Caller>,
+    _: AllowedOrigin,
+    Query(WsQuery { since }): Query<WsQuery>,
 
     ws: WebSocketUpgrade,
-) -> impl IntoResponse {
-    let user_id = session.get::<String>(USER_ID).await.unwrap().unwrap();
-
-    let (is_public,): (bool,) =
-        sqlx::query_as("SELECT is_public FROM rooms WHERE uuid=?")
-            .bind(room_id.to_string())
-            .fetch_one(&db_pool)
-            .await
-            .unwrap();
-
-    let profile_id: Option<(String,)> = sqlx::query_as("SELECT uuid FROM profiles WHERE user_id=? AND room_id=?")
-        .bind(&user_id)
-        .bind(room_id.to_string())
-        .fetch_optional(&db_pool)
-        .await
-        .unwrap();
-
-    let profile_id = if let Some((profile_id,)) = profile_id {
-        Uuid::parse_str(&profile_id).unwrap()
-    } else if is_public {
-        auth::create_profile(&db_pool, &user_id, &room_id.to_string()).await.unwrap().0
-    } else {
-        panic!();
+) -> AppResult<Response> {
+    let sorry = res::sorry("room");
+
+    let Some(Caller { user_id, can_write, session_id }) = caller else {
+        return sorry;
+    };
+
+    let Some(access) = room_access(&db_pool, Some(&user_id), room_id).await? else {
+        return sorry;
+    };
+
+    // before joining anyone up, so a flood of sockets can't make profiles
+    let Some(connection) = limits.connect(addr.ip()) else {
+        return Err((StatusCode::TOO_MANY_REQUESTS, "Too many connections from your address").into_response().into());
     };
 
-    ws.on_upgrade(async move |stream| {
+    let profile_id = member_profile(&db_pool, &guests, &user_id, room_id, access).await?;
+    let (alias,): (String,) = sqlx::query_as("SELECT alias FROM profiles WHERE uuid=?")
+        .bind(profile_id.to_string())
+        .fetch_one(&db_pool)
+        .await?;
+
+    let mut revoked = store.subscribe();
+
+    Ok(ws.on_upgrade(async move |stream| {
+        // counts against the address until the socket closes
+        let _connection = connection;
+        // subscribe before replaying so nothing falls in between, doubles are
+        // replaced by id on the page
         let mut rx = tx.subscribe();
         let (mut sender, mut receiver) = stream.split();
+        // frames for this socket alone, from the receiving side
+        let (replies_tx, mut replies) = mpsc::unbounded_channel::<String>();
+
+        if let Some(joined) = presence.join(room_id, profile_id, &alias) {
+            let _ = tx.send(joined);
+        }
+
+        let missed = match since {
+            Some(since) => msg::missed_msgs(&db_pool, room_id, since).await.unwrap_or_default(),
+            None => Vec::new(),
+        };
 
         let mut broadcast_task = tokio::spawn(async move {
-            while let Ok(msg) = rx.recv().await {
-                if sender.send(msg.into()).await.is_err() {
-                    break;
+            for event in missed {
+                if sender.send(event.data.into()).await.is_err() {
+                    return;
+                }
+            }
+
+            loop {
+                tokio::select! {
+                    event = rx.recv() => {
+                        let event = match event {
+                            Ok(event) => event,
+                            Err(RecvError::Lagged(_)) => {
+                                let _ = sender.send(RESYNC.into()).await;
+                                break;
+                            },
+                            Err(RecvError::Closed) => break,
+                        };
+                        if event.room_id != room_id || !event.is_for(Some(profile_id)) {
+                            continue;
+                        }
+                        if event.kicks(Some(profile_id)) {
+                            let _ = sender.send(Message::Close(Some(CloseFrame {
+                                code: close_code::POLICY,
+                                reason: "kicked".into(),
+                            }))).await;
+                            break;
+                        }
+
+                        if sender.send(event.data.into()).await.is_err() {
+                            break;
+                        }
+                    },
+                    Some(reply) = replies.recv() => if sender.send(reply.into()).await.is_err() {
+                        break;
+                    },
+                    // hang up once the session this socket was opened with is gone
+                    Ok(id) = revoked.recv() => if session_id == Some(id) {
+                        let _ = sender.send(Message::Close(Some(CloseFrame {
+                            code: close_code::POLICY,
+                            reason: "signed out".into(),
+                        }))).await;
+                        break;
+                    },
                 }
             }
         });
 
-        while let Some(Ok(msg)) = receiver.next().await {
-            let Ok(msg) = serde_json::from_slice(&msg.into_data()) else {
-                continue
-            };
+        let (receive_tx, receive_presence) = (tx.clone(), presence.clone());
+        let mut receive_task = tokio::spawn(async move {
+            let (tx, presence) = (receive_tx, receive_presence);
+            while let Some(Ok(msg)) = receiver.next().await {
+                let data = msg.into_data();
 
-            let _ = msg::send_msg(&db_pool, &tx, profile_id, room_id, msg).await;
-        }
+                if let Ok(Signal::Read { id }) = serde_json::from_slice(&data) {
+                    let _ = mark_read(&db_pool, profile_id, id).await;
+                    continue;
+                }
+
+                // read-only tokens just listen
+                if !can_write {
+                    continue;
+                }
+
+                if let Ok(Signal::Typing) = serde_json::from_slice(&data) {
+                    if let Some(typing) = presence.typing(room_id, profile_id) {
+                        let _ = tx.send(typing);
+                    }
+                    continue;
+                }
+
+                let Ok(msg) = serde_json::from_slice(&data) else {
+                    continue
+                };
+
+                let sent = match can_send(&db_pool, &guests, &limits, &user_id, profile_id).await {
+                    Ok(()) => msg::send_msg(&db_pool, &tx, profile_id, room_id, msg).await,
+                    Err(err) => Err(err),
+                };
+                match sent {
+                    Ok(Sent::Posted(_)) => (),
+                    Ok(Sent::Held(_)) => { let _ = replies_tx.send(json!({ "type": "held", "message": HELD }).to_string()); },
+                    Err(err) => { let _ = replies_tx.send(refusal(&err)); },
+                }
+            }
+        });
 
         tokio::select! {
-            _ = &mut broadcast_task => broadcast_task.abort(),
+            _ = &mut broadcast_task => receive_task.abort(),
+            _ = &mut receive_task => broadcast_task.abort(),
         };
-    })
-}
\ No newline at end of file
+
+        if let Some(left) = presence.leave(room_id, profile_id) {
+            let _ = tx.send(left);
+        }
+    }).into_response())
+}
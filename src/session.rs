@@ -1,4 +1,9 @@
 pub(crate) const USER_ID: &str = "user_id";
 pub(crate) const CSRF_STATE: &str = "csrf_state";
 pub(crate) const PKCE_VERIFIER: &str = "pkce_verifier";
-pub(crate) const RETURN_URL: &str = "return_url";
\ No newline at end of file
+pub(crate) const RETURN_URL: &str = "return_url";
+pub(crate) const NONCE: &str = "nonce";
+pub(crate) const LINKING: &str = "linking";
+pub(crate) const CSRF_TOKEN: &str = "csrf_token";
+pub(crate) const PROVIDER: &str = "provider";
+pub(crate) const USER_AGENT: &str = "user_agent";
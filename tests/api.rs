@@ -0,0 +1,210 @@
+//! Runs `/api/v1` on localhost, signed in with API tokens or a session cookie.
+
+mod common;
+
+use reqwest::StatusCode;
+use serde_json::{json, Value};
+use silentkisses::{api, auth::{create_token, resolve_user}, rooms::{add_rule, approve_held, ban_profile, held_messages, RuleAction, RuleKind}};
+use sqlx::SqlitePool;
+use tokio::sync::broadcast;
+use uuid::Uuid;
+
+async fn serve(db_pool: SqlitePool) -> String {
+    format!("http://{}/api/v1", common::serve(db_pool, 16).await.0)
+}
+
+/// A room with its owner in it, plus a token for it.
+async fn setup(can_write: bool) -> (String, Uuid, String) {
+    let db_pool = common::db().await;
+    let owner = resolve_user(&db_pool, "github", "4242", None).await.unwrap();
+    let room_id = common::room(&db_pool, false).await;
+    common::profile(&db_pool, &owner, room_id).await;
+    let (_, secret) = create_token(&db_pool, &owner, "CI", &[room_id], can_write).await.unwrap();
+    (serve(db_pool).await, room_id, secret)
+}
+
+async fn call(method: reqwest::Method, url: String, secret: &str, body: Option<Value>) -> (StatusCode, Value) {
+    let mut request = reqwest::Client::new().request(method, url).bearer_auth(secret);
+    if let Some(body) = body {
+        request = request.json(&body);
+    }
+    let response = request.send().await.unwrap();
+    let status = response.status();
+    (status, response.json().await.unwrap_or(Value::Null))
+}
+
+#[tokio::test]
+async fn bots_post_page_edit_and_delete() {
+    let (base, room_id, secret) = setup(true).await;
+    let messages = format!("{base}/rooms/{room_id}/messages");
+
+    let mut ids = Vec::new();
+    for content in ["one", "two", "three"] {
+        let (status, message) = call(reqwest::Method::POST, messages.clone(), &secret, Some(json!({ "content": content }))).await;
+        assert_eq!(status, StatusCode::CREATED);
+        ids.push(message["id"].as_str().unwrap().to_owned());
+    }
+
+    let (status, page) = call(reqwest::Method::GET, format!("{messages}?limit=2"), &secret, None).await;
+    assert_eq!(status, StatusCode::OK);
+    let contents: Vec<_> = page["messages"].as_array().unwrap().iter().map(|m| m["content"].clone()).collect();
+    assert_eq!(contents, [json!("three"), json!("two")]);
+
+    let (_, page) = call(reqwest::Method::GET, format!("{messages}?limit=2&before={}", page["next_before"].as_str().unwrap()), &secret, None).await;
+    assert_eq!(page["messages"][0]["content"], "one");
+    assert!(page["next_before"].is_null());
+
+    let (status, edited) = call(reqwest::Method::PATCH, format!("{messages}/{}", ids[0]), &secret, Some(json!({ "content": "uno" }))).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(edited["content"], "uno");
+    assert!(edited["edited_at"].is_i64());
+
+    let (status, _) = call(reqwest::Method::DELETE, format!("{messages}/{}", ids[1]), &secret, None).await;
+    assert_eq!(status, StatusCode::NO_CONTENT);
+    let (_, page) = call(reqwest::Method::GET, messages, &secret, None).await;
+    assert_eq!(page["messages"][1]["content"], "");
+    assert!(page["messages"][1]["deleted_at"].is_i64());
+
+    let (status, rooms) = call(reqwest::Method::GET, format!("{base}/rooms"), &secret, None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(rooms[0]["id"], room_id.to_string());
+    let (status, profile) = call(reqwest::Method::GET, format!("{base}/profiles/{}", rooms[0]["profile_id"].as_str().unwrap()), &secret, None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(profile["alias"], "CI [bot]");
+}
+
+#[tokio::test]
+async fn same_rules_as_the_pages() {
+    let (base, room_id, secret) = setup(false).await;
+
+    let (status, error) = call(reqwest::Method::POST, format!("{base}/rooms/{room_id}/messages"), &secret, Some(json!({ "content": "hi" }))).await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+    assert!(error["error"].is_string());
+
+    let (status, _) = call(reqwest::Method::GET, format!("{base}/rooms/{room_id}"), &secret, None).await;
+    assert_eq!(status, StatusCode::OK);
+    let (status, _) = call(reqwest::Method::GET, format!("{base}/rooms/{}", Uuid::now_v7()), &secret, None).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+
+    let response = reqwest::get(format!("{base}/rooms")).await.unwrap();
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    assert!(response.json::<Value>().await.unwrap()["error"].is_string());
+}
+
+#[tokio::test]
+async fn held_messages_wait_for_a_moderator() {
+    let db_pool = common::db().await;
+    let owner = resolve_user(&db_pool, "github", "4242", None).await.unwrap();
+    let room_id = common::room(&db_pool, false).await;
+    common::profile(&db_pool, &owner, room_id).await;
+    let (_, secret) = create_token(&db_pool, &owner, "CI", &[room_id], true).await.unwrap();
+    add_rule(&db_pool, room_id, RuleKind::Word, "sale", RuleAction::Hold).await.unwrap();
+    add_rule(&db_pool, room_id, RuleKind::Word, "scam", RuleAction::Reject).await.unwrap();
+    let base = serve(db_pool.clone()).await;
+    let messages = format!("{base}/rooms/{room_id}/messages");
+
+    let (status, body) = call(reqwest::Method::POST, messages.clone(), &secret, Some(json!({ "content": "big sale" }))).await;
+    assert_eq!(status, StatusCode::ACCEPTED);
+    assert!(body["held_id"].is_string());
+    assert!(body.get("error").is_none());
+    let (status, _) = call(reqwest::Method::POST, messages.clone(), &secret, Some(json!({ "content": "a scam" }))).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(common::count(&db_pool, "messages", room_id).await, 0);
+
+    let held = held_messages(&db_pool, room_id).await.unwrap();
+    assert_eq!(held.len(), 1);
+    approve_held(&db_pool, &broadcast::channel(16).0, room_id, held[0].id).await.unwrap();
+    let (_, page) = call(reqwest::Method::GET, messages, &secret, None).await;
+    assert_eq!(page["messages"][0]["content"], json!("big sale"));
+    assert!(held_messages(&db_pool, room_id).await.unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn held_messages_stay_masked_and_banned_senders_stay_out() {
+    let db_pool = common::db().await;
+    let owner = resolve_user(&db_pool, "github", "4242", None).await.unwrap();
+    let room_id = common::room(&db_pool, false).await;
+    common::profile(&db_pool, &owner, room_id).await;
+    let (_, secret) = create_token(&db_pool, &owner, "CI", &[room_id], true).await.unwrap();
+    add_rule(&db_pool, room_id, RuleKind::Word, "sale", RuleAction::Hold).await.unwrap();
+    add_rule(&db_pool, room_id, RuleKind::Word, "darn", RuleAction::Mask).await.unwrap();
+    let base = serve(db_pool.clone()).await;
+    let messages = format!("{base}/rooms/{room_id}/messages");
+    let tx = broadcast::channel(16).0;
+
+    call(reqwest::Method::POST, messages.clone(), &secret, Some(json!({ "content": "darn good sale" }))).await;
+    let held = held_messages(&db_pool, room_id).await.unwrap();
+    assert_eq!(held[0].content, "**** good sale");
+    approve_held(&db_pool, &tx, room_id, held[0].id).await.unwrap();
+    let (_, page) = call(reqwest::Method::GET, messages.clone(), &secret, None).await;
+    assert_eq!(page["messages"][0]["content"], json!("**** good sale"));
+
+    call(reqwest::Method::POST, messages.clone(), &secret, Some(json!({ "content": "another sale" }))).await;
+    let held = held_messages(&db_pool, room_id).await.unwrap();
+    ban_profile(&db_pool, &tx, room_id, held[0].profile_id).await.unwrap();
+    assert!(approve_held(&db_pool, &tx, room_id, held[0].id).await.is_err());
+    // still there to be rejected
+    assert_eq!(held_messages(&db_pool, room_id).await.unwrap().len(), 1);
+}
+
+#[test]
+fn openapi_covers_every_route() {
+    let openapi = serde_json::to_value(api::openapi()).unwrap();
+
+    let paths = &openapi["paths"];
+    assert!(paths["/rooms"]["get"].is_object());
+    assert!(paths["/rooms/{room_id}"]["get"].is_object());
+    assert!(paths["/rooms/{room_id}/presence"]["get"].is_object());
+    assert!(paths["/rooms/{room_id}/messages"]["get"].is_object());
+    assert!(paths["/rooms/{room_id}/messages"]["post"].is_object());
+    assert!(paths["/rooms/{room_id}/messages/{message_id}"]["patch"].is_object());
+    assert!(paths["/rooms/{room_id}/messages/{message_id}"]["delete"].is_object());
+    assert!(paths["/profiles/{profile_id}"]["get"].is_object());
+    assert!(openapi["components"]["schemas"]["Message"].is_object());
+    assert!(openapi["components"]["securitySchemes"]["token"].is_object());
+}
+
+#[tokio::test]
+async fn muted_bots_cannot_edit_either() {
+    let db_pool = common::db().await;
+    let owner = resolve_user(&db_pool, "github", "4242", None).await.unwrap();
+    let room_id = common::room(&db_pool, false).await;
+    common::profile(&db_pool, &owner, room_id).await;
+    let (bot, secret) = create_token(&db_pool, &owner, "CI", &[room_id], true).await.unwrap();
+    let base = serve(db_pool.clone()).await;
+    let messages = format!("{base}/rooms/{room_id}/messages");
+
+    let (_, message) = call(reqwest::Method::POST, messages.clone(), &secret, Some(json!({ "content": "fine" }))).await;
+    sqlx::query("UPDATE profiles SET muted_until=unixepoch()+600 WHERE user_id=?")
+        .bind(&bot)
+        .execute(&db_pool)
+        .await
+        .unwrap();
+
+    let (status, _) = call(reqwest::Method::PATCH, format!("{messages}/{}", message["id"].as_str().unwrap()), &secret, Some(json!({ "content": "spam" }))).await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+}
+
+#[tokio::test]
+async fn admins_delete_anyones_messages() {
+    let db_pool = common::db().await;
+    let owner = resolve_user(&db_pool, "github", "4242", None).await.unwrap();
+    let admin = resolve_user(&db_pool, "github", "1", None).await.unwrap();
+    sqlx::query("UPDATE users SET is_admin=1 WHERE id=?")
+        .bind(&admin)
+        .execute(&db_pool)
+        .await
+        .unwrap();
+    let room_id = common::room(&db_pool, true).await;
+    let profile_id = common::profile(&db_pool, &owner, room_id).await;
+    let message = common::message(&db_pool, room_id, profile_id, None, "spam").await;
+    let (host, _, store) = common::serve(db_pool.clone(), 16).await;
+
+    let cookie = common::session(&store, &admin).await;
+    let response = reqwest::Client::new().delete(format!("http://{host}/api/v1/rooms/{room_id}/messages/{message}"))
+        .header("cookie", cookie)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+}
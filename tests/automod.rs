@@ -0,0 +1,56 @@
+mod common;
+
+use silentkisses::{auth::resolve_user, rooms::{add_rule, check, remove_rule, RuleAction, RuleKind, Verdict}};
+
+#[tokio::test]
+async fn rules_mask_hold_and_refuse() {
+    let db_pool = common::db().await;
+    let user = resolve_user(&db_pool, "github", "4242", None).await.unwrap();
+    let room_id = common::room(&db_pool, true).await;
+    let profile_id = common::profile(&db_pool, &user, room_id).await;
+
+    add_rule(&db_pool, room_id, RuleKind::Word, "darn", RuleAction::Mask).await.unwrap();
+    add_rule(&db_pool, room_id, RuleKind::LinkAllow, "example.org", RuleAction::Hold).await.unwrap();
+    let long = add_rule(&db_pool, room_id, RuleKind::MaxLength, "30", RuleAction::Reject).await.unwrap();
+
+    assert_eq!(check(&db_pool, room_id, profile_id, "oh DARN it").await.unwrap(), Verdict::Pass("oh **** it".to_owned()));
+    // whole words only
+    assert_eq!(check(&db_pool, room_id, profile_id, "darning").await.unwrap(), Verdict::Pass("darning".to_owned()));
+    assert_eq!(check(&db_pool, room_id, profile_id, "https://docs.example.org/x").await.unwrap(), Verdict::Pass("https://docs.example.org/x".to_owned()));
+    assert!(matches!(check(&db_pool, room_id, profile_id, "http://evil.test").await.unwrap(), Verdict::Hold(..)));
+    // refusing wins
+    assert!(matches!(check(&db_pool, room_id, profile_id, "http://evil.test is where it's at").await.unwrap(), Verdict::Reject(_)));
+
+    remove_rule(&db_pool, room_id, long).await.unwrap();
+    assert!(matches!(check(&db_pool, room_id, profile_id, "http://evil.test is where it's at").await.unwrap(), Verdict::Hold(..)));
+
+    // every hit is logged, per rule
+    assert_eq!(common::count(&db_pool, "automod_log", room_id).await, 5);
+}
+
+#[tokio::test]
+async fn patterns_links_and_mentions() {
+    let db_pool = common::db().await;
+    let user = resolve_user(&db_pool, "github", "4242", None).await.unwrap();
+    let room_id = common::room(&db_pool, true).await;
+    let profile_id = common::profile(&db_pool, &user, room_id).await;
+
+    add_rule(&db_pool, room_id, RuleKind::Regex, r"\d{3}-\d{4}", RuleAction::Mask).await.unwrap();
+    add_rule(&db_pool, room_id, RuleKind::LinkDeny, "spam.test, ads.test", RuleAction::Mask).await.unwrap();
+    add_rule(&db_pool, room_id, RuleKind::MaxMentions, "2", RuleAction::Reject).await.unwrap();
+
+    assert_eq!(
+        check(&db_pool, room_id, profile_id, "call 555-1234 or see http://www.ads.test/now").await.unwrap(),
+        Verdict::Pass("call ******** or see ***********************".to_owned()),
+    );
+    assert!(matches!(check(&db_pool, room_id, profile_id, "@a @b hi").await.unwrap(), Verdict::Pass(_)));
+    assert!(matches!(check(&db_pool, room_id, profile_id, "@a @b @c hi").await.unwrap(), Verdict::Reject(_)));
+    // emails aren't mentions
+    assert!(matches!(check(&db_pool, room_id, profile_id, "a@b.c d@e.f g@h.i").await.unwrap(), Verdict::Pass(_)));
+
+    // nonsense is turned away up front
+    assert!(add_rule(&db_pool, room_id, RuleKind::Regex, "(", RuleAction::Reject).await.is_err());
+    assert!(add_rule(&db_pool, room_id, RuleKind::MaxLength, "lots", RuleAction::Reject).await.is_err());
+    assert!(add_rule(&db_pool, room_id, RuleKind::MaxLength, "10", RuleAction::Mask).await.is_err());
+    assert!(add_rule(&db_pool, room_id, RuleKind::Word, "  ", RuleAction::Mask).await.is_err());
+}
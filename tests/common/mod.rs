@@ -0,0 +1,111 @@
+#![allow(dead_code)]
+
+use std::{collections::HashMap, net::SocketAddr};
+
+use axum::{middleware, Router};
+use serde_json::json;
+use silentkisses::{api, auth::{self, AllowedOrigins, Clients, GuestConfig, SqliteStore}, rooms::{self, Limits, Presence, RoomEvent}, AppState};
+use sqlx::{sqlite::SqlitePoolOptions, SqlitePool};
+use tokio::sync::broadcast;
+use time::OffsetDateTime;
+use tower_sessions::{session::{Id, Record}, SessionManagerLayer, SessionStore};
+use uuid::Uuid;
+
+/// Fresh in-memory database with every migration applied.
+pub async fn db() -> SqlitePool {
+    let db_pool = SqlitePoolOptions::new()
+        .max_connections(1)
+        .connect("sqlite::memory:")
+        .await
+        .unwrap();
+    sqlx::migrate!().run(&db_pool).await.unwrap();
+    db_pool
+}
+
+pub async fn room(db_pool: &SqlitePool, is_public: bool) -> Uuid {
+    let uuid = Uuid::now_v7();
+    sqlx::query("INSERT INTO rooms (uuid,name,is_public) values (?,?,?)")
+        .bind(uuid.to_string())
+        .bind("test room")
+        .bind(is_public)
+        .execute(db_pool)
+        .await
+        .unwrap();
+    uuid
+}
+
+pub async fn profile(db_pool: &SqlitePool, user_id: &str, room_id: Uuid) -> Uuid {
+    let uuid = Uuid::now_v7();
+    sqlx::query("INSERT INTO profiles (uuid,user_id,room_id,handle,alias) VALUES (?,?,?,?,?)")
+        .bind(uuid.to_string())
+        .bind(user_id)
+        .bind(room_id.to_string())
+        .bind(format!("user{}", uuid.simple()))
+        .bind("Test Profile")
+        .execute(db_pool)
+        .await
+        .unwrap();
+    uuid
+}
+
+pub async fn message(db_pool: &SqlitePool, room_id: Uuid, profile_id: Uuid, reply_to_id: Option<Uuid>, content: &str) -> Uuid {
+    let id = Uuid::now_v7();
+    sqlx::query("INSERT INTO messages (id,room_id,profile_id,reply_to_id,content,created_at) values (?,?,?,?,?,unixepoch())")
+        .bind(id.to_string())
+        .bind(room_id.to_string())
+        .bind(profile_id.to_string())
+        .bind(reply_to_id.as_ref().map(Uuid::to_string))
+        .bind(content)
+        .execute(db_pool)
+        .await
+        .unwrap();
+    id
+}
+
+pub async fn count(db_pool: &SqlitePool, table: &str, room_id: Uuid) -> i64 {
+    sqlx::query_as::<_, (i64,)>(&format!("SELECT count(*) FROM {table} WHERE room_id=?"))
+        .bind(room_id.to_string())
+        .fetch_one(db_pool)
+        .await
+        .unwrap()
+        .0
+}
+
+/// Runs `/r` and `/api/v1` on a free localhost port, signed in with API tokens
+/// or session cookies. Returns the `host:port`, the room event bus, which
+/// holds `capacity` events per socket, and the session store.
+pub async fn serve(db_pool: SqlitePool, capacity: usize) -> (String, broadcast::Sender<RoomEvent>, SqliteStore) {
+    let tx = broadcast::channel(capacity).0;
+    let state = AppState {
+        db_pool: db_pool.clone(),
+        clients: Clients::from_json(json!({})).unwrap(),
+        guests: GuestConfig { enabled: false, messages_per_minute: 10, max_rooms: 5 },
+        origins: AllowedOrigins(Vec::new()),
+        sessions: SqliteStore::new(db_pool),
+        presence: Presence::default(),
+        limits: Limits::default(),
+        tx: tx.clone(),
+    };
+    let app = Router::new()
+        .nest("/r", rooms::router())
+        .nest("/api/v1", api::router())
+        .with_state(state.clone())
+        .layer(middleware::from_fn_with_state(state.clone(), auth::bearer_auth))
+        .layer(SessionManagerLayer::new(state.sessions.clone()).with_secure(false));
+
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let host = listener.local_addr().unwrap().to_string();
+    tokio::spawn(async move { axum::serve(listener, app.into_make_service_with_connect_info::<SocketAddr>()).await.unwrap() });
+    (host, tx, state.sessions)
+}
+
+/// Signs `user_id` in through `store`, returns the `Cookie` header for it.
+pub async fn session(store: &SqliteStore, user_id: &str) -> String {
+    let mut record = Record {
+        id: Id::default(),
+        data: HashMap::from([("user_id".to_owned(), json!(user_id))]),
+        expiry_date: OffsetDateTime::now_utc() + time::Duration::minutes(5),
+    };
+    store.create(&mut record).await.unwrap();
+    format!("id={}", record.id)
+}
@@ -0,0 +1,80 @@
+//! Runs the CSRF middleware behind a real session layer on localhost.
+
+use axum::{http::{header, HeaderMap, HeaderValue}, middleware, routing::{get, post}, Router};
+use reqwest::StatusCode;
+use silentkisses::{auth::{self, AllowedOrigins}, AppResult};
+use tower_sessions::{MemoryStore, Session, SessionManagerLayer};
+
+async fn token(session: Session) -> AppResult<String> {
+    auth::csrf_token(&session).await
+}
+
+/// Starts the app, then fetches a token the way a page render would.
+async fn app() -> (String, String, String) {
+    let app = Router::new()
+        .route("/token", get(token))
+        .route("/form", post(async || "ok"))
+        .layer(middleware::from_fn(auth::verify_csrf))
+        .layer(SessionManagerLayer::new(MemoryStore::default()).with_secure(false));
+
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let base = format!("http://{}", listener.local_addr().unwrap());
+    tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+    let response = reqwest::get(format!("{base}/token")).await.unwrap();
+    let cookie = response.headers()[header::SET_COOKIE].to_str().unwrap()
+        .split(';').next().unwrap().to_owned();
+    let token = response.text().await.unwrap();
+    (base, cookie, token)
+}
+
+async fn post_form(base: &str, cookie: &str, body: &str) -> StatusCode {
+    reqwest::Client::new().post(format!("{base}/form"))
+        .header(header::COOKIE, cookie)
+        .header(header::CONTENT_TYPE, "application/x-www-form-urlencoded")
+        .body(body.to_owned())
+        .send()
+        .await
+        .unwrap()
+        .status()
+}
+
+#[tokio::test]
+async fn posts_need_the_session_token() {
+    let (base, cookie, token) = app().await;
+
+    assert_eq!(post_form(&base, &cookie, "name=room").await, StatusCode::FORBIDDEN);
+    assert_eq!(post_form(&base, &cookie, "name=room&csrf_token=guess").await, StatusCode::FORBIDDEN);
+    assert_eq!(post_form(&base, &cookie, &format!("name=room&csrf_token={token}")).await, StatusCode::OK);
+
+    let by_header = reqwest::Client::new().post(format!("{base}/form"))
+        .header(header::COOKIE, &cookie)
+        .header(auth::CSRF_HEADER, &token)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(by_header.status(), StatusCode::OK);
+}
+
+#[tokio::test]
+async fn tokens_dont_cross_sessions() {
+    let (base, _, token) = app().await;
+    let (_, other_cookie, _) = app().await;
+
+    assert_eq!(post_form(&base, &other_cookie, &format!("csrf_token={token}")).await, StatusCode::FORBIDDEN);
+}
+
+#[test]
+fn socket_origins_are_checked() {
+    let origins = AllowedOrigins(vec!["http://localhost:8080".to_owned()]);
+    let with_origin = |origin: &'static str| {
+        let mut headers = HeaderMap::new();
+        headers.insert(header::ORIGIN, HeaderValue::from_static(origin));
+        headers
+    };
+
+    assert!(origins.allows(&with_origin("http://localhost:8080")));
+    assert!(!origins.allows(&with_origin("https://evil.example")));
+    assert!(!origins.allows(&with_origin("http://localhost:8080.evil.example")));
+    assert!(origins.allows(&HeaderMap::new()));
+}
@@ -0,0 +1,139 @@
+mod common;
+
+use reqwest::StatusCode;
+use silentkisses::{auth::{create_guest, create_token, resolve_user}, rooms::{block_profile, find_profile, has_blocked, room_access, send_dm, unblock_profile, RoomAccess}};
+use tokio::sync::broadcast;
+
+#[tokio::test]
+async fn dms_reach_only_the_two_profiles() {
+    let db_pool = common::db().await;
+    let me = resolve_user(&db_pool, "github", "4242", None).await.unwrap();
+    let them = resolve_user(&db_pool, "github", "2424", None).await.unwrap();
+    let bystander = resolve_user(&db_pool, "github", "1111", None).await.unwrap();
+    let room_id = common::room(&db_pool, false).await;
+    let mine = common::profile(&db_pool, &me, room_id).await;
+    let theirs = common::profile(&db_pool, &them, room_id).await;
+    let watching = common::profile(&db_pool, &bystander, room_id).await;
+
+    let (tx, mut rx) = broadcast::channel(16);
+    send_dm(&db_pool, &tx, mine, theirs, "psst").await.unwrap();
+
+    let event = rx.recv().await.unwrap();
+    assert!(event.data.contains("psst"));
+    assert!(event.is_for(Some(mine)) && event.is_for(Some(theirs)));
+    assert!(!event.is_for(Some(watching)) && !event.is_for(None));
+    assert!(!event.data.contains(&me) && !event.data.contains(&them));
+
+    // kept apart from the room
+    assert_eq!(common::count(&db_pool, "messages", room_id).await, 0);
+    assert_eq!(common::count(&db_pool, "direct_messages", room_id).await, 1);
+
+    assert!(send_dm(&db_pool, &tx, mine, mine, "me").await.is_err());
+    let elsewhere = common::profile(&db_pool, &them, common::room(&db_pool, false).await).await;
+    assert!(send_dm(&db_pool, &tx, mine, elsewhere, "hi").await.is_err());
+}
+
+#[tokio::test]
+async fn blocks_stop_dms_both_ways() {
+    let db_pool = common::db().await;
+    let me = resolve_user(&db_pool, "github", "4242", None).await.unwrap();
+    let them = resolve_user(&db_pool, "github", "2424", None).await.unwrap();
+    let room_id = common::room(&db_pool, false).await;
+    let mine = common::profile(&db_pool, &me, room_id).await;
+    let theirs = common::profile(&db_pool, &them, room_id).await;
+    let tx = broadcast::channel(16).0;
+
+    block_profile(&db_pool, theirs, mine).await.unwrap();
+    assert!(send_dm(&db_pool, &tx, mine, theirs, "hello?").await.is_err());
+    assert!(send_dm(&db_pool, &tx, theirs, mine, "go away").await.is_err());
+
+    unblock_profile(&db_pool, theirs, mine).await.unwrap();
+    assert!(send_dm(&db_pool, &tx, mine, theirs, "hello?").await.is_ok());
+}
+
+#[tokio::test]
+async fn profiles_are_found_by_uuid_or_handle() {
+    let db_pool = common::db().await;
+    let me = resolve_user(&db_pool, "github", "4242", None).await.unwrap();
+    let room_id = common::room(&db_pool, false).await;
+    let mine = common::profile(&db_pool, &me, room_id).await;
+    let handle: String = sqlx::query_scalar("SELECT handle FROM profiles WHERE uuid=?")
+        .bind(mine.to_string())
+        .fetch_one(&db_pool)
+        .await
+        .unwrap();
+
+    assert_eq!(find_profile(&db_pool, room_id, &mine.to_string()).await.unwrap(), Some(mine));
+    assert_eq!(find_profile(&db_pool, room_id, &handle).await.unwrap(), Some(mine));
+    assert_eq!(find_profile(&db_pool, room_id, &format!("@{handle}")).await.unwrap(), Some(mine));
+
+    let other_room = common::room(&db_pool, false).await;
+    assert_eq!(find_profile(&db_pool, other_room, &handle).await.unwrap(), None);
+}
+
+#[tokio::test]
+async fn muted_profiles_cannot_dm() {
+    let db_pool = common::db().await;
+    let me = resolve_user(&db_pool, "github", "4242", None).await.unwrap();
+    let them = resolve_user(&db_pool, "github", "2424", None).await.unwrap();
+    let room_id = common::room(&db_pool, false).await;
+    let mine = common::profile(&db_pool, &me, room_id).await;
+    let theirs = common::profile(&db_pool, &them, room_id).await;
+    sqlx::query("UPDATE profiles SET muted_until=unixepoch()+600 WHERE uuid=?")
+        .bind(mine.to_string())
+        .execute(&db_pool)
+        .await
+        .unwrap();
+
+    assert!(send_dm(&db_pool, &broadcast::channel(16).0, mine, theirs, "psst").await.is_err());
+}
+
+#[tokio::test]
+async fn read_only_tokens_cannot_dm_or_block() {
+    let db_pool = common::db().await;
+    let me = resolve_user(&db_pool, "github", "4242", None).await.unwrap();
+    let them = resolve_user(&db_pool, "github", "2424", None).await.unwrap();
+    let room_id = common::room(&db_pool, false).await;
+    common::profile(&db_pool, &me, room_id).await;
+    let theirs = common::profile(&db_pool, &them, room_id).await;
+    let (bot, secret) = create_token(&db_pool, &me, "CI", &[room_id], false).await.unwrap();
+    let Some(RoomAccess::Member(botting)) = room_access(&db_pool, Some(&bot), room_id).await.unwrap() else {
+        panic!("bot should be in the room");
+    };
+    let base = format!("http://{}/r/{room_id}/dm/{theirs}", common::serve(db_pool.clone(), 16).await.0);
+
+    let client = reqwest::Client::new();
+    for (url, form) in [(base.clone(), vec![("content", "psst")]), (format!("{base}/block"), vec![])] {
+        let response = client.post(url).bearer_auth(&secret).form(&form).send().await.unwrap();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+        assert!(response.text().await.unwrap().contains("read only"));
+    }
+    assert_eq!(common::count(&db_pool, "direct_messages", room_id).await, 0);
+    assert!(!has_blocked(&db_pool, botting, theirs).await.unwrap());
+}
+
+#[tokio::test]
+async fn guests_dms_count_against_their_limit() {
+    let db_pool = common::db().await;
+    let guest = create_guest(&db_pool).await.unwrap();
+    let them = resolve_user(&db_pool, "github", "2424", None).await.unwrap();
+    let room_id = common::room(&db_pool, true).await;
+    let mine = common::profile(&db_pool, &guest, room_id).await;
+    let theirs = common::profile(&db_pool, &them, room_id).await;
+    let tx = broadcast::channel(16).0;
+    // the test server lets guests send 10 a minute
+    for _ in 0..10 {
+        send_dm(&db_pool, &tx, mine, theirs, "hi").await.unwrap();
+    }
+    let (host, _, store) = common::serve(db_pool.clone(), 16).await;
+
+    let cookie = common::session(&store, &guest).await;
+    let response = reqwest::Client::new().post(format!("http://{host}/r/{room_id}/dm/{theirs}"))
+        .header("cookie", cookie)
+        .form(&[("content", "one more")])
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+    assert_eq!(common::count(&db_pool, "direct_messages", room_id).await, 10);
+}
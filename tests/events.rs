@@ -0,0 +1,244 @@
+//! Runs the rooms' socketless endpoints on localhost, signed in with an API token.
+
+mod common;
+
+use reqwest::StatusCode;
+use serde_json::json;
+use silentkisses::{auth::{create_token, resolve_user}, rooms};
+use sqlx::SqlitePool;
+use tokio::sync::broadcast;
+
+async fn serve(db_pool: SqlitePool) -> String {
+    format!("http://{}/r", common::serve(db_pool, 16).await.0)
+}
+
+#[tokio::test]
+async fn posts_then_resumes_after_the_last_event_id() {
+    let db_pool = common::db().await;
+    let owner = resolve_user(&db_pool, "github", "4242", None).await.unwrap();
+    let room_id = common::room(&db_pool, false).await;
+    common::profile(&db_pool, &owner, room_id).await;
+    let (_, secret) = create_token(&db_pool, &owner, "CI", &[room_id], true).await.unwrap();
+    let base = serve(db_pool.clone()).await;
+
+    let client = reqwest::Client::new();
+    for content in ["seen", "missed"] {
+        let response = client.post(format!("{base}/{room_id}/messages"))
+            .bearer_auth(&secret)
+            .json(&json!({ "content": content }))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+    }
+
+    let ids: Vec<(String,)> = sqlx::query_as("SELECT id FROM messages WHERE room_id=? ORDER BY created_at,id")
+        .bind(room_id.to_string())
+        .fetch_all(&db_pool)
+        .await
+        .unwrap();
+
+    let mut response = client.get(format!("{base}/{room_id}/events"))
+        .bearer_auth(&secret)
+        .header("Last-Event-ID", &ids[0].0)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let chunk = response.chunk().await.unwrap().unwrap();
+    let event = String::from_utf8_lossy(&chunk);
+    assert!(event.contains(&format!("id: {}", ids[1].0)));
+    assert!(event.contains("missed"));
+    assert!(!event.contains("seen"));
+}
+
+#[tokio::test]
+async fn resuming_after_an_expired_message_still_replays() {
+    let db_pool = common::db().await;
+    let owner = resolve_user(&db_pool, "github", "4242", None).await.unwrap();
+    let room_id = common::room(&db_pool, false).await;
+    let profile_id = common::profile(&db_pool, &owner, room_id).await;
+    let (_, secret) = create_token(&db_pool, &owner, "CI", &[room_id], false).await.unwrap();
+
+    let seen = common::message(&db_pool, room_id, profile_id, None, "seen").await;
+    common::message(&db_pool, room_id, profile_id, None, "missed").await;
+    sqlx::query("UPDATE messages SET expires_at=unixepoch()-1 WHERE id=?")
+        .bind(seen.to_string())
+        .execute(&db_pool)
+        .await
+        .unwrap();
+    rooms::sweep_expired(&db_pool, &broadcast::channel(16).0).await.unwrap();
+
+    let base = serve(db_pool).await;
+    let mut response = reqwest::Client::new().get(format!("{base}/{room_id}/events"))
+        .bearer_auth(&secret)
+        .header("Last-Event-ID", seen.to_string())
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let chunk = response.chunk().await.unwrap().unwrap();
+    assert!(String::from_utf8_lossy(&chunk).contains("missed"));
+}
+
+#[tokio::test]
+async fn read_only_tokens_cannot_post() {
+    let db_pool = common::db().await;
+    let owner = resolve_user(&db_pool, "github", "4242", None).await.unwrap();
+    let room_id = common::room(&db_pool, false).await;
+    common::profile(&db_pool, &owner, room_id).await;
+    let (_, secret) = create_token(&db_pool, &owner, "CI", &[room_id], false).await.unwrap();
+    let base = serve(db_pool).await;
+
+    let response = reqwest::Client::new().post(format!("{base}/{room_id}/messages"))
+        .bearer_auth(&secret)
+        .json(&json!({ "content": "hi" }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+    let response = reqwest::get(format!("{base}/{room_id}/events")).await.unwrap();
+    assert_ne!(response.status(), StatusCode::OK);
+}
+
+#[tokio::test]
+async fn replies_to_expired_messages_still_render() {
+    let db_pool = common::db().await;
+    let owner = resolve_user(&db_pool, "github", "4242", None).await.unwrap();
+    let room_id = common::room(&db_pool, false).await;
+    let profile_id = common::profile(&db_pool, &owner, room_id).await;
+    let (_, secret) = create_token(&db_pool, &owner, "CI", &[room_id], false).await.unwrap();
+
+    let parent = common::message(&db_pool, room_id, profile_id, None, "<b>gone</b> soon").await;
+    common::message(&db_pool, room_id, profile_id, Some(parent), "reply").await;
+    sqlx::query("UPDATE messages SET expires_at=unixepoch()-1 WHERE id=?")
+        .bind(parent.to_string())
+        .execute(&db_pool)
+        .await
+        .unwrap();
+    rooms::sweep_expired(&db_pool, &broadcast::channel(16).0).await.unwrap();
+
+    let base = serve(db_pool).await;
+    let response = reqwest::Client::new().get(format!("{base}/{room_id}"))
+        .bearer_auth(&secret)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let page = response.text().await.unwrap();
+    assert!(page.contains("message expired"));
+    assert!(page.contains("reply"));
+}
+
+#[tokio::test]
+async fn slow_mode_says_how_long_to_wait() {
+    let db_pool = common::db().await;
+    let owner = resolve_user(&db_pool, "github", "4242", None).await.unwrap();
+    let room_id = common::room(&db_pool, false).await;
+    common::profile(&db_pool, &owner, room_id).await;
+    let (_, secret) = create_token(&db_pool, &owner, "CI", &[room_id], true).await.unwrap();
+    sqlx::query("UPDATE rooms SET slow_mode_secs=30 WHERE uuid=?")
+        .bind(room_id.to_string())
+        .execute(&db_pool)
+        .await
+        .unwrap();
+    let base = serve(db_pool.clone()).await;
+
+    let client = reqwest::Client::new();
+    let post = |content: &'static str| client.post(format!("{base}/{room_id}/messages"))
+        .bearer_auth(&secret)
+        .json(&json!({ "content": content }))
+        .send();
+    assert_eq!(post("first").await.unwrap().status(), StatusCode::NO_CONTENT);
+
+    let response = post("too soon").await.unwrap();
+    assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+    let wait: u64 = response.headers()["retry-after"].to_str().unwrap().parse().unwrap();
+    assert!((1..=30).contains(&wait));
+    assert!(response.text().await.unwrap().contains("Slow mode"));
+    assert_eq!(common::count(&db_pool, "messages", room_id).await, 1);
+}
+
+#[tokio::test]
+async fn bot_names_are_plain_text() {
+    let db_pool = common::db().await;
+    let owner = resolve_user(&db_pool, "github", "4242", None).await.unwrap();
+    let room_id = common::room(&db_pool, false).await;
+    common::profile(&db_pool, &owner, room_id).await;
+    assert!(create_token(&db_pool, &owner, "<img src=x onerror=alert(1)>", &[room_id], true).await.is_err());
+    assert!(create_token(&db_pool, &owner, "{content}", &[room_id], true).await.is_err());
+
+    // as if named before names were checked
+    let (bot, secret) = create_token(&db_pool, &owner, "CI", &[room_id], true).await.unwrap();
+    sqlx::query("UPDATE profiles SET alias='<img src=x onerror=alert(1)> {content}' WHERE user_id=?")
+        .bind(&bot)
+        .execute(&db_pool)
+        .await
+        .unwrap();
+    let base = serve(db_pool).await;
+
+    let client = reqwest::Client::new();
+    let response = client.post(format!("{base}/{room_id}/messages"))
+        .bearer_auth(&secret)
+        .json(&json!({ "content": "beep" }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+
+    let page = client.get(format!("{base}/{room_id}"))
+        .bearer_auth(&secret)
+        .send()
+        .await
+        .unwrap()
+        .text()
+        .await
+        .unwrap();
+    assert!(page.contains("&lt;img src=x onerror=alert(1)&gt; &#123;content&#125;"));
+    assert!(!page.contains("<img src=x"));
+}
+
+#[tokio::test]
+async fn threads_follow_the_reply_chain_both_ways() {
+    let db_pool = common::db().await;
+    let owner = resolve_user(&db_pool, "github", "4242", None).await.unwrap();
+    let room_id = common::room(&db_pool, false).await;
+    let profile_id = common::profile(&db_pool, &owner, room_id).await;
+    let (_, secret) = create_token(&db_pool, &owner, "CI", &[room_id], false).await.unwrap();
+
+    let root = common::message(&db_pool, room_id, profile_id, None, "root-message").await;
+    let reply = common::message(&db_pool, room_id, profile_id, Some(root), "first-reply").await;
+    let nested = common::message(&db_pool, room_id, profile_id, Some(reply), "nested-reply").await;
+    common::message(&db_pool, room_id, profile_id, Some(root), "sibling-reply").await;
+    common::message(&db_pool, room_id, profile_id, None, "unrelated-message").await;
+
+    let base = serve(db_pool.clone()).await;
+    let thread = async |message_id| {
+        let response = reqwest::Client::new().get(format!("{base}/{room_id}/t/{message_id}"))
+            .bearer_auth(&secret)
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        response.text().await.unwrap()
+    };
+
+    // ancestors and replies, oldest first, but not the other branches
+    let page = thread(reply).await;
+    let at = |content| page.find(content).unwrap();
+    assert!(at("root-message") < at("first-reply") && at("first-reply") < at("nested-reply"));
+    assert!(!page.contains("sibling-reply") && !page.contains("unrelated-message"));
+
+    // a deleted parent still links the chain
+    sqlx::query("UPDATE messages SET content='',deleted_at=unixepoch() WHERE id=?")
+        .bind(reply.to_string())
+        .execute(&db_pool)
+        .await
+        .unwrap();
+    let page = thread(nested).await;
+    assert!(page.contains("root-message") && page.contains("nested-reply"));
+    assert!(!page.contains("first-reply"));
+}
@@ -0,0 +1,103 @@
+mod common;
+
+use silentkisses::{auth::{create_guest, is_guest, resolve_user, upgrade_guest}, rooms::{block_profile, has_blocked, report, room_access, room_reports, ReportReason, Reported, RoomAccess}};
+
+#[tokio::test]
+async fn guests_only_get_public_rooms() {
+    let db_pool = common::db().await;
+    let guest = create_guest(&db_pool).await.unwrap();
+    let public = common::room(&db_pool, true).await;
+    let private = common::room(&db_pool, false).await;
+    let profile_id = common::profile(&db_pool, &guest, public).await;
+    common::profile(&db_pool, &guest, private).await;
+
+    assert_eq!(room_access(&db_pool, Some(&guest), public).await.unwrap(), Some(RoomAccess::Member(profile_id)));
+    assert_eq!(room_access(&db_pool, Some(&guest), private).await.unwrap(), None);
+}
+
+#[tokio::test]
+async fn upgrading_keeps_the_guests_profiles() {
+    let db_pool = common::db().await;
+    let guest = create_guest(&db_pool).await.unwrap();
+    let room_id = common::room(&db_pool, true).await;
+    let profile_id = common::profile(&db_pool, &guest, room_id).await;
+
+    let user_id = upgrade_guest(&db_pool, &guest, "github", "4242").await.unwrap();
+
+    assert_eq!(user_id, guest);
+    assert!(!is_guest(&db_pool, &user_id).await.unwrap());
+    assert_eq!(resolve_user(&db_pool, "github", "4242", None).await.unwrap(), user_id);
+    assert_eq!(room_access(&db_pool, Some(&user_id), room_id).await.unwrap(), Some(RoomAccess::Member(profile_id)));
+}
+
+#[tokio::test]
+async fn upgrading_into_an_existing_account_moves_profiles_over() {
+    let db_pool = common::db().await;
+    let user_id = resolve_user(&db_pool, "github", "4242", None).await.unwrap();
+    let guest = create_guest(&db_pool).await.unwrap();
+    let shared = common::room(&db_pool, true).await;
+    let guest_only = common::room(&db_pool, true).await;
+    let kept = common::profile(&db_pool, &user_id, shared).await;
+    let left_behind = common::profile(&db_pool, &guest, shared).await;
+    let moved = common::profile(&db_pool, &guest, guest_only).await;
+    let message = common::message(&db_pool, shared, left_behind, None, "hi as a guest").await;
+
+    assert_eq!(upgrade_guest(&db_pool, &guest, "github", "4242").await.unwrap(), user_id);
+
+    assert_eq!(room_access(&db_pool, Some(&user_id), shared).await.unwrap(), Some(RoomAccess::Member(kept)));
+    assert_eq!(room_access(&db_pool, Some(&user_id), guest_only).await.unwrap(), Some(RoomAccess::Member(moved)));
+
+    // the guest's profile in the shared room folds into the account's
+    let (sender,): (String,) = sqlx::query_as("SELECT profile_id FROM messages WHERE id=?")
+        .bind(message.to_string())
+        .fetch_one(&db_pool)
+        .await
+        .unwrap();
+    assert_eq!(sender, kept.to_string());
+    let (left,): (i64,) = sqlx::query_as("SELECT count(*) FROM profiles WHERE user_id=?")
+        .bind(&guest)
+        .fetch_one(&db_pool)
+        .await
+        .unwrap();
+    assert_eq!(left, 0);
+}
+
+#[tokio::test]
+async fn upgrading_with_the_guests_own_identity_keeps_the_guest() {
+    let db_pool = common::db().await;
+    let guest = create_guest(&db_pool).await.unwrap();
+    let room_id = common::room(&db_pool, true).await;
+    let profile_id = common::profile(&db_pool, &guest, room_id).await;
+    sqlx::query("INSERT INTO identities (user_id,provider,subject) VALUES (?,'github','4242')")
+        .bind(&guest)
+        .execute(&db_pool)
+        .await
+        .unwrap();
+
+    assert_eq!(upgrade_guest(&db_pool, &guest, "github", "4242").await.unwrap(), guest);
+
+    assert!(!is_guest(&db_pool, &guest).await.unwrap());
+    assert_eq!(resolve_user(&db_pool, "github", "4242", None).await.unwrap(), guest);
+    assert_eq!(room_access(&db_pool, Some(&guest), room_id).await.unwrap(), Some(RoomAccess::Member(profile_id)));
+}
+
+#[tokio::test]
+async fn folding_a_guest_keeps_reports_and_blocks_against_it() {
+    let db_pool = common::db().await;
+    let user_id = resolve_user(&db_pool, "github", "4242", None).await.unwrap();
+    let reporter = resolve_user(&db_pool, "github", "2424", None).await.unwrap();
+    let guest = create_guest(&db_pool).await.unwrap();
+    let room_id = common::room(&db_pool, true).await;
+    let kept = common::profile(&db_pool, &user_id, room_id).await;
+    let reporting = common::profile(&db_pool, &reporter, room_id).await;
+    let folded = common::profile(&db_pool, &guest, room_id).await;
+
+    report(&db_pool, &reporter, room_id, Reported::Profile(folded), ReportReason::Harassment, "").await.unwrap();
+    block_profile(&db_pool, reporting, folded).await.unwrap();
+    assert_eq!(upgrade_guest(&db_pool, &guest, "github", "4242").await.unwrap(), user_id);
+
+    let reports = room_reports(&db_pool, room_id).await.unwrap();
+    assert_eq!(reports.len(), 1);
+    assert_eq!(reports[0].profile_id, kept);
+    assert!(has_blocked(&db_pool, reporting, kept).await.unwrap());
+}
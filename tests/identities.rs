@@ -0,0 +1,35 @@
+mod common;
+
+use silentkisses::auth::{identities, link_identity, resolve_user, unlink_identity};
+
+#[tokio::test]
+async fn linked_providers_sign_into_the_same_user() {
+    let db_pool = common::db().await;
+    let user_id = resolve_user(&db_pool, "github", "4242", None).await.unwrap();
+
+    link_identity(&db_pool, &user_id, "google", "g-1").await.unwrap();
+
+    assert_eq!(resolve_user(&db_pool, "google", "g-1", None).await.unwrap(), user_id);
+    assert_eq!(identities(&db_pool, &user_id).await.unwrap().len(), 2);
+}
+
+#[tokio::test]
+async fn cant_steal_someone_elses_identity() {
+    let db_pool = common::db().await;
+    let alice = resolve_user(&db_pool, "github", "alice", None).await.unwrap();
+    let bob = resolve_user(&db_pool, "google", "bob", None).await.unwrap();
+
+    assert!(link_identity(&db_pool, &bob, "github", "alice").await.is_err());
+    assert_eq!(resolve_user(&db_pool, "github", "alice", None).await.unwrap(), alice);
+}
+
+#[tokio::test]
+async fn last_identity_cant_be_unlinked() {
+    let db_pool = common::db().await;
+    let user_id = resolve_user(&db_pool, "github", "4242", None).await.unwrap();
+    link_identity(&db_pool, &user_id, "google", "g-1").await.unwrap();
+
+    unlink_identity(&db_pool, &user_id, "google", "g-1").await.unwrap();
+    assert!(unlink_identity(&db_pool, &user_id, "github", "4242").await.is_err());
+    assert_eq!(identities(&db_pool, &user_id).await.unwrap(), vec![("github".to_owned(), "4242".to_owned())]);
+}
@@ -0,0 +1,36 @@
+use std::net::{IpAddr, Ipv4Addr};
+
+use axum::http::{header, StatusCode};
+use silentkisses::rooms::Limits;
+use uuid::Uuid;
+
+#[test]
+fn profiles_get_a_burst_then_wait() {
+    let limits = Limits::default();
+    let (flooder, other) = (Uuid::now_v7(), Uuid::now_v7());
+
+    for _ in 0..5 {
+        limits.take(flooder).unwrap();
+    }
+    let err = limits.take(flooder).unwrap_err();
+    assert_eq!(err.1.status(), StatusCode::TOO_MANY_REQUESTS);
+    assert_eq!(err.1.headers()[header::RETRY_AFTER], "1");
+    assert!(err.0.to_string().contains("too fast"));
+
+    // buckets are per profile
+    limits.take(other).unwrap();
+}
+
+#[test]
+fn connections_count_until_dropped() {
+    let limits = Limits::new(2);
+    let (home, elsewhere) = (IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1)), IpAddr::V4(Ipv4Addr::new(10, 0, 0, 2)));
+
+    let first = limits.connect(home).unwrap();
+    let _second = limits.connect(home).unwrap();
+    assert!(limits.connect(home).is_none());
+    assert!(limits.connect(elsewhere).is_some());
+
+    drop(first);
+    assert!(limits.connect(home).is_some());
+}
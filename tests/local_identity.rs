@@ -0,0 +1,75 @@
+//! Signs in through a stub OAuth server with no Firebase configured.
+
+mod common;
+
+use axum::{http::HeaderMap, routing::{get, post}, Json, Router};
+use serde_json::{json, Value};
+use silentkisses::auth::{resolve_user, ClientProvider, Clients};
+
+async fn token() -> Json<Value> {
+    Json(json!({
+        "access_token": "stub-access-token",
+        "token_type": "bearer",
+    }))
+}
+
+async fn user(headers: HeaderMap) -> Json<Value> {
+    assert_eq!(headers["authorization"], "Bearer stub-access-token");
+    Json(json!({ "id": 4242, "login": "octocat" }))
+}
+
+async fn stub_clients() -> Clients {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let base = format!("http://{}", listener.local_addr().unwrap());
+    let app = Router::new()
+        .route("/token", post(token))
+        .route("/user", get(user));
+    tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+    Clients::from_json(json!({
+        "github": {
+            "client_id": "id",
+            "client_secret": "secret",
+            "auth_url": format!("{base}/authorize"),
+            "token_url": format!("{base}/token"),
+            "userinfo_url": format!("{base}/user"),
+        }
+    })).unwrap()
+}
+
+#[tokio::test]
+async fn userinfo_resolves_to_a_stable_local_user() {
+    let db_pool = common::db().await;
+    let clients = stub_clients().await;
+    let http_client = reqwest::Client::new();
+    assert!(clients.firebase_idpurl().is_none());
+
+    let access_token = clients.exchange_code(&ClientProvider::Github, &http_client, "code".to_owned(), "verifier".to_owned()).await.unwrap();
+    let (subject, handle) = clients.userinfo(&ClientProvider::Github, &http_client, &access_token).await.unwrap();
+    assert_eq!(subject, "4242");
+    assert_eq!(handle.as_deref(), Some("octocat"));
+
+    let user_id = resolve_user(&db_pool, "github", &subject, None).await.unwrap();
+    assert_eq!(resolve_user(&db_pool, "github", &subject, None).await.unwrap(), user_id);
+    assert_ne!(resolve_user(&db_pool, "google", &subject, None).await.unwrap(), user_id);
+}
+
+#[tokio::test]
+async fn firebase_users_keep_their_id() {
+    let db_pool = common::db().await;
+
+    assert_eq!(resolve_user(&db_pool, "google", "firebase-local-id", Some("firebase-local-id")).await.unwrap(), "firebase-local-id");
+    assert_eq!(resolve_user(&db_pool, "google", "firebase-local-id", None).await.unwrap(), "firebase-local-id");
+}
+
+#[test]
+fn firebase_is_optional() {
+    let with_firebase = json!({ "firebase": { "apikey": "key" } });
+    assert!(Clients::from_json(with_firebase.clone()).unwrap().firebase_idpurl().is_some());
+
+    let mut forced_local = with_firebase;
+    forced_local["identity"] = json!("local");
+    assert!(Clients::from_json(forced_local).unwrap().firebase_idpurl().is_none());
+
+    assert!(Clients::from_json(json!({})).unwrap().firebase_idpurl().is_none());
+}
@@ -0,0 +1,155 @@
+mod common;
+
+use silentkisses::{auth::{create_guest, create_token, resolve_user, upgrade_guest}, rooms::{ban_profile, is_banned, is_muted, kick_profile, mute_profile, room_access, unban_profile, unmute_profile, RoomAccess}};
+use sqlx::SqlitePool;
+use tokio::sync::broadcast;
+use uuid::Uuid;
+
+async fn owned_room(db_pool: &SqlitePool, owner: &str) -> Uuid {
+    let room_id = common::room(db_pool, true).await;
+    sqlx::query("UPDATE rooms SET owner_id=? WHERE uuid=?")
+        .bind(owner)
+        .bind(room_id.to_string())
+        .execute(db_pool)
+        .await
+        .unwrap();
+    common::profile(db_pool, owner, room_id).await;
+    room_id
+}
+
+#[tokio::test]
+async fn kicks_hang_up_on_the_profile_only() {
+    let db_pool = common::db().await;
+    let owner = resolve_user(&db_pool, "github", "4242", None).await.unwrap();
+    let troll = resolve_user(&db_pool, "github", "6666", None).await.unwrap();
+    let room_id = owned_room(&db_pool, &owner).await;
+    let trolling = common::profile(&db_pool, &troll, room_id).await;
+
+    let (tx, mut rx) = broadcast::channel(16);
+    kick_profile(&db_pool, &tx, room_id, trolling).await.unwrap();
+
+    let kick = rx.recv().await.unwrap();
+    assert!(kick.kicks(Some(trolling)));
+    assert!(!kick.kicks(Some(Uuid::now_v7())) && !kick.kicks(None));
+    let notice = rx.recv().await.unwrap();
+    assert!(notice.data.contains("Test Profile was kicked"));
+
+    // a kick isn't a ban
+    assert_eq!(room_access(&db_pool, Some(&troll), room_id).await.unwrap(), Some(RoomAccess::Member(trolling)));
+}
+
+#[tokio::test]
+async fn mutes_lapse_and_can_be_lifted() {
+    let db_pool = common::db().await;
+    let owner = resolve_user(&db_pool, "github", "4242", None).await.unwrap();
+    let troll = resolve_user(&db_pool, "github", "6666", None).await.unwrap();
+    let room_id = owned_room(&db_pool, &owner).await;
+    let trolling = common::profile(&db_pool, &troll, room_id).await;
+    let (tx, _rx) = broadcast::channel(16);
+
+    assert!(mute_profile(&db_pool, &tx, room_id, trolling, 42).await.is_err());
+    mute_profile(&db_pool, &tx, room_id, trolling, 600).await.unwrap();
+    assert!(is_muted(&db_pool, trolling).await.unwrap());
+    unmute_profile(&db_pool, &tx, room_id, trolling).await.unwrap();
+    assert!(!is_muted(&db_pool, trolling).await.unwrap());
+
+    mute_profile(&db_pool, &tx, room_id, trolling, 600).await.unwrap();
+    sqlx::query("UPDATE profiles SET muted_until=unixepoch()-1 WHERE uuid=?")
+        .bind(trolling.to_string())
+        .execute(&db_pool)
+        .await
+        .unwrap();
+    assert!(!is_muted(&db_pool, trolling).await.unwrap());
+}
+
+#[tokio::test]
+async fn bans_are_per_user_and_spare_the_owner() {
+    let db_pool = common::db().await;
+    let owner = resolve_user(&db_pool, "github", "4242", None).await.unwrap();
+    let troll = resolve_user(&db_pool, "github", "6666", None).await.unwrap();
+    let room_id = owned_room(&db_pool, &owner).await;
+    let trolling = common::profile(&db_pool, &troll, room_id).await;
+    let (tx, mut rx) = broadcast::channel(16);
+
+    let (owners,): (String,) = sqlx::query_as("SELECT uuid FROM profiles WHERE user_id=?")
+        .bind(&owner)
+        .fetch_one(&db_pool)
+        .await
+        .unwrap();
+    assert!(ban_profile(&db_pool, &tx, room_id, Uuid::parse_str(&owners).unwrap()).await.is_err());
+
+    ban_profile(&db_pool, &tx, room_id, trolling).await.unwrap();
+    assert!(rx.recv().await.unwrap().kicks(Some(trolling)));
+    assert!(rx.recv().await.unwrap().data.contains("was banned"));
+    assert!(is_banned(&db_pool, &troll, room_id).await.unwrap());
+    // the public room is shut to them, with or without their profile
+    assert_eq!(room_access(&db_pool, Some(&troll), room_id).await.unwrap(), None);
+    sqlx::query("DELETE FROM profiles WHERE uuid=?")
+        .bind(trolling.to_string())
+        .execute(&db_pool)
+        .await
+        .unwrap();
+    assert_eq!(room_access(&db_pool, Some(&troll), room_id).await.unwrap(), None);
+    let fresh = sqlx::query("INSERT INTO profiles (uuid,user_id,room_id,handle,alias) VALUES (?,?,?,'again','Again')")
+        .bind(Uuid::now_v7().to_string())
+        .bind(&troll)
+        .bind(room_id.to_string())
+        .execute(&db_pool)
+        .await;
+    assert!(fresh.is_err());
+}
+
+#[tokio::test]
+async fn unbanned_users_come_back_as_they_were() {
+    let db_pool = common::db().await;
+    let owner = resolve_user(&db_pool, "github", "4242", None).await.unwrap();
+    let troll = resolve_user(&db_pool, "github", "6666", None).await.unwrap();
+    let room_id = owned_room(&db_pool, &owner).await;
+    let trolling = common::profile(&db_pool, &troll, room_id).await;
+    let (tx, _rx) = broadcast::channel(16);
+
+    ban_profile(&db_pool, &tx, room_id, trolling).await.unwrap();
+    unban_profile(&db_pool, &tx, room_id, trolling).await.unwrap();
+    assert_eq!(room_access(&db_pool, Some(&troll), room_id).await.unwrap(), Some(RoomAccess::Member(trolling)));
+}
+
+#[tokio::test]
+async fn signing_in_doesnt_lift_a_guests_ban_or_mute() {
+    let db_pool = common::db().await;
+    let owner = resolve_user(&db_pool, "github", "4242", None).await.unwrap();
+    let account = resolve_user(&db_pool, "github", "6666", None).await.unwrap();
+    let guest = create_guest(&db_pool).await.unwrap();
+    let banned_from = owned_room(&db_pool, &owner).await;
+    let muted_in = owned_room(&db_pool, &owner).await;
+    let kept = common::profile(&db_pool, &account, muted_in).await;
+    let banned = common::profile(&db_pool, &guest, banned_from).await;
+    let muted = common::profile(&db_pool, &guest, muted_in).await;
+    let (tx, _rx) = broadcast::channel(16);
+
+    ban_profile(&db_pool, &tx, banned_from, banned).await.unwrap();
+    mute_profile(&db_pool, &tx, muted_in, muted, 600).await.unwrap();
+    assert_eq!(upgrade_guest(&db_pool, &guest, "github", "6666").await.unwrap(), account);
+
+    assert!(is_banned(&db_pool, &account, banned_from).await.unwrap());
+    assert_eq!(room_access(&db_pool, Some(&account), banned_from).await.unwrap(), None);
+    assert!(is_muted(&db_pool, kept).await.unwrap());
+}
+
+#[tokio::test]
+async fn bans_kick_the_users_bots_too() {
+    let db_pool = common::db().await;
+    let owner = resolve_user(&db_pool, "github", "4242", None).await.unwrap();
+    let troll = resolve_user(&db_pool, "github", "6666", None).await.unwrap();
+    let room_id = owned_room(&db_pool, &owner).await;
+    let trolling = common::profile(&db_pool, &troll, room_id).await;
+    let (bot, _) = create_token(&db_pool, &troll, "spam", &[room_id], true).await.unwrap();
+    let Some(RoomAccess::Member(botting)) = room_access(&db_pool, Some(&bot), room_id).await.unwrap() else {
+        panic!("bot should be in the room");
+    };
+    let (tx, mut rx) = broadcast::channel(16);
+
+    ban_profile(&db_pool, &tx, room_id, trolling).await.unwrap();
+    assert!(rx.recv().await.unwrap().kicks(Some(trolling)));
+    assert!(rx.recv().await.unwrap().kicks(Some(botting)));
+    assert_eq!(room_access(&db_pool, Some(&bot), room_id).await.unwrap(), None);
+}
@@ -0,0 +1,169 @@
+//! Runs the OIDC login against a mock issuer on localhost.
+
+use std::sync::{Arc, Mutex};
+
+use axum::{extract::State, routing::{get, post}, Json, Router};
+use openidconnect::{
+    core::{CoreIdToken, CoreIdTokenClaims, CoreJsonWebKeySet, CoreJwsSigningAlgorithm, CoreRsaPrivateSigningKey}, JsonWebKeyId, PrivateSigningKey
+};
+use serde_json::{json, Value};
+use silentkisses::auth::OidcConfig;
+
+const ISSUER_KEY: &str = include_str!("fixtures/oidc_issuer.pem");
+const IMPOSTOR_KEY: &str = include_str!("fixtures/oidc_impostor.pem");
+const CLIENT_ID: &str = "silentkisses";
+
+/// What the mock issuer puts in the next ID token.
+#[derive(Clone)]
+struct Mock {
+    issuer: String,
+    iss: String,
+    audience: String,
+    nonce: String,
+    expires_in: i64,
+    signing_key: &'static str,
+}
+
+type MockState = Arc<Mutex<Mock>>;
+
+fn key(pem: &str) -> CoreRsaPrivateSigningKey {
+    CoreRsaPrivateSigningKey::from_pem(pem, Some(JsonWebKeyId::new("test".to_owned()))).unwrap()
+}
+
+async fn discovery(State(mock): State<MockState>) -> Json<Value> {
+    let issuer = mock.lock().unwrap().issuer.clone();
+    Json(json!({
+        "issuer": issuer,
+        "authorization_endpoint": format!("{issuer}/authorize"),
+        "token_endpoint": format!("{issuer}/token"),
+        "jwks_uri": format!("{issuer}/jwks"),
+        "response_types_supported": ["code"],
+        "subject_types_supported": ["public"],
+        "id_token_signing_alg_values_supported": ["RS256"],
+    }))
+}
+
+async fn jwks() -> Json<CoreJsonWebKeySet> {
+    Json(CoreJsonWebKeySet::new(vec![key(ISSUER_KEY).as_verification_key()]))
+}
+
+async fn token(State(mock): State<MockState>) -> Json<Value> {
+    let mock = mock.lock().unwrap().clone();
+    let now = time::OffsetDateTime::now_utc().unix_timestamp();
+    let claims: CoreIdTokenClaims = serde_json::from_value(json!({
+        "iss": mock.iss,
+        "aud": [mock.audience],
+        "exp": now + mock.expires_in,
+        "iat": now,
+        "sub": "alice",
+        "nonce": mock.nonce,
+        "name": "Alice",
+        "email": "alice@example.com",
+    })).unwrap();
+    let id_token = CoreIdToken::new(
+        claims,
+        &key(mock.signing_key),
+        CoreJwsSigningAlgorithm::RsaSsaPkcs1V15Sha256,
+        None,
+        None,
+    ).unwrap();
+
+    Json(json!({
+        "access_token": "mock-access-token",
+        "token_type": "Bearer",
+        "expires_in": 3600,
+        "id_token": id_token,
+    }))
+}
+
+async fn mock_issuer() -> (OidcConfig, MockState) {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let issuer = format!("http://{}", listener.local_addr().unwrap());
+    let mock = Arc::new(Mutex::new(Mock {
+        issuer: issuer.clone(),
+        iss: issuer.clone(),
+        audience: CLIENT_ID.to_owned(),
+        nonce: String::new(),
+        expires_in: 300,
+        signing_key: ISSUER_KEY,
+    }));
+
+    let app = Router::new()
+        .route("/.well-known/openid-configuration", get(discovery))
+        .route("/jwks", get(jwks))
+        .route("/token", post(token))
+        .with_state(mock.clone());
+    tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+    let config = OidcConfig {
+        id: "mock".to_owned(),
+        name: "Mock".to_owned(),
+        issuer,
+        client_id: CLIENT_ID.to_owned(),
+        client_secret: Some("shh".to_owned()),
+        redirect_url: "http://localhost:8080/lockin/mock".to_owned(),
+        scopes: vec!["profile".to_owned(), "email".to_owned()],
+    };
+    (config, mock)
+}
+
+/// Goes through authorize + exchange, letting `tamper` change the ID token first.
+async fn login(tamper: impl FnOnce(&mut Mock)) -> Result<silentkisses::auth::OidcIdentity, silentkisses::AppError> {
+    let (config, mock) = mock_issuer().await;
+    let http_client = reqwest::Client::new();
+
+    let authorize = config.authorize(&http_client).await.unwrap();
+    assert!(authorize.url.starts_with(&format!("{}/authorize?", config.issuer)));
+    assert!(authorize.url.contains(&format!("nonce={}", authorize.nonce)));
+    assert!(authorize.url.contains("code_challenge="));
+
+    {
+        let mut mock = mock.lock().unwrap();
+        mock.nonce = authorize.nonce.clone();
+        tamper(&mut mock);
+    }
+
+    config.exchange(&http_client, "mock-code".to_owned(), authorize.pkce_verifier, authorize.nonce).await
+}
+
+#[tokio::test]
+async fn valid_id_token_signs_in() {
+    let identity = login(|_| ()).await.unwrap();
+
+    assert_eq!(identity.subject, "alice");
+    assert_eq!(identity.name.as_deref(), Some("Alice"));
+    assert_eq!(identity.email.as_deref(), Some("alice@example.com"));
+}
+
+#[tokio::test]
+async fn wrong_nonce_is_rejected() {
+    assert!(login(|mock| mock.nonce = "replayed".to_owned()).await.is_err());
+}
+
+#[tokio::test]
+async fn foreign_signature_is_rejected() {
+    assert!(login(|mock| mock.signing_key = IMPOSTOR_KEY).await.is_err());
+}
+
+#[tokio::test]
+async fn wrong_audience_is_rejected() {
+    assert!(login(|mock| mock.audience = "someone-else".to_owned()).await.is_err());
+}
+
+#[tokio::test]
+async fn wrong_issuer_is_rejected() {
+    assert!(login(|mock| mock.iss = "http://evil.example".to_owned()).await.is_err());
+}
+
+#[tokio::test]
+async fn expired_id_token_is_rejected() {
+    assert!(login(|mock| mock.expires_in = -60).await.is_err());
+}
+
+#[test]
+fn built_in_provider_ids_are_refused() {
+    for id in ["google", "github"] {
+        assert!(OidcConfig::from_json(&json!({ "id": id, "issuer": "http://localhost", "client_id": CLIENT_ID })).is_err());
+    }
+    assert!(OidcConfig::from_json(&json!({ "id": "keycloak", "issuer": "http://localhost", "client_id": CLIENT_ID })).is_ok());
+}
@@ -0,0 +1,32 @@
+use silentkisses::rooms::Presence;
+use uuid::Uuid;
+
+#[test]
+fn joins_and_leaves_are_per_profile_not_per_socket() {
+    let presence = Presence::default();
+    let (room_id, profile_id) = (Uuid::now_v7(), Uuid::now_v7());
+
+    assert!(presence.join(room_id, profile_id, "moth").is_some());
+    // a second tab
+    assert!(presence.join(room_id, profile_id, "moth").is_none());
+    assert_eq!(presence.snapshot(room_id), [(profile_id, "moth".to_owned())]);
+    assert!(presence.snapshot(Uuid::now_v7()).is_empty());
+
+    assert!(presence.leave(room_id, profile_id).is_none());
+    let left = presence.leave(room_id, profile_id).unwrap();
+    assert!(left.data.contains(r#""type":"leave""#));
+    assert!(presence.snapshot(room_id).is_empty());
+}
+
+#[test]
+fn typing_is_throttled_and_needs_a_socket() {
+    let presence = Presence::default();
+    let (room_id, profile_id) = (Uuid::now_v7(), Uuid::now_v7());
+
+    assert!(presence.typing(room_id, profile_id).is_none());
+
+    presence.join(room_id, profile_id, "moth");
+    let typing = presence.typing(room_id, profile_id).unwrap();
+    assert!(typing.data.contains(r#""alias":"moth""#));
+    assert!(presence.typing(room_id, profile_id).is_none());
+}
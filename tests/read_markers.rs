@@ -0,0 +1,48 @@
+mod common;
+
+use silentkisses::{auth::resolve_user, rooms::{mark_read, mark_room_read, unread_count}};
+
+#[tokio::test]
+async fn unread_counts_follow_the_marker() {
+    let db_pool = common::db().await;
+    let me = resolve_user(&db_pool, "github", "4242", None).await.unwrap();
+    let them = resolve_user(&db_pool, "github", "2424", None).await.unwrap();
+    let room_id = common::room(&db_pool, false).await;
+    let mine = common::profile(&db_pool, &me, room_id).await;
+    let theirs = common::profile(&db_pool, &them, room_id).await;
+
+    let first = common::message(&db_pool, room_id, theirs, None, "one").await;
+    let second = common::message(&db_pool, room_id, theirs, None, "two").await;
+    // your own messages are never unread
+    common::message(&db_pool, room_id, mine, None, "mine").await;
+    assert_eq!(unread_count(&db_pool, mine).await.unwrap(), 2);
+
+    mark_read(&db_pool, mine, first).await.unwrap();
+    assert_eq!(unread_count(&db_pool, mine).await.unwrap(), 1);
+
+    mark_read(&db_pool, mine, second).await.unwrap();
+    // never moves back
+    mark_read(&db_pool, mine, first).await.unwrap();
+    assert_eq!(unread_count(&db_pool, mine).await.unwrap(), 0);
+
+    common::message(&db_pool, room_id, theirs, None, "three").await;
+    assert_eq!(unread_count(&db_pool, mine).await.unwrap(), 1);
+    mark_room_read(&db_pool, mine).await.unwrap();
+    assert_eq!(unread_count(&db_pool, mine).await.unwrap(), 0);
+}
+
+#[tokio::test]
+async fn markers_stay_in_their_room() {
+    let db_pool = common::db().await;
+    let me = resolve_user(&db_pool, "github", "4242", None).await.unwrap();
+    let room_id = common::room(&db_pool, false).await;
+    let other_room = common::room(&db_pool, false).await;
+    let mine = common::profile(&db_pool, &me, room_id).await;
+    let elsewhere = common::profile(&db_pool, &me, other_room).await;
+
+    common::message(&db_pool, room_id, elsewhere, None, "one").await;
+    let foreign = common::message(&db_pool, other_room, elsewhere, None, "not here").await;
+
+    mark_read(&db_pool, mine, foreign).await.unwrap();
+    assert_eq!(unread_count(&db_pool, mine).await.unwrap(), 1);
+}
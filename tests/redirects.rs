@@ -0,0 +1,41 @@
+use silentkisses::auth::{is_safe_redirect, safe_redirect};
+
+#[test]
+fn plain_paths_are_fine() {
+    for url in ["/", "/r", "/r/67e55044-10b1-426f-9247-bb680e5fe0c8", "/r?q=cats&sort=name", "/account#top", "/r/%20x"] {
+        assert!(is_safe_redirect(url), "{url}");
+    }
+}
+
+#[test]
+fn other_origins_are_refused() {
+    for url in [
+        "",
+        "evil.com",
+        "https://evil.com",
+        "//evil.com",
+        "///evil.com",
+        "/\\evil.com",
+        "\\\\evil.com",
+        "/\t/evil.com",
+        "/\n/evil.com",
+        " /evil",
+        "javascript:alert(1)",
+        "%2F%2Fevil.com",
+        "/%2F/evil.com",
+        "/%5Cevil.com",
+        "/%252F/evil.com",
+        "/%09/evil.com",
+        "%6A%61vascript:alert(1)",
+        "/%zz",
+    ] {
+        assert!(!is_safe_redirect(url), "{url:?}");
+    }
+}
+
+#[test]
+fn unsafe_falls_back_home() {
+    assert_eq!(safe_redirect(Some("//evil.com".to_owned())), "/");
+    assert_eq!(safe_redirect(None), "/");
+    assert_eq!(safe_redirect(Some("/r/new".to_owned())), "/r/new");
+}
@@ -0,0 +1,88 @@
+mod common;
+
+use silentkisses::{auth::resolve_user, rooms::{all_reports, can_moderate, is_banned, report, resolve_report, room_reports, ReportAction, ReportReason, Reported}};
+use sqlx::SqlitePool;
+use tokio::sync::broadcast;
+use uuid::Uuid;
+
+async fn owned_room(db_pool: &SqlitePool, owner: &str) -> Uuid {
+    let room_id = common::room(db_pool, true).await;
+    sqlx::query("UPDATE rooms SET owner_id=? WHERE uuid=?")
+        .bind(owner)
+        .bind(room_id.to_string())
+        .execute(db_pool)
+        .await
+        .unwrap();
+    room_id
+}
+
+#[tokio::test]
+async fn only_admins_see_who_reported() {
+    let db_pool = common::db().await;
+    let owner = resolve_user(&db_pool, "github", "4242", None).await.unwrap();
+    let reporter = resolve_user(&db_pool, "github", "1111", None).await.unwrap();
+    let troll = resolve_user(&db_pool, "github", "6666", None).await.unwrap();
+    let room_id = owned_room(&db_pool, &owner).await;
+    common::profile(&db_pool, &reporter, room_id).await;
+    let trolling = common::profile(&db_pool, &troll, room_id).await;
+    let message_id = common::message(&db_pool, room_id, trolling, None, "nasty").await;
+
+    report(&db_pool, &reporter, room_id, Reported::Message(message_id), ReportReason::Harassment, "again").await.unwrap();
+    // once is enough
+    assert!(report(&db_pool, &reporter, room_id, Reported::Message(message_id), ReportReason::Spam, "").await.is_err());
+    // not yourself, and not system messages
+    assert!(report(&db_pool, &troll, room_id, Reported::Profile(trolling), ReportReason::Other, "").await.is_err());
+    assert!(report(&db_pool, &reporter, room_id, Reported::Message(Uuid::now_v7()), ReportReason::Other, "").await.is_err());
+
+    let queue = room_reports(&db_pool, room_id).await.unwrap();
+    assert_eq!(queue.len(), 1);
+    assert_eq!(queue[0].profile_id, trolling);
+    assert_eq!(queue[0].content.as_deref(), Some("nasty"));
+    assert_eq!(queue[0].reason, ReportReason::Harassment);
+    assert_eq!(queue[0].reporter_id, None);
+
+    let all = all_reports(&db_pool).await.unwrap();
+    assert_eq!(all[0].reporter_id.as_deref(), Some(reporter.as_str()));
+
+    assert!(can_moderate(&db_pool, &owner, room_id).await.unwrap());
+    assert!(!can_moderate(&db_pool, &reporter, room_id).await.unwrap());
+    sqlx::query("UPDATE users SET is_admin=1 WHERE id=?")
+        .bind(&reporter)
+        .execute(&db_pool)
+        .await
+        .unwrap();
+    assert!(can_moderate(&db_pool, &reporter, room_id).await.unwrap());
+}
+
+#[tokio::test]
+async fn acting_on_a_report_closes_it() {
+    let db_pool = common::db().await;
+    let owner = resolve_user(&db_pool, "github", "4242", None).await.unwrap();
+    let reporter = resolve_user(&db_pool, "github", "1111", None).await.unwrap();
+    let troll = resolve_user(&db_pool, "github", "6666", None).await.unwrap();
+    let room_id = owned_room(&db_pool, &owner).await;
+    common::profile(&db_pool, &reporter, room_id).await;
+    let trolling = common::profile(&db_pool, &troll, room_id).await;
+    let message_id = common::message(&db_pool, room_id, trolling, None, "nasty").await;
+    let (tx, _rx) = broadcast::channel(16);
+
+    let on_profile = report(&db_pool, &reporter, room_id, Reported::Profile(trolling), ReportReason::Spam, "").await.unwrap();
+    let on_message = report(&db_pool, &reporter, room_id, Reported::Message(message_id), ReportReason::Hate, "").await.unwrap();
+
+    // profiles have no message to delete
+    assert!(resolve_report(&db_pool, &tx, room_id, on_profile, ReportAction::Delete).await.is_err());
+    resolve_report(&db_pool, &tx, room_id, on_message, ReportAction::Delete).await.unwrap();
+    let (deleted,): (bool,) = sqlx::query_as("SELECT deleted_at IS NOT NULL FROM messages WHERE id=?")
+        .bind(message_id.to_string())
+        .fetch_one(&db_pool)
+        .await
+        .unwrap();
+    assert!(deleted);
+    assert!(resolve_report(&db_pool, &tx, room_id, on_message, ReportAction::Dismiss).await.is_err());
+
+    // not from another room
+    assert!(resolve_report(&db_pool, &tx, common::room(&db_pool, true).await, on_profile, ReportAction::Ban).await.is_err());
+    resolve_report(&db_pool, &tx, room_id, on_profile, ReportAction::Ban).await.unwrap();
+    assert!(is_banned(&db_pool, &troll, room_id).await.unwrap());
+    assert!(room_reports(&db_pool, room_id).await.unwrap().is_empty());
+}
@@ -0,0 +1,80 @@
+mod common;
+
+use silentkisses::{auth::resolve_user, rooms::{mark_read, sweep_expired, unread_count}};
+use tokio::sync::broadcast;
+use uuid::Uuid;
+
+#[tokio::test]
+async fn sweeps_ttls_and_room_retention() {
+    let db_pool = common::db().await;
+    let user = resolve_user(&db_pool, "github", "4242", None).await.unwrap();
+    let room_id = common::room(&db_pool, false).await;
+    let profile_id = common::profile(&db_pool, &user, room_id).await;
+
+    let old = common::message(&db_pool, room_id, profile_id, None, "old").await;
+    let fleeting = common::message(&db_pool, room_id, profile_id, None, "fleeting").await;
+    let kept = common::message(&db_pool, room_id, profile_id, None, "kept").await;
+    sqlx::query("UPDATE messages SET created_at=unixepoch()-7200 WHERE id=?")
+        .bind(old.to_string())
+        .execute(&db_pool)
+        .await
+        .unwrap();
+    sqlx::query("UPDATE messages SET expires_at=unixepoch()-1 WHERE id=?")
+        .bind(fleeting.to_string())
+        .execute(&db_pool)
+        .await
+        .unwrap();
+
+    let (tx, mut rx) = broadcast::channel(16);
+    // no retention yet, only the TTL goes
+    assert_eq!(sweep_expired(&db_pool, &tx).await.unwrap(), 1);
+    assert!(rx.recv().await.unwrap().data.contains(&fleeting.to_string()));
+
+    sqlx::query("UPDATE rooms SET retention_secs=3600 WHERE uuid=?")
+        .bind(room_id.to_string())
+        .execute(&db_pool)
+        .await
+        .unwrap();
+    assert_eq!(sweep_expired(&db_pool, &tx).await.unwrap(), 1);
+    let event = rx.recv().await.unwrap();
+    assert_eq!(event.room_id, room_id);
+    assert!(event.data.contains(r#""type":"expired""#) && event.data.contains(&old.to_string()));
+
+    let left: Vec<String> = sqlx::query_scalar("SELECT id FROM messages WHERE room_id=?")
+        .bind(room_id.to_string())
+        .fetch_all(&db_pool)
+        .await
+        .unwrap();
+    assert_eq!(left, [kept.to_string()]);
+}
+
+#[tokio::test]
+async fn read_markers_on_expired_messages_move_back() {
+    let db_pool = common::db().await;
+    let me = resolve_user(&db_pool, "github", "4242", None).await.unwrap();
+    let them = resolve_user(&db_pool, "github", "2424", None).await.unwrap();
+    let room_id = common::room(&db_pool, false).await;
+    let mine = common::profile(&db_pool, &me, room_id).await;
+    let theirs = common::profile(&db_pool, &them, room_id).await;
+
+    let seen = common::message(&db_pool, room_id, theirs, None, "seen").await;
+    let fleeting = common::message(&db_pool, room_id, theirs, None, "fleeting").await;
+    common::message(&db_pool, room_id, theirs, None, "new").await;
+    mark_read(&db_pool, mine, fleeting).await.unwrap();
+    assert_eq!(unread_count(&db_pool, mine).await.unwrap(), 1);
+
+    sqlx::query("UPDATE messages SET expires_at=unixepoch()-1 WHERE id=?")
+        .bind(fleeting.to_string())
+        .execute(&db_pool)
+        .await
+        .unwrap();
+    sweep_expired(&db_pool, &broadcast::channel(16).0).await.unwrap();
+
+    let marker: Option<String> = sqlx::query_scalar("SELECT last_read_id FROM profiles WHERE uuid=?")
+        .bind(mine.to_string())
+        .fetch_one(&db_pool)
+        .await
+        .unwrap();
+    assert_eq!(marker.map(|id| Uuid::parse_str(&id).unwrap()), Some(seen));
+    assert_eq!(unread_count(&db_pool, mine).await.unwrap(), 1);
+}
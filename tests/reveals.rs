@@ -0,0 +1,124 @@
+mod common;
+
+use reqwest::StatusCode;
+use silentkisses::{auth::{create_token, resolve_user, set_identity_handle}, rooms::{accept_reveal, block_profile, request_reveal, reveal_between, reveal_choices, withdraw_reveal, Reveal}};
+use sqlx::SqlitePool;
+use uuid::Uuid;
+
+/// Two profiles in one room, the first user with their Github login known,
+/// the second with a display name.
+async fn pair(db_pool: &SqlitePool) -> (Uuid, Uuid, String, String) {
+    let asker = resolve_user(db_pool, "github", "4242", None).await.unwrap();
+    set_identity_handle(db_pool, "github", "4242", Some("octo")).await.unwrap();
+    let asked = resolve_user(db_pool, "github", "2424", None).await.unwrap();
+    sqlx::query("UPDATE users SET display_name='Bea' WHERE id=?")
+        .bind(&asked)
+        .execute(db_pool)
+        .await
+        .unwrap();
+    let room_id = common::room(db_pool, false).await;
+    let a = common::profile(db_pool, &asker, room_id).await;
+    let b = common::profile(db_pool, &asked, room_id).await;
+    (a, b, asker, asked)
+}
+
+#[tokio::test]
+async fn both_sides_see_each_other_once_accepted() {
+    let db_pool = common::db().await;
+    let (a, b, asker, asked) = pair(&db_pool).await;
+
+    let id = request_reveal(&db_pool, a, b, "github").await.unwrap();
+    accept_reveal(&db_pool, b, id, "name").await.unwrap();
+
+    let Some(Reveal::Revealed(_, b_is)) = reveal_between(&db_pool, a, b).await.unwrap() else {
+        panic!("not revealed to the asker");
+    };
+    let Some(Reveal::Revealed(_, a_is)) = reveal_between(&db_pool, b, a).await.unwrap() else {
+        panic!("not revealed to the asked");
+    };
+    assert_eq!(b_is, "Bea");
+    assert_eq!(a_is, "octo on github");
+    assert!(!a_is.contains(&asker) && !b_is.contains(&asked));
+    assert!(!a_is.contains("4242"));
+
+    // taking it back hides it again
+    assert!(withdraw_reveal(&db_pool, b, id).await.unwrap());
+    assert_eq!(reveal_between(&db_pool, a, b).await.unwrap(), None);
+}
+
+#[tokio::test]
+async fn one_sided_requests_reveal_nothing() {
+    let db_pool = common::db().await;
+    let (a, b, _, _) = pair(&db_pool).await;
+
+    let id = request_reveal(&db_pool, a, b, "github").await.unwrap();
+    assert_eq!(reveal_between(&db_pool, a, b).await.unwrap(), Some(Reveal::Asked(id)));
+    assert_eq!(reveal_between(&db_pool, b, a).await.unwrap(), Some(Reveal::Asking(id)));
+
+    // only the asked profile can accept, showing something it actually has
+    assert!(accept_reveal(&db_pool, a, id, "github").await.is_err());
+    assert!(accept_reveal(&db_pool, b, id, "google").await.is_err());
+    // nor an account we don't know the handle of, only its subject
+    assert_eq!(reveal_choices(&db_pool, b).await.unwrap(), ["name"]);
+    assert!(accept_reveal(&db_pool, b, id, "github").await.is_err());
+    assert!(request_reveal(&db_pool, b, a, "name").await.is_err());
+    assert!(request_reveal(&db_pool, a, a, "github").await.is_err());
+
+    assert!(withdraw_reveal(&db_pool, a, id).await.unwrap());
+    assert!(accept_reveal(&db_pool, b, id, "name").await.is_err());
+    assert_eq!(reveal_between(&db_pool, b, a).await.unwrap(), None);
+}
+
+#[tokio::test]
+async fn expired_requests_cannot_be_accepted() {
+    let db_pool = common::db().await;
+    let (a, b, _, _) = pair(&db_pool).await;
+
+    let id = request_reveal(&db_pool, a, b, "github").await.unwrap();
+    sqlx::query("UPDATE reveals SET expires_at=unixepoch()-1 WHERE id=?")
+        .bind(id.to_string())
+        .execute(&db_pool)
+        .await
+        .unwrap();
+
+    assert_eq!(reveal_between(&db_pool, b, a).await.unwrap(), None);
+    assert!(accept_reveal(&db_pool, b, id, "name").await.is_err());
+
+    // asking again starts over
+    let renewed = request_reveal(&db_pool, a, b, "github").await.unwrap();
+    assert_eq!(renewed, id);
+    accept_reveal(&db_pool, b, renewed, "name").await.unwrap();
+}
+
+#[tokio::test]
+async fn blocks_since_the_request_stop_the_reveal() {
+    let db_pool = common::db().await;
+    let (a, b, _, _) = pair(&db_pool).await;
+
+    let id = request_reveal(&db_pool, a, b, "github").await.unwrap();
+    block_profile(&db_pool, a, b).await.unwrap();
+    assert!(accept_reveal(&db_pool, b, id, "name").await.is_err());
+    assert_eq!(reveal_between(&db_pool, b, a).await.unwrap(), Some(Reveal::Asking(id)));
+}
+
+#[tokio::test]
+async fn read_only_tokens_cannot_ask() {
+    let db_pool = common::db().await;
+    let (a, b, asker, _) = pair(&db_pool).await;
+    let room_id: String = sqlx::query_scalar("SELECT room_id FROM profiles WHERE uuid=?")
+        .bind(a.to_string())
+        .fetch_one(&db_pool)
+        .await
+        .unwrap();
+    let (_, secret) = create_token(&db_pool, &asker, "CI", &[Uuid::parse_str(&room_id).unwrap()], false).await.unwrap();
+    let (host, ..) = common::serve(db_pool.clone(), 16).await;
+
+    let response = reqwest::Client::new().post(format!("http://{host}/r/{room_id}/dm/{b}/reveal"))
+        .bearer_auth(&secret)
+        .form(&[("shows", "github")])
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    assert!(response.text().await.unwrap().contains("read only"));
+}
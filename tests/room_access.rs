@@ -0,0 +1,50 @@
+mod common;
+
+use silentkisses::rooms::{room_access, RoomAccess};
+use uuid::Uuid;
+
+#[tokio::test]
+async fn public_room_lets_anyone_look() {
+    let db_pool = common::db().await;
+    let room_id = common::room(&db_pool, true).await;
+
+    assert_eq!(room_access(&db_pool, None, room_id).await.unwrap(), Some(RoomAccess::Visitor));
+    assert_eq!(room_access(&db_pool, Some("stranger"), room_id).await.unwrap(), Some(RoomAccess::Visitor));
+}
+
+#[tokio::test]
+async fn public_room_member_gets_their_profile() {
+    let db_pool = common::db().await;
+    let room_id = common::room(&db_pool, true).await;
+    let profile_id = common::profile(&db_pool, "member", room_id).await;
+
+    assert_eq!(room_access(&db_pool, Some("member"), room_id).await.unwrap(), Some(RoomAccess::Member(profile_id)));
+}
+
+#[tokio::test]
+async fn private_room_lets_members_in() {
+    let db_pool = common::db().await;
+    let room_id = common::room(&db_pool, false).await;
+    let profile_id = common::profile(&db_pool, "member", room_id).await;
+
+    assert_eq!(room_access(&db_pool, Some("member"), room_id).await.unwrap(), Some(RoomAccess::Member(profile_id)));
+}
+
+#[tokio::test]
+async fn private_room_keeps_non_members_out() {
+    let db_pool = common::db().await;
+    let room_id = common::room(&db_pool, false).await;
+    let other_room_id = common::room(&db_pool, false).await;
+    common::profile(&db_pool, "member", room_id).await;
+    common::profile(&db_pool, "outsider", other_room_id).await;
+
+    assert_eq!(room_access(&db_pool, None, room_id).await.unwrap(), None);
+    assert_eq!(room_access(&db_pool, Some("outsider"), room_id).await.unwrap(), None);
+}
+
+#[tokio::test]
+async fn missing_room_is_denied() {
+    let db_pool = common::db().await;
+
+    assert_eq!(room_access(&db_pool, Some("anyone"), Uuid::now_v7()).await.unwrap(), None);
+}
@@ -0,0 +1,91 @@
+mod common;
+
+use reqwest::StatusCode;
+use silentkisses::{auth::resolve_user, rooms::{delete_room, purge_due_rooms}};
+
+#[tokio::test]
+async fn deleting_a_room_leaves_nothing_behind() {
+    let db_pool = common::db().await;
+    let room_id = common::room(&db_pool, true).await;
+    let other_room_id = common::room(&db_pool, true).await;
+    let profile_id = common::profile(&db_pool, "member", room_id).await;
+    let other_profile_id = common::profile(&db_pool, "member", other_room_id).await;
+    let first = common::message(&db_pool, room_id, profile_id, None, "hi").await;
+    common::message(&db_pool, room_id, profile_id, Some(first), "hi again").await;
+    common::message(&db_pool, other_room_id, other_profile_id, None, "elsewhere").await;
+
+    delete_room(&db_pool, room_id).await.unwrap();
+
+    assert_eq!(common::count(&db_pool, "profiles", room_id).await, 0);
+    assert_eq!(common::count(&db_pool, "messages", room_id).await, 0);
+    let (rooms,): (i64,) = sqlx::query_as("SELECT count(*) FROM rooms WHERE uuid=?")
+        .bind(room_id.to_string())
+        .fetch_one(&db_pool)
+        .await
+        .unwrap();
+    assert_eq!(rooms, 0);
+
+    assert_eq!(common::count(&db_pool, "profiles", other_room_id).await, 1);
+    assert_eq!(common::count(&db_pool, "messages", other_room_id).await, 1);
+}
+
+#[tokio::test]
+async fn purge_only_takes_rooms_past_their_grace_period() {
+    let db_pool = common::db().await;
+    let due = common::room(&db_pool, true).await;
+    let pending = common::room(&db_pool, true).await;
+    let kept = common::room(&db_pool, true).await;
+    let profile_id = common::profile(&db_pool, "member", due).await;
+    common::message(&db_pool, due, profile_id, None, "bye").await;
+
+    for (room_id, delete_at) in [(due, "unixepoch()-1"), (pending, "unixepoch()+3600")] {
+        sqlx::query(&format!("UPDATE rooms SET delete_at={delete_at} WHERE uuid=?"))
+            .bind(room_id.to_string())
+            .execute(&db_pool)
+            .await
+            .unwrap();
+    }
+
+    assert_eq!(purge_due_rooms(&db_pool).await.unwrap(), 1);
+    assert_eq!(common::count(&db_pool, "profiles", due).await, 0);
+    assert_eq!(common::count(&db_pool, "messages", due).await, 0);
+
+    let (rooms,): (i64,) = sqlx::query_as("SELECT count(*) FROM rooms WHERE uuid IN (?,?)")
+        .bind(pending.to_string())
+        .bind(kept.to_string())
+        .fetch_one(&db_pool)
+        .await
+        .unwrap();
+    assert_eq!(rooms, 2);
+}
+
+#[tokio::test]
+async fn grace_periods_are_bounded() {
+    let db_pool = common::db().await;
+    let owner = resolve_user(&db_pool, "github", "4242", None).await.unwrap();
+    let room_id = common::room(&db_pool, true).await;
+    let (name,): (String,) = sqlx::query_as("UPDATE rooms SET owner_id=? WHERE uuid=? RETURNING name")
+        .bind(&owner)
+        .bind(room_id.to_string())
+        .fetch_one(&db_pool)
+        .await
+        .unwrap();
+    let (host, _, store) = common::serve(db_pool.clone(), 16).await;
+    let cookie = common::session(&store, &owner).await;
+
+    for grace_hours in ["-1", "721", "9223372036854775807"] {
+        let response = reqwest::Client::new().post(format!("http://{host}/r/{room_id}/delete"))
+            .header("cookie", &cookie)
+            .form(&[("confirm_name", name.as_str()), ("grace_hours", grace_hours)])
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+    let (delete_at,): (Option<i64>,) = sqlx::query_as("SELECT delete_at FROM rooms WHERE uuid=?")
+        .bind(room_id.to_string())
+        .fetch_one(&db_pool)
+        .await
+        .unwrap();
+    assert_eq!(delete_at, None);
+}
@@ -0,0 +1,108 @@
+mod common;
+
+use std::collections::HashMap;
+
+use serde_json::json;
+use silentkisses::auth::{coarse_user_agent, SqliteStore};
+use time::{Duration, OffsetDateTime};
+use tower_sessions::{session::{Id, Record}, SessionStore};
+
+fn record(user_id: &str, expires_in: Duration) -> Record {
+    Record {
+        id: Id::default(),
+        data: HashMap::from([
+            ("user_id".to_owned(), json!(user_id)),
+            ("provider".to_owned(), json!("github")),
+            ("user_agent".to_owned(), json!("Firefox on Linux")),
+        ]),
+        expiry_date: (OffsetDateTime::now_utc() + expires_in).replace_nanosecond(0).unwrap(),
+    }
+}
+
+#[tokio::test]
+async fn sessions_are_listed_per_user() {
+    let store = SqliteStore::new(common::db().await);
+    let mut alice = record("alice", Duration::minutes(5));
+    let mut bob = record("bob", Duration::minutes(5));
+    let mut expired = record("alice", Duration::minutes(-5));
+    store.create(&mut alice).await.unwrap();
+    store.create(&mut bob).await.unwrap();
+    store.create(&mut expired).await.unwrap();
+
+    assert_eq!(store.load(&alice.id).await.unwrap(), Some(alice.clone()));
+    assert_eq!(store.load(&expired.id).await.unwrap(), None);
+
+    let listed = store.list("alice").await.unwrap();
+    assert_eq!(listed.len(), 1);
+    assert_eq!(listed[0].id, Some(alice.id));
+    assert_eq!(listed[0].provider.as_deref(), Some("github"));
+    assert_eq!(listed[0].user_agent.as_deref(), Some("Firefox on Linux"));
+}
+
+#[tokio::test]
+async fn revoking_ends_only_that_session_and_tells_sockets() {
+    let store = SqliteStore::new(common::db().await);
+    let mut phone = record("alice", Duration::minutes(5));
+    let mut laptop = record("alice", Duration::minutes(5));
+    store.create(&mut phone).await.unwrap();
+    store.create(&mut laptop).await.unwrap();
+    let mut revoked = store.subscribe();
+
+    let key = store.list("alice").await.unwrap().into_iter()
+        .find(|active| active.id == Some(phone.id))
+        .unwrap()
+        .key;
+    assert!(!store.revoke("bob", key).await.unwrap(), "can't revoke someone else's session");
+    assert!(store.revoke("alice", key).await.unwrap());
+
+    assert_eq!(revoked.try_recv().unwrap(), phone.id);
+    assert_eq!(store.load(&phone.id).await.unwrap(), None);
+    assert!(store.load(&laptop.id).await.unwrap().is_some());
+
+    // a request that loaded the session before it was revoked can't bring it back
+    store.save(&phone).await.unwrap();
+    assert_eq!(store.load(&phone.id).await.unwrap(), None);
+}
+
+#[tokio::test]
+async fn logging_out_everywhere_leaves_other_users_alone() {
+    let store = SqliteStore::new(common::db().await);
+    for user_id in ["alice", "alice", "bob"] {
+        store.create(&mut record(user_id, Duration::minutes(5))).await.unwrap();
+    }
+
+    assert_eq!(store.revoke_all("alice").await.unwrap(), 2);
+    assert!(store.list("alice").await.unwrap().is_empty());
+    assert_eq!(store.list("bob").await.unwrap().len(), 1);
+}
+
+#[tokio::test]
+async fn reaping_expired_sessions_isnt_a_revoke() {
+    let store = SqliteStore::new(common::db().await);
+    let mut live = record("alice", Duration::minutes(5));
+    let mut expired = record("alice", Duration::minutes(-5));
+    store.create(&mut live).await.unwrap();
+    store.create(&mut expired).await.unwrap();
+    let mut revoked = store.subscribe();
+
+    assert_eq!(store.delete_expired().await.unwrap(), 1);
+    assert!(revoked.try_recv().is_err());
+    assert!(store.load(&live.id).await.unwrap().is_some());
+}
+
+#[test]
+fn user_agents_are_coarse() {
+    assert_eq!(
+        coarse_user_agent("Mozilla/5.0 (X11; Linux x86_64; rv:131.0) Gecko/20100101 Firefox/131.0"),
+        "Firefox on Linux"
+    );
+    assert_eq!(
+        coarse_user_agent("Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/130.0.0.0 Safari/537.36 Edg/130.0.0.0"),
+        "Edge on Windows"
+    );
+    assert_eq!(
+        coarse_user_agent("Mozilla/5.0 (iPhone; CPU iPhone OS 17_0 like Mac OS X) AppleWebKit/605.1.15 (KHTML, like Gecko) Version/17.0 Mobile/15E148 Safari/604.1"),
+        "Safari on iOS"
+    );
+    assert_eq!(coarse_user_agent("curl/8.5.0"), "Unknown browser");
+}
@@ -0,0 +1,73 @@
+mod common;
+
+use silentkisses::{auth::{create_guest, create_token, delete_token, find_token, resolve_user}, rooms::{room_access, RoomAccess}};
+
+#[tokio::test]
+async fn tokens_are_stored_hashed_and_shown_once() {
+    let db_pool = common::db().await;
+    let owner = resolve_user(&db_pool, "github", "4242", None).await.unwrap();
+    let room_id = common::room(&db_pool, false).await;
+    common::profile(&db_pool, &owner, room_id).await;
+
+    let (id, secret) = create_token(&db_pool, &owner, "CI", &[room_id], true).await.unwrap();
+
+    let (token_hash,): (String,) = sqlx::query_as("SELECT token_hash FROM api_tokens WHERE id=?")
+        .bind(&id)
+        .fetch_one(&db_pool)
+        .await
+        .unwrap();
+    assert!(!token_hash.contains(&secret));
+
+    let token = find_token(&db_pool, &secret).await.unwrap().unwrap();
+    assert_eq!(token.id, id);
+    assert_eq!(token.owner_id, owner);
+    assert!(token.can_write);
+    assert!(find_token(&db_pool, "sk_guess").await.unwrap().is_none());
+
+    assert!(delete_token(&db_pool, &owner, &id).await.unwrap());
+    assert!(find_token(&db_pool, &secret).await.unwrap().is_none());
+}
+
+#[tokio::test]
+async fn tokens_act_as_a_bot_in_their_rooms_only() {
+    let db_pool = common::db().await;
+    let owner = resolve_user(&db_pool, "github", "4242", None).await.unwrap();
+    let scoped = common::room(&db_pool, false).await;
+    let other_public = common::room(&db_pool, true).await;
+    let owner_profile = common::profile(&db_pool, &owner, scoped).await;
+    common::profile(&db_pool, &owner, other_public).await;
+
+    let (bot, _) = create_token(&db_pool, &owner, "CI", &[scoped], false).await.unwrap();
+
+    let Some(RoomAccess::Member(bot_profile)) = room_access(&db_pool, Some(&bot), scoped).await.unwrap() else {
+        panic!("bot should be in its room");
+    };
+    let (alias,): (String,) = sqlx::query_as("SELECT alias FROM profiles WHERE uuid=?")
+        .bind(bot_profile.to_string())
+        .fetch_one(&db_pool)
+        .await
+        .unwrap();
+    assert_eq!(alias, "CI [bot]");
+    assert_eq!(room_access(&db_pool, Some(&bot), other_public).await.unwrap(), None);
+
+    // owner leaves, the bot loses the room too
+    sqlx::query("DELETE FROM profiles WHERE uuid=?")
+        .bind(owner_profile.to_string())
+        .execute(&db_pool)
+        .await
+        .unwrap();
+    assert_eq!(room_access(&db_pool, Some(&bot), scoped).await.unwrap(), None);
+}
+
+#[tokio::test]
+async fn tokens_only_get_rooms_the_owner_is_in() {
+    let db_pool = common::db().await;
+    let owner = resolve_user(&db_pool, "github", "4242", None).await.unwrap();
+    let guest = create_guest(&db_pool).await.unwrap();
+    let public = common::room(&db_pool, true).await;
+    common::profile(&db_pool, &guest, public).await;
+
+    assert!(create_token(&db_pool, &owner, "CI", &[public], true).await.is_err());
+    assert!(create_token(&db_pool, &guest, "CI", &[public], true).await.is_err());
+    assert!(create_token(&db_pool, &owner, "CI", &[], true).await.is_err());
+}
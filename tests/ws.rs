@@ -0,0 +1,143 @@
+//! Runs the room socket on localhost, signed in with an API token or a session cookie.
+
+mod common;
+
+use std::time::Duration;
+
+use futures_util::{SinkExt, StreamExt};
+use serde_json::json;
+use silentkisses::{auth::{create_token, resolve_user}, rooms::RoomEvent};
+use tokio::{net::TcpStream, sync::broadcast};
+use tokio_tungstenite::{tungstenite::{client::IntoClientRequest, Message}, MaybeTlsStream, WebSocketStream};
+use uuid::Uuid;
+
+type Socket = WebSocketStream<MaybeTlsStream<TcpStream>>;
+
+/// A room with its owner and a token in it, two messages sent and the socket
+/// opened after the first one.
+async fn setup(capacity: usize) -> (Socket, broadcast::Sender<RoomEvent>, Uuid, Vec<String>) {
+    let db_pool = common::db().await;
+    let owner = resolve_user(&db_pool, "github", "4242", None).await.unwrap();
+    let room_id = common::room(&db_pool, false).await;
+    common::profile(&db_pool, &owner, room_id).await;
+    let (_, secret) = create_token(&db_pool, &owner, "CI", &[room_id], true).await.unwrap();
+    let (host, tx, _) = common::serve(db_pool.clone(), capacity).await;
+    let base = format!("{host}/r");
+
+    for content in ["seen", "missed"] {
+        reqwest::Client::new().post(format!("http://{base}/{room_id}/messages"))
+            .bearer_auth(&secret)
+            .json(&json!({ "content": content }))
+            .send()
+            .await
+            .unwrap();
+    }
+    let ids: Vec<String> = sqlx::query_scalar("SELECT id FROM messages WHERE room_id=? ORDER BY created_at,id")
+        .bind(room_id.to_string())
+        .fetch_all(&db_pool)
+        .await
+        .unwrap();
+
+    let mut request = format!("ws://{base}/{room_id}/ws?since={}", ids[0]).into_client_request().unwrap();
+    request.headers_mut().insert("authorization", format!("Bearer {secret}").parse().unwrap());
+    let (socket, _) = tokio_tungstenite::connect_async(request).await.unwrap();
+    (socket, tx, room_id, ids)
+}
+
+async fn next_text(socket: &mut Socket) -> String {
+    match socket.next().await.unwrap().unwrap() {
+        Message::Text(text) => text.to_string(),
+        other => panic!("expected text, got {other:?}"),
+    }
+}
+
+#[tokio::test]
+async fn reconnects_replay_what_was_missed() {
+    let (mut socket, tx, room_id, ids) = setup(16).await;
+
+    let replayed = next_text(&mut socket).await;
+    assert!(replayed.contains(&ids[1]));
+    assert!(replayed.contains("missed"));
+
+    let joined: serde_json::Value = serde_json::from_str(&next_text(&mut socket).await).unwrap();
+    assert_eq!(joined["type"], "join");
+    assert_eq!(joined["alias"], "CI [bot]");
+
+    tx.send(RoomEvent { room_id, message_id: None, data: "<div>live</div>".to_owned(), only_to: None }).unwrap();
+    assert_eq!(next_text(&mut socket).await, "<div>live</div>");
+}
+
+#[tokio::test]
+async fn lagging_sockets_are_told_to_resync() {
+    let (mut socket, tx, room_id, _) = setup(1).await;
+    // the replay arriving means the socket is subscribed
+    next_text(&mut socket).await;
+
+    for _ in 0..8 {
+        tx.send(RoomEvent { room_id, message_id: None, data: "<div>flood</div>".to_owned(), only_to: None }).unwrap();
+    }
+    // the socket's own join may have gone out before the flood
+    let mut frame = next_text(&mut socket).await;
+    if frame.contains(r#""type":"join""#) {
+        frame = next_text(&mut socket).await;
+    }
+    assert_eq!(frame, r#"{"type":"resync"}"#);
+}
+
+#[tokio::test]
+async fn floods_get_a_typed_error_with_the_wait() {
+    let (mut socket, _, _, _) = setup(64).await;
+
+    for _ in 0..8 {
+        socket.send(Message::text(r#"{"content":"flood"}"#)).await.unwrap();
+    }
+    let error = loop {
+        let frame = next_text(&mut socket).await;
+        if frame.contains(r#""type":"error""#) {
+            break serde_json::from_str::<serde_json::Value>(&frame).unwrap();
+        }
+    };
+    assert_eq!(error["code"], "rate_limited");
+    assert!(error["retry_after_secs"].as_u64().unwrap() >= 1);
+    assert!(error["message"].as_str().unwrap().contains("too fast"));
+}
+
+#[tokio::test]
+async fn sockets_only_hear_their_own_room() {
+    let (mut socket, tx, room_id, _) = setup(16).await;
+    // replay, then the socket's own join
+    next_text(&mut socket).await;
+    next_text(&mut socket).await;
+
+    let other_room = Uuid::now_v7();
+    tx.send(RoomEvent { room_id: other_room, message_id: None, data: "<div>private</div>".to_owned(), only_to: None }).unwrap();
+    tx.send(RoomEvent { room_id, message_id: None, data: "<div>ours</div>".to_owned(), only_to: None }).unwrap();
+    assert_eq!(next_text(&mut socket).await, "<div>ours</div>");
+}
+
+#[tokio::test]
+async fn sessions_running_out_dont_hang_up_busy_sockets() {
+    let db_pool = common::db().await;
+    let owner = resolve_user(&db_pool, "github", "4242", None).await.unwrap();
+    let room_id = common::room(&db_pool, false).await;
+    common::profile(&db_pool, &owner, room_id).await;
+    let (host, tx, store) = common::serve(db_pool.clone(), 16).await;
+
+    let cookie = common::session(&store, &owner).await;
+    let mut request = format!("ws://{host}/r/{room_id}/ws").into_client_request().unwrap();
+    request.headers_mut().insert("cookie", cookie.parse().unwrap());
+    let (mut socket, _) = tokio_tungstenite::connect_async(request).await.unwrap();
+    // the socket's own join
+    next_text(&mut socket).await;
+
+    // frames don't touch the session, so it runs out while the socket is busy
+    sqlx::query("UPDATE sessions SET expiry_date=unixepoch()-1")
+        .execute(&db_pool)
+        .await
+        .unwrap();
+    assert_eq!(store.delete_expired().await.unwrap(), 1);
+    tokio::time::sleep(Duration::from_millis(50)).await;
+
+    tx.send(RoomEvent { room_id, message_id: None, data: "<div>still here</div>".to_owned(), only_to: None }).unwrap();
+    assert_eq!(next_text(&mut socket).await, "<div>still here</div>");
+}